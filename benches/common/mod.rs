@@ -0,0 +1,27 @@
+//! The synthetic star field shared by the benchmarks and the labelling benchmark example
+
+use star_counter::Bitmap;
+
+/// Scatters square stars of up to 7x7 pixels until about `fill` of the field is covered,
+/// the same way on every run
+pub fn synthetic_field(size: usize, fill: f64) -> Bitmap {
+    // A tiny LCG keeps the field the same on every run without pulling in a dependency
+    let mut state = 0x2545_f491_4f6c_dd1d_u64;
+    let mut next = |bound: usize| {
+        state = state
+            .wrapping_mul(6_364_136_223_846_793_005)
+            .wrapping_add(1_442_695_040_888_963_407);
+        (state >> 33) as usize % bound
+    };
+    let mut field = Bitmap::new(size, size);
+    let stars = (size * size) as f64 * fill / 16.0;
+    for _ in 0..stars as usize {
+        let (x, y, side) = (next(size), next(size), next(7) + 1);
+        for y in y..(y + side).min(size) {
+            for x in x..(x + side).min(size) {
+                field.set(x, y, true);
+            }
+        }
+    }
+    field
+}
@@ -0,0 +1,146 @@
+//! Benchmarks counting and rendering synthetic star fields of several sizes and densities
+//!
+//! Run with `cargo bench`, or `cargo bench -- count_groups/2000` for a single one.
+
+mod common;
+
+use common::synthetic_field;
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use image::{GrayImage, Luma};
+use star_counter::{
+    convert_to_image, count_components, count_groups, label_groups, label_groups_parallel,
+    threshold_image, Bitmap, Connectivity, DetectOptions, Threshold,
+};
+
+/// Square sizes of the fields, in pixels
+const SIZES: [usize; 3] = [500, 2000, 4000];
+
+/// Rough fractions of the pixels that are stars, from a sparse sky to a dense cluster
+const FILLS: [f64; 3] = [0.01, 0.1, 0.3];
+
+fn bench_count_groups(c: &mut Criterion) {
+    let mut group = c.benchmark_group("count_groups");
+    group.sample_size(10);
+    let options = DetectOptions::default();
+    for size in SIZES {
+        group.throughput(Throughput::Elements((size * size) as u64));
+        for fill in FILLS {
+            let field = synthetic_field(size, fill);
+            // Counting erases discarded groups, so every run gets a fresh copy
+            group.bench_with_input(
+                BenchmarkId::new(size.to_string(), fill),
+                &field,
+                |b, field| {
+                    b.iter_batched_ref(
+                        || field.clone(),
+                        |stars| count_groups(stars, None, &options),
+                        BatchSize::LargeInput,
+                    )
+                },
+            );
+        }
+    }
+    group.finish();
+}
+
+/// The same fields as `count_groups` without measuring the stars, to compare against it
+fn bench_count_components(c: &mut Criterion) {
+    let mut group = c.benchmark_group("count_components");
+    group.sample_size(10);
+    for size in SIZES {
+        group.throughput(Throughput::Elements((size * size) as u64));
+        for fill in FILLS {
+            let field = synthetic_field(size, fill);
+            group.bench_with_input(
+                BenchmarkId::new(size.to_string(), fill),
+                &field,
+                |b, field| b.iter(|| count_components(field, Connectivity::Eight)),
+            );
+        }
+    }
+    group.finish();
+}
+
+/// Labelling the whole field on one thread against a strip per thread, for the speedup
+fn bench_label_groups(c: &mut Criterion) {
+    let mut group = c.benchmark_group("label_groups");
+    group.sample_size(10);
+    for size in SIZES {
+        let field = synthetic_field(size, 0.1);
+        group.throughput(Throughput::Elements((size * size) as u64));
+        group.bench_with_input(BenchmarkId::new("serial", size), &field, |b, field| {
+            b.iter(|| label_groups(field, Connectivity::Eight))
+        });
+        group.bench_with_input(BenchmarkId::new("parallel", size), &field, |b, field| {
+            b.iter(|| label_groups_parallel(field, Connectivity::Eight))
+        });
+    }
+    group.finish();
+}
+
+/// Reading every pixel of the field and writing it to another bitmap, the access pattern of
+/// the scans
+fn bench_bitmap(c: &mut Criterion) {
+    let mut group = c.benchmark_group("bitmap");
+    for size in SIZES {
+        let field = synthetic_field(size, 0.1);
+        group.throughput(Throughput::Elements((size * size) as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &field, |b, field| {
+            b.iter_batched_ref(
+                || Bitmap::new(size, size),
+                |copy| {
+                    for y in 0..size {
+                        for x in 0..size {
+                            copy.set(x, y, field.get(x, y));
+                        }
+                    }
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+/// Thresholding the synthetic fields drawn as images, which runs a row per task
+fn bench_threshold_image(c: &mut Criterion) {
+    let mut group = c.benchmark_group("threshold_image");
+    for size in SIZES {
+        let field = synthetic_field(size, 0.1);
+        let img = GrayImage::from_fn(size as u32, size as u32, |x, y| {
+            Luma([if field.get(x as usize, y as usize) {
+                200
+            } else {
+                10
+            }])
+        });
+        group.throughput(Throughput::Elements((size * size) as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &img, |b, img| {
+            b.iter(|| threshold_image(img, Threshold::new(100)))
+        });
+    }
+    group.finish();
+}
+
+fn bench_convert_to_image(c: &mut Criterion) {
+    let mut group = c.benchmark_group("convert_to_image");
+    for size in SIZES {
+        let field = synthetic_field(size, 0.1);
+        group.throughput(Throughput::Elements((size * size) as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &field, |b, field| {
+            b.iter(|| convert_to_image(field))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_count_groups,
+    bench_count_components,
+    bench_label_groups,
+    bench_bitmap,
+    bench_threshold_image,
+    bench_convert_to_image
+);
+criterion_main!(benches);
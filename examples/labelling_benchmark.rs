@@ -0,0 +1,70 @@
+//! Compares the two-pass union-find labelling used by `count_groups` with the scanline flood
+//! fill of `mark_group` on a dense synthetic star field
+//!
+//! Run with `cargo run --release --example labelling_benchmark [SIZE] [FILL]`, where `FILL` is
+//! roughly the fraction of the pixels that are stars.
+
+#[path = "../benches/common/mod.rs"]
+mod common;
+
+use std::{
+    env,
+    time::{Duration, Instant},
+};
+
+use common::synthetic_field;
+use star_counter::{label_groups, mark_group, Bitmap, Connectivity};
+
+const RUNS: usize = 5;
+
+fn flood_fill_count(stars: &Bitmap, connectivity: Connectivity) -> usize {
+    let mut visited = Bitmap::new(stars.width(), stars.height());
+    let mut count = 0;
+    for y in 0..stars.height() {
+        for x in 0..stars.width() {
+            if stars.get(x, y) && !visited.get(x, y) {
+                mark_group((x, y), stars, &mut visited, connectivity);
+                count += 1;
+            }
+        }
+    }
+    count
+}
+
+/// Fastest of several runs, along with the count of the last one
+fn best_of(mut count: impl FnMut() -> usize) -> (Duration, usize) {
+    let mut best = Duration::MAX;
+    let mut result = 0;
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        result = count();
+        best = best.min(start.elapsed());
+    }
+    (best, result)
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let size = args
+        .next()
+        .map_or(4000, |size| size.parse().expect("SIZE is a number"));
+    let fill = args
+        .next()
+        .map_or(0.3, |fill| fill.parse().expect("FILL is a number"));
+    let field = synthetic_field(size, fill);
+
+    println!("{size}x{size} field, best of {RUNS} runs");
+    for connectivity in [
+        Connectivity::Four,
+        Connectivity::Eight,
+        Connectivity::Radius(3),
+    ] {
+        let (flood_fill, expected) = best_of(|| flood_fill_count(&field, connectivity));
+        let (union_find, count) = best_of(|| label_groups(&field, connectivity).count());
+        assert_eq!(count, expected, "Both algorithms find the same groups");
+        println!(
+            "Connectivity {connectivity}, {count} groups: flood fill {flood_fill:.2?}, \
+             union-find {union_find:.2?}"
+        );
+    }
+}
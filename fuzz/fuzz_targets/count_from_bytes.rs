@@ -0,0 +1,17 @@
+//! Feeds arbitrary bytes through decoding and counting, which has to return an error for
+//! anything that isn't an image instead of panicking. Run it with
+//! `cargo +nightly fuzz run count_from_bytes`, seeding the corpus with small images of every
+//! supported format to get past the format detection quickly
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // The first byte picks the sensitivity, so thresholds other than the default are covered
+    let Some((&sensitivity, image)) = data.split_first() else {
+        return;
+    };
+    if let Ok(stats) = star_counter::count_from_bytes(image, sensitivity) {
+        assert_eq!(stats.count as usize, stats.stars.len());
+    }
+});
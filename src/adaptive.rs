@@ -0,0 +1,112 @@
+use image::{ImageBuffer, Luma, Primitive};
+use rayon::prelude::*;
+
+use crate::{Bitmap, Threshold};
+
+/// Sums of every rectangle of the image from the top-left corner, with an extra row and column
+/// of zeros so that the sum of any window takes four lookups
+struct IntegralImage {
+    width: usize,
+    sums: Vec<u64>,
+}
+
+impl IntegralImage {
+    fn new<T: Primitive>(img: &ImageBuffer<Luma<T>, Vec<T>>) -> Self {
+        let (width, height) = (img.width() as usize, img.height() as usize);
+        let stride = width + 1;
+        let mut sums = vec![0u64; stride * (height + 1)];
+        for (y, row) in img.chunks_exact(width.max(1)).take(height).enumerate() {
+            let mut row_sum = 0;
+            for (x, value) in row.iter().enumerate() {
+                row_sum += value.to_u64().unwrap_or(0);
+                sums[(y + 1) * stride + x + 1] = sums[y * stride + x + 1] + row_sum;
+            }
+        }
+        IntegralImage { width, sums }
+    }
+
+    /// Sum of the pixels in `x_min..x_max` and `y_min..y_max`
+    fn sum(&self, x_min: usize, y_min: usize, x_max: usize, y_max: usize) -> u64 {
+        let stride = self.width + 1;
+        self.sums[y_max * stride + x_max] + self.sums[y_min * stride + x_min]
+            - self.sums[y_min * stride + x_max]
+            - self.sums[y_max * stride + x_min]
+    }
+}
+
+/// Subtracts the mean of the `window` by `window` pixels around every pixel from it, which
+/// flattens a slowly varying sky to black while stars keep their height above it
+///
+/// Pixels darker than the sky around them become black. The window should be much larger than
+/// the stars, or they raise their own background and are dimmed.
+pub fn subtract_background<T: Primitive + Send + Sync>(
+    img: &ImageBuffer<Luma<T>, Vec<T>>,
+    window: usize,
+) -> ImageBuffer<Luma<T>, Vec<T>> {
+    let (width, height) = (img.width() as usize, img.height() as usize);
+    let mut data = vec![T::zero(); width * height];
+    if data.is_empty() {
+        return ImageBuffer::new(img.width(), img.height());
+    }
+    let integral = IntegralImage::new(img);
+    let radius = window / 2;
+
+    data.par_chunks_mut(width)
+        .zip(img.par_chunks(width))
+        .enumerate()
+        .for_each(|(y, (row, pixels))| {
+            let (y_min, y_max) = (y.saturating_sub(radius), (y + radius + 1).min(height));
+            for (x, (value, &pixel)) in row.iter_mut().zip(pixels).enumerate() {
+                let (x_min, x_max) = (x.saturating_sub(radius), (x + radius + 1).min(width));
+                let count = ((x_max - x_min) * (y_max - y_min)) as u64;
+                let mean = integral.sum(x_min, y_min, x_max, y_max) / count;
+                let above = pixel.to_u64().unwrap_or(0).saturating_sub(mean);
+                // Never above the pixel itself, so it fits its type
+                *value = T::from(above).unwrap_or_else(T::zero);
+            }
+        });
+    ImageBuffer::from_raw(img.width(), img.height(), data)
+        .expect("Buffer has a value for every pixel")
+}
+
+/// Classifies every pixel against the mean of the `window` by `window` pixels around it, so a
+/// star only needs to be brighter than the sky near it
+///
+/// The sensitivity of the threshold is how much brighter than the local mean a pixel needs to
+/// be, or darker when inverted. The window is cut off at the edges of the image, and its upper
+/// bound stays an absolute pixel value.
+pub fn adaptive_threshold<T: Primitive + Send + Sync>(
+    img: &ImageBuffer<Luma<T>, Vec<T>>,
+    window: usize,
+    threshold: Threshold<T>,
+) -> Bitmap {
+    let (width, height) = (img.width() as usize, img.height() as usize);
+    let mut data = vec![false; width * height];
+    if data.is_empty() {
+        return Bitmap::new(width, height);
+    }
+    let integral = IntegralImage::new(img);
+    let offset = threshold.sensitivity.to_u64().unwrap_or(0);
+    let radius = window / 2;
+
+    data.par_chunks_mut(width)
+        .zip(img.par_chunks(width))
+        .enumerate()
+        .for_each(|(y, (row, pixels))| {
+            let (y_min, y_max) = (y.saturating_sub(radius), (y + radius + 1).min(height));
+            for (x, (star, &pixel)) in row.iter_mut().zip(pixels).enumerate() {
+                let (x_min, x_max) = (x.saturating_sub(radius), (x + radius + 1).min(width));
+                let count = ((x_max - x_min) * (y_max - y_min)) as u64;
+                let sum = integral.sum(x_min, y_min, x_max, y_max);
+                // Compares against the mean without dividing, so nothing is rounded away
+                let value = pixel.to_u64().unwrap_or(0) * count;
+                *star = if threshold.invert {
+                    value + offset * count < sum
+                } else {
+                    value > sum + offset * count
+                        && threshold.max_sensitivity.is_none_or(|max| pixel <= max)
+                };
+            }
+        });
+    Bitmap::from_vec(width, height, data)
+}
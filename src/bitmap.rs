@@ -0,0 +1,141 @@
+use crate::StarCounterError;
+
+/// A grid of booleans addressed by `(x, y)` like an image, stored row by row in a single
+/// allocation
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Bitmap {
+    width: usize,
+    height: usize,
+    data: Vec<bool>,
+}
+
+impl Bitmap {
+    /// Creates a bitmap with every pixel set to `false`
+    pub fn new(width: usize, height: usize) -> Self {
+        Bitmap {
+            width,
+            height,
+            data: vec![false; width * height],
+        }
+    }
+
+    /// Creates a bitmap from row-major data, `data[y * width + x]`
+    pub fn from_vec(width: usize, height: usize, data: Vec<bool>) -> Self {
+        assert_eq!(data.len(), width * height, "Bitmap data has wrong length");
+        Bitmap {
+            width,
+            height,
+            data,
+        }
+    }
+
+    /// Creates a bitmap with the pixels set whose centres are between `inner_radius` and
+    /// `outer_radius` pixels from `center`, which is a disc when `inner_radius` is 0
+    pub fn annulus(
+        width: usize,
+        height: usize,
+        (center_x, center_y): (f64, f64),
+        inner_radius: f64,
+        outer_radius: f64,
+    ) -> Self {
+        let mut data = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let distance = (x as f64 - center_x).hypot(y as f64 - center_y);
+                data.push(distance >= inner_radius && distance <= outer_radius);
+            }
+        }
+        Bitmap::from_vec(width, height, data)
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> bool {
+        self.data[self.index(x, y)]
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, value: bool) {
+        let index = self.index(x, y);
+        self.data[index] = value;
+    }
+
+    /// Whether the coordinate lies inside the bitmap
+    pub fn contains(&self, x: usize, y: usize) -> bool {
+        x < self.width && y < self.height
+    }
+
+    /// Clears every pixel that isn't set in `mask`, which must have the same size
+    pub fn intersect(&mut self, mask: &Bitmap) {
+        self.try_intersect(mask)
+            .expect("Mask has a different size than the bitmap");
+    }
+
+    /// Same as [`intersect`](Bitmap::intersect), failing when the mask has a different size
+    pub fn try_intersect(&mut self, mask: &Bitmap) -> Result<(), StarCounterError> {
+        if self.width != mask.width || self.height != mask.height {
+            return Err(StarCounterError::MaskDimensionMismatch {
+                mask: (mask.width, mask.height),
+                image: (self.width, self.height),
+            });
+        }
+        for (pixel, &keep) in self.data.iter_mut().zip(&mask.data) {
+            *pixel &= keep;
+        }
+        Ok(())
+    }
+
+    /// Every pixel of row `y`, from left to right
+    pub fn row(&self, y: usize) -> &[bool] {
+        &self.data[y * self.width..(y + 1) * self.width]
+    }
+
+    /// The raw row-major data
+    pub fn as_slice(&self) -> &[bool] {
+        &self.data
+    }
+
+    /// Lengths of the alternating runs of unset and set pixels in row-major order. The first
+    /// run is unset, so it is empty when the first pixel is set
+    pub fn runs(&self) -> Vec<usize> {
+        let mut runs = Vec::new();
+        let mut value = false;
+        let mut length = 0;
+        for &pixel in &self.data {
+            if pixel != value {
+                runs.push(length);
+                value = pixel;
+                length = 0;
+            }
+            length += 1;
+        }
+        runs.push(length);
+        runs
+    }
+
+    /// Creates a bitmap from its [`runs`](Bitmap::runs), or `None` if they don't add up to
+    /// `width * height` pixels
+    pub fn from_runs(width: usize, height: usize, runs: &[usize]) -> Option<Self> {
+        let mut data = Vec::with_capacity(width * height);
+        let mut value = false;
+        for &length in runs {
+            if length > width * height - data.len() {
+                return None;
+            }
+            data.resize(data.len() + length, value);
+            value = !value;
+        }
+        (data.len() == width * height).then(|| Bitmap::from_vec(width, height, data))
+    }
+
+    /// Panics outside of the bitmap, also where `y * width + x` would land on another row
+    fn index(&self, x: usize, y: usize) -> usize {
+        assert!(self.contains(x, y), "({x}, {y}) is outside the bitmap");
+        y * self.width + x
+    }
+}
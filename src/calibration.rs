@@ -0,0 +1,29 @@
+use image::{ImageBuffer, Luma, Primitive};
+use rayon::prelude::*;
+
+/// Subtracts a dark frame taken with the same exposure and the lens covered from the image,
+/// which removes the fixed pattern noise, hot pixels and amp glow of the sensor
+///
+/// Pixels darker than the dark frame become black. Returns `None` when the sizes differ, since
+/// a dark frame only matches the sensor it was taken with.
+pub fn subtract_dark<T: Primitive + Send + Sync>(
+    img: &ImageBuffer<Luma<T>, Vec<T>>,
+    dark: &ImageBuffer<Luma<T>, Vec<T>>,
+) -> Option<ImageBuffer<Luma<T>, Vec<T>>> {
+    if img.dimensions() != dark.dimensions() {
+        return None;
+    }
+    let data: Vec<T> = img
+        .as_raw()
+        .par_iter()
+        .zip(dark.as_raw().par_iter())
+        .map(|(&pixel, &dark)| {
+            if pixel > dark {
+                pixel - dark
+            } else {
+                T::zero()
+            }
+        })
+        .collect();
+    ImageBuffer::from_raw(img.width(), img.height(), data)
+}
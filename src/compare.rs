@@ -0,0 +1,68 @@
+use std::collections::HashMap;
+
+use crate::Star;
+
+/// How the stars of two frames correspond, as indices into the slices that were matched
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StarMatches {
+    /// Pairs of a star in the first frame and the same star in the second
+    pub matched: Vec<(usize, usize)>,
+    /// Stars of the first frame without a star near them in the second
+    pub unmatched_first: Vec<usize>,
+    /// Stars of the second frame without a star near them in the first
+    pub unmatched_second: Vec<usize>,
+}
+
+/// Pairs up stars whose weighted centroids are at most `radius` pixels apart
+///
+/// The closest pairs are matched first and every star is matched at most once, so of two stars
+/// near the same star in the other frame only the closer one is matched.
+pub fn match_stars(first: &[Star], second: &[Star], radius: f64) -> StarMatches {
+    // Stars of the second frame by the cell of a radius sized grid they are in, so only the
+    // neighbouring cells need to be searched
+    let cell_size = radius.max(f64::MIN_POSITIVE);
+    let cell = |star: &Star| {
+        let (x, y) = star.weighted_centroid;
+        (
+            (x / cell_size).floor() as i64,
+            (y / cell_size).floor() as i64,
+        )
+    };
+    let mut grid: HashMap<(i64, i64), Vec<usize>> = HashMap::new();
+    for (index, star) in second.iter().enumerate() {
+        grid.entry(cell(star)).or_default().push(index);
+    }
+
+    let mut candidates = Vec::new();
+    for (index, star) in first.iter().enumerate() {
+        let (x, y) = star.weighted_centroid;
+        let (cell_x, cell_y) = cell(star);
+        for neighbour_y in cell_y - 1..=cell_y + 1 {
+            for neighbour_x in cell_x - 1..=cell_x + 1 {
+                for &other in grid.get(&(neighbour_x, neighbour_y)).into_iter().flatten() {
+                    let (other_x, other_y) = second[other].weighted_centroid;
+                    let distance = (x - other_x).hypot(y - other_y);
+                    if distance <= radius {
+                        candidates.push((distance, index, other));
+                    }
+                }
+            }
+        }
+    }
+    candidates.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let mut first_matched = vec![false; first.len()];
+    let mut second_matched = vec![false; second.len()];
+    let mut matches = StarMatches::default();
+    for (_, index, other) in candidates {
+        if !first_matched[index] && !second_matched[other] {
+            first_matched[index] = true;
+            second_matched[other] = true;
+            matches.matched.push((index, other));
+        }
+    }
+    matches.matched.sort_unstable();
+    matches.unmatched_first = (0..first.len()).filter(|&i| !first_matched[i]).collect();
+    matches.unmatched_second = (0..second.len()).filter(|&i| !second_matched[i]).collect();
+    matches
+}
@@ -0,0 +1,88 @@
+use std::{error::Error, fmt, io};
+
+use image::{error::UnsupportedError, ImageError};
+
+use crate::{CountError, PastDeadline, Roi, TooManyGroups};
+
+/// Everything that can go wrong in the library, so callers can tell the causes apart without
+/// looking at the messages
+#[derive(Debug)]
+pub enum StarCounterError {
+    /// The image couldn't be read
+    Io(io::Error),
+    /// The image data is broken or truncated
+    Decode(ImageError),
+    /// The image is in a format or colour type that can't be decoded
+    UnsupportedFormat(UnsupportedError),
+    /// The region of interest reaches past the edge of the image
+    InvalidRoi { roi: Roi, width: u32, height: u32 },
+    /// The mask is of a different size than what it masks, as `(width, height)`
+    MaskDimensionMismatch {
+        mask: (usize, usize),
+        image: (usize, usize),
+    },
+    /// Counting stopped at the maximum number of groups
+    TooManyGroups(TooManyGroups),
+    /// Counting stopped at the deadline
+    PastDeadline(PastDeadline),
+}
+
+impl fmt::Display for StarCounterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StarCounterError::Io(_) => write!(f, "failed to read the image"),
+            StarCounterError::Decode(_) => write!(f, "failed to decode the image"),
+            StarCounterError::UnsupportedFormat(_) => write!(f, "the image format isn't supported"),
+            StarCounterError::InvalidRoi { roi, width, height } => {
+                write!(f, "ROI {roi} does not fit in the {width}x{height} image")
+            }
+            StarCounterError::MaskDimensionMismatch { mask, image } => write!(
+                f,
+                "mask is {}x{} but the image is {}x{}",
+                mask.0, mask.1, image.0, image.1
+            ),
+            StarCounterError::TooManyGroups(err) => err.fmt(f),
+            StarCounterError::PastDeadline(err) => err.fmt(f),
+        }
+    }
+}
+
+impl Error for StarCounterError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            StarCounterError::Io(err) => Some(err),
+            StarCounterError::Decode(err) => Some(err),
+            StarCounterError::UnsupportedFormat(err) => Some(err),
+            StarCounterError::InvalidRoi { .. }
+            | StarCounterError::MaskDimensionMismatch { .. }
+            | StarCounterError::TooManyGroups(_)
+            | StarCounterError::PastDeadline(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for StarCounterError {
+    fn from(err: io::Error) -> Self {
+        StarCounterError::Io(err)
+    }
+}
+
+/// Sorts the errors of the `image` crate into reading, decoding and unsupported formats
+impl From<ImageError> for StarCounterError {
+    fn from(err: ImageError) -> Self {
+        match err {
+            ImageError::IoError(err) => StarCounterError::Io(err),
+            ImageError::Unsupported(err) => StarCounterError::UnsupportedFormat(err),
+            err => StarCounterError::Decode(err),
+        }
+    }
+}
+
+impl From<CountError> for StarCounterError {
+    fn from(err: CountError) -> Self {
+        match err {
+            CountError::TooManyGroups(err) => StarCounterError::TooManyGroups(err),
+            CountError::PastDeadline(err) => StarCounterError::PastDeadline(err),
+        }
+    }
+}
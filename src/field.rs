@@ -0,0 +1,276 @@
+use std::slice;
+
+use image::GrayImage;
+
+use crate::{
+    convert_to_image, count_groups, mark_group, threshold_image, Bitmap, Connectivity,
+    DetectOptions, Moments, Star, StarStats, Threshold,
+};
+
+/// An image together with the parameters used to find stars in it
+///
+/// ```no_run
+/// # use star_counter::{Connectivity, StarField};
+/// # let img = image::GrayImage::new(0, 0);
+/// let detections = StarField::from_image(&img)
+///     .sensitivity(20)
+///     .connectivity(Connectivity::Eight)
+///     .detect();
+/// println!("Found {} stars", detections.count());
+/// ```
+#[derive(Clone, Debug)]
+pub struct StarField<'a> {
+    image: &'a GrayImage,
+    threshold: Threshold,
+    options: DetectOptions,
+}
+
+impl<'a> StarField<'a> {
+    /// Uses the default sensitivity of 20 and the default [`DetectOptions`]
+    pub fn from_image(image: &'a GrayImage) -> Self {
+        StarField {
+            image,
+            threshold: Threshold::new(20),
+            options: DetectOptions::default(),
+        }
+    }
+
+    pub fn sensitivity(mut self, sensitivity: u8) -> Self {
+        self.threshold.sensitivity = sensitivity;
+        self
+    }
+
+    pub fn max_sensitivity(mut self, max_sensitivity: Option<u8>) -> Self {
+        self.threshold.max_sensitivity = max_sensitivity;
+        self
+    }
+
+    pub fn invert(mut self, invert: bool) -> Self {
+        self.threshold.invert = invert;
+        self
+    }
+
+    pub fn connectivity(mut self, connectivity: Connectivity) -> Self {
+        self.options.connectivity = connectivity;
+        self
+    }
+
+    pub fn min_size(mut self, min_size: usize) -> Self {
+        self.options.min_size = min_size;
+        self
+    }
+
+    pub fn max_size(mut self, max_size: Option<usize>) -> Self {
+        self.options.max_size = max_size;
+        self
+    }
+
+    pub fn max_elongation(mut self, max_elongation: Option<f64>) -> Self {
+        self.options.max_elongation = max_elongation;
+        self
+    }
+
+    pub fn max_axis_ratio(mut self, max_axis_ratio: Option<f64>) -> Self {
+        self.options.max_axis_ratio = max_axis_ratio;
+        self
+    }
+
+    pub fn exclude_border(mut self, exclude_border: usize) -> Self {
+        self.options.exclude_border = exclude_border;
+        self
+    }
+
+    /// Stops [`StarField::detect`] and [`StarField::stars`] after this many stars.
+    /// [`group_at`] ignores it
+    pub fn limit(mut self, limit: Option<u64>) -> Self {
+        self.options.limit = limit;
+        self
+    }
+
+    pub fn width(&self) -> usize {
+        self.image.width() as usize
+    }
+
+    pub fn height(&self) -> usize {
+        self.image.height() as usize
+    }
+
+    pub fn image(&self) -> &'a GrayImage {
+        self.image
+    }
+
+    /// Which pixels pass the threshold, before any group is discarded
+    pub fn bitmap(&self) -> Bitmap {
+        threshold_image(self.image, self.threshold)
+    }
+
+    pub fn detect(&self) -> Detections {
+        let mut stars = self.bitmap();
+        let stats = count_groups(&mut stars, Some(self.image), &self.options);
+        Detections { stats, stars }
+    }
+
+    /// Finds the stars one at a time as they are iterated, instead of all of them up front
+    pub fn stars(&self) -> StarIter<'a> {
+        let stars = self.bitmap();
+        StarIter {
+            image: self.image,
+            options: self.options.clone(),
+            visited: Bitmap::new(stars.width(), stars.height()),
+            stars,
+            next: 0,
+            found: 0,
+        }
+    }
+}
+
+/// Lazily finds the stars of a [`StarField`], in the order a row by row scan reaches them
+///
+/// Every call to `next` scans on to the next unvisited star pixel and flood fills its group,
+/// so only the thresholded image is kept in memory and not the stars that were already
+/// yielded. Unlike [`Detections::iter`], stars are not sorted by centroid.
+#[derive(Clone, Debug)]
+pub struct StarIter<'a> {
+    image: &'a GrayImage,
+    options: DetectOptions,
+    stars: Bitmap,
+    visited: Bitmap,
+    /// Index of the first pixel that hasn't been scanned yet
+    next: usize,
+    /// How many stars were yielded, to stop at [`DetectOptions::limit`]
+    found: u64,
+}
+
+impl Iterator for StarIter<'_> {
+    type Item = Star;
+
+    fn next(&mut self) -> Option<Star> {
+        if self.options.limit.is_some_and(|limit| self.found >= limit) {
+            return None;
+        }
+        let width = self.stars.width();
+        loop {
+            let unvisited = self.stars.as_slice()[self.next..]
+                .iter()
+                .zip(&self.visited.as_slice()[self.next..])
+                .position(|(&star, &visited)| star && !visited)?;
+            let index = self.next + unvisited;
+            self.next = index + 1;
+
+            let star = measure_group(
+                self.image,
+                &self.stars,
+                &mut self.visited,
+                (index % width, index / width),
+                self.options.connectivity,
+            );
+            if self.options.accepts(&star)
+                && !self
+                    .options
+                    .touches_border(&star, width, self.stars.height())
+            {
+                self.found += 1;
+                return Some(star);
+            }
+        }
+    }
+}
+
+/// Calls `f` with every star of the field as the scan finds it, without keeping the stars that
+/// were already visited, e.g. to stream the stars of a huge image to disk
+///
+/// The stars come in the order of [`StarField::stars`], not sorted by centroid.
+pub fn detect_with<F: FnMut(&Star)>(field: &StarField, mut f: F) {
+    for star in field.stars() {
+        f(&star);
+    }
+}
+
+/// The star that the pixel at `(x, y)` is part of, or `None` if it is background or part of a
+/// group that the options of the field discard
+///
+/// This thresholds the whole image and flood fills only the group of the pixel, so it suits
+/// looking up single clicks rather than every pixel of an image.
+pub fn group_at(field: &StarField, x: usize, y: usize) -> Option<Star> {
+    if x >= field.width() || y >= field.height() {
+        return None;
+    }
+    let stars = field.bitmap();
+    if !stars.get(x, y) {
+        return None;
+    }
+    let mut visited = Bitmap::new(stars.width(), stars.height());
+    let star = measure_group(
+        field.image,
+        &stars,
+        &mut visited,
+        (x, y),
+        field.options.connectivity,
+    );
+    (field.options.accepts(&star)
+        && !field
+            .options
+            .touches_border(&star, stars.width(), stars.height()))
+    .then_some(star)
+}
+
+/// Flood fills the group containing `seed`, marking it visited, and measures it on the image
+fn measure_group(
+    image: &GrayImage,
+    stars: &Bitmap,
+    visited: &mut Bitmap,
+    (x, y): (usize, usize),
+    connectivity: Connectivity,
+) -> Star {
+    let group = mark_group((x, y), stars, visited, connectivity);
+    let mut moments = Moments::new(x, y);
+    for (x, y) in group {
+        moments.add(x, y, Some(image.get_pixel(x as u32, y as u32).0[0]));
+    }
+    moments.into_star()
+}
+
+/// Stars found in a [`StarField`]
+#[derive(Clone, Debug)]
+pub struct Detections {
+    stats: StarStats,
+    stars: Bitmap,
+}
+
+impl Detections {
+    pub fn count(&self) -> u64 {
+        self.stats.count
+    }
+
+    /// Every star, sorted by centroid `y` and then `x`
+    pub fn iter(&self) -> slice::Iter<'_, Star> {
+        self.stats.stars.iter()
+    }
+
+    pub fn stats(&self) -> &StarStats {
+        &self.stats
+    }
+
+    pub fn into_stats(self) -> StarStats {
+        self.stats
+    }
+
+    /// Pixels of the stars that were kept
+    pub fn bitmap(&self) -> &Bitmap {
+        &self.stars
+    }
+
+    /// Renders the kept stars in white on black
+    pub fn render(&self) -> GrayImage {
+        convert_to_image(&self.stars)
+    }
+}
+
+impl<'a> IntoIterator for &'a Detections {
+    type Item = &'a Star;
+    type IntoIter = slice::Iter<'a, Star>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
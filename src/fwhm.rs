@@ -0,0 +1,109 @@
+use image::GrayImage;
+
+use crate::Star;
+
+/// Stars with a larger [`Star::axis_ratio`] than this are too elongated for their width to
+/// say much about the focus
+pub const ROUND_AXIS_RATIO: f64 = 1.5;
+
+/// Directions the brightness profile of a star is followed in, evenly spread around it
+const DIRECTIONS: usize = 16;
+
+/// Step along the profile in pixels
+const STEP: f64 = 0.25;
+
+/// Median full width at half maximum of the round stars of an image, the usual measure of how
+/// well it is focused
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Fwhm {
+    /// In pixels
+    pub median: f64,
+    /// Number of stars it is the median of
+    pub measured: usize,
+}
+
+/// Full width at half maximum of the star in pixels, or `None` when its profile doesn't drop
+/// to half of its peak above the `background`
+///
+/// The profile is followed from the weighted centroid outwards in several directions,
+/// interpolating between pixels, and the width is twice the average distance at which it
+/// falls to half the peak. The star is measured on `luma`, which should be the image it was
+/// found in, and may reach beyond its thresholded pixels.
+pub fn fwhm(luma: &GrayImage, star: &Star, background: f64) -> Option<f64> {
+    let half = background + (star.peak as f64 - background) / 2.0;
+    let (center_x, center_y) = star.weighted_centroid;
+    if star.peak as f64 <= background || sample(luma, center_x, center_y)? <= half {
+        return None;
+    }
+    // Far enough out to reach the sky around any star that isn't mostly sky itself
+    let reach = 2.0 * (star.bounding_box.width().max(star.bounding_box.height()) as f64) + 2.0;
+    let mut radii = Vec::with_capacity(DIRECTIONS);
+    for direction in 0..DIRECTIONS {
+        let angle = direction as f64 * std::f64::consts::TAU / DIRECTIONS as f64;
+        let (dx, dy) = (angle.cos(), angle.sin());
+        let mut previous = (0.0, sample(luma, center_x, center_y)?);
+        let mut distance = STEP;
+        while distance <= reach {
+            let Some(value) = sample(luma, center_x + dx * distance, center_y + dy * distance)
+            else {
+                break;
+            };
+            if value <= half {
+                let (previous_distance, previous_value) = previous;
+                let fraction = (previous_value - half) / (previous_value - value);
+                radii.push(previous_distance + fraction * (distance - previous_distance));
+                break;
+            }
+            previous = (distance, value);
+            distance += STEP;
+        }
+    }
+    // Most of the directions have to reach half the peak inside the image
+    if radii.len() * 2 < DIRECTIONS {
+        return None;
+    }
+    Some(2.0 * radii.iter().sum::<f64>() / radii.len() as f64)
+}
+
+/// The median [`fwhm`] of the stars that are neither saturated, whose peak got cut off, nor
+/// more elongated than [`ROUND_AXIS_RATIO`], or `None` when none of them can be measured
+pub fn median_fwhm(luma: &GrayImage, stars: &[Star], background: f64) -> Option<Fwhm> {
+    let mut widths: Vec<f64> = stars
+        .iter()
+        .filter(|star| !star.is_saturated() && star.axis_ratio() <= ROUND_AXIS_RATIO)
+        .filter_map(|star| fwhm(luma, star, background))
+        .collect();
+    if widths.is_empty() {
+        return None;
+    }
+    widths.sort_unstable_by(f64::total_cmp);
+    let middle = widths.len() / 2;
+    let median = if widths.len().is_multiple_of(2) {
+        (widths[middle - 1] + widths[middle]) / 2.0
+    } else {
+        widths[middle]
+    };
+    Some(Fwhm {
+        median,
+        measured: widths.len(),
+    })
+}
+
+/// Bilinearly interpolated luma at the position, `None` outside the centres of the outermost
+/// pixels
+fn sample(luma: &GrayImage, x: f64, y: f64) -> Option<f64> {
+    let (width, height) = (luma.width() as f64, luma.height() as f64);
+    if x < 0.0 || y < 0.0 || x > width - 1.0 || y > height - 1.0 {
+        return None;
+    }
+    let (x0, y0) = (x.floor() as u32, y.floor() as u32);
+    let (x1, y1) = (
+        (x0 + 1).min(luma.width() - 1),
+        (y0 + 1).min(luma.height() - 1),
+    );
+    let (fx, fy) = (x - x0 as f64, y - y0 as f64);
+    let value = |x, y| luma.get_pixel(x, y).0[0] as f64;
+    let top = value(x0, y0) * (1.0 - fx) + value(x1, y0) * fx;
+    let bottom = value(x0, y1) * (1.0 - fx) + value(x1, y1) * fx;
+    Some(top * (1.0 - fy) + bottom * fy)
+}
@@ -0,0 +1,378 @@
+use std::{fmt, path::Path, str::FromStr};
+
+use image::{ColorType, DynamicImage, GrayImage, ImageBuffer, Luma, LumaA, Pixel, Primitive, Rgb};
+
+use crate::{threshold_image, Bitmap, StarCounterError, Threshold};
+
+/// Which part of a colour image is used as the brightness of a pixel
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Channel {
+    /// The usual luma conversion
+    Gray,
+    Red,
+    Green,
+    Blue,
+    /// The brightest of the three colour channels
+    Max,
+}
+
+impl FromStr for Channel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "gray" => Ok(Channel::Gray),
+            "red" => Ok(Channel::Red),
+            "green" => Ok(Channel::Green),
+            "blue" => Ok(Channel::Blue),
+            "max" => Ok(Channel::Max),
+            _ => Err(format!(
+                "channel must be one of gray, red, green, blue or max, got {s}"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Channel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Channel::Gray => "gray",
+            Channel::Red => "red",
+            Channel::Green => "green",
+            Channel::Blue => "blue",
+            Channel::Max => "max",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// Formula that combines the three colour channels into the brightness of a pixel
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GrayMethod {
+    /// Rec. 709 luma, the usual conversion, which is mostly green
+    Luma,
+    /// Mean of the three channels
+    Average,
+    /// HSL lightness, halfway between the brightest and the darkest channel
+    Lightness,
+    /// HSV value, the brightest channel, like [`Channel::Max`]. Keeps coloured stars as bright
+    /// as their strongest channel
+    Value,
+}
+
+impl FromStr for GrayMethod {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "luma" => Ok(GrayMethod::Luma),
+            "average" => Ok(GrayMethod::Average),
+            "lightness" => Ok(GrayMethod::Lightness),
+            "value" => Ok(GrayMethod::Value),
+            _ => Err(format!(
+                "gray method must be one of luma, average, lightness or value, got {s}"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for GrayMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            GrayMethod::Luma => "luma",
+            GrayMethod::Average => "average",
+            GrayMethod::Lightness => "lightness",
+            GrayMethod::Value => "value",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// How much each colour channel contributes to the brightness, as in `R,G,B`
+///
+/// Only the ratios matter, the weights are scaled to add up to 1 so no pixel is made brighter
+/// than white. The default is the usual Rec. 709 luma, like [`Channel::Gray`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GrayWeights {
+    pub red: f32,
+    pub green: f32,
+    pub blue: f32,
+}
+
+impl Default for GrayWeights {
+    fn default() -> Self {
+        GrayWeights {
+            red: 0.2126,
+            green: 0.7152,
+            blue: 0.0722,
+        }
+    }
+}
+
+impl FromStr for GrayWeights {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || format!("gray weights must be three numbers like 0.2,0.7,0.1, got {s}");
+        let weights: Vec<f32> = s
+            .split(',')
+            .map(|weight| weight.trim().parse().map_err(|_| error()))
+            .collect::<Result<_, _>>()?;
+        let [red, green, blue] = weights[..] else {
+            return Err(error());
+        };
+        if weights
+            .iter()
+            .any(|weight| !weight.is_finite() || *weight < 0.0)
+        {
+            return Err(format!("gray weights can't be negative, got {s}"));
+        }
+        if red + green + blue == 0.0 {
+            return Err(format!("at least one gray weight must be above 0, got {s}"));
+        }
+        Ok(GrayWeights { red, green, blue })
+    }
+}
+
+impl fmt::Display for GrayWeights {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{},{},{}", self.red, self.green, self.blue)
+    }
+}
+
+/// Whether the image has 16 bits per channel, so converting it to 8 bits would lose detail
+pub fn is_16_bit(img: &DynamicImage) -> bool {
+    matches!(
+        img.color(),
+        ColorType::L16 | ColorType::La16 | ColorType::Rgb16 | ColorType::Rgba16
+    )
+}
+
+/// The alpha channel of the image at 8 bits, or `None` when it has none
+///
+/// The luma conversions drop alpha, so this is how transparent parts can still be told apart
+/// from the sky.
+pub fn alpha_channel(img: &DynamicImage) -> Option<GrayImage> {
+    if !img.color().has_alpha() {
+        return None;
+    }
+    let rgba = img.to_rgba8();
+    Some(ImageBuffer::from_fn(rgba.width(), rgba.height(), |x, y| {
+        Luma([rgba.get_pixel(x, y).0[3]])
+    }))
+}
+
+/// A single channel float image, e.g. of a scientific TIFF, as 16 bits with an alpha channel,
+/// or `None` when there are fewer samples than pixels
+///
+/// Float images come in any unit, so the finite values are stretched linearly from the lowest
+/// of them at black to the highest at white. Pixels that are NaN or infinite, which often mark
+/// masked parts of the frame, become black and fully transparent, so like other transparent
+/// pixels they are never part of a star.
+pub fn luma16_from_float(
+    width: u32,
+    height: u32,
+    samples: &[f32],
+) -> Option<ImageBuffer<LumaA<u16>, Vec<u16>>> {
+    let samples = samples.get(..width as usize * height as usize)?;
+    let (low, high) = samples
+        .iter()
+        .filter(|value| value.is_finite())
+        .fold((f32::INFINITY, f32::NEG_INFINITY), |(low, high), &value| {
+            (low.min(value), high.max(value))
+        });
+    let range = high as f64 - low as f64;
+    let data = samples
+        .iter()
+        .flat_map(|&value| {
+            if !value.is_finite() {
+                return [0, 0];
+            }
+            let level = if range > 0.0 {
+                (value as f64 - low as f64) / range * u16::MAX as f64
+            } else {
+                0.0
+            };
+            [level.round() as u16, u16::MAX]
+        })
+        .collect();
+    ImageBuffer::from_raw(width, height, data)
+}
+
+/// Converts the image to a single brightness channel
+pub fn to_luma(img: &DynamicImage, channel: Channel) -> GrayImage {
+    match channel {
+        Channel::Gray => img.grayscale().to_luma8(),
+        _ => pick_channel(&img.to_rgb8(), channel),
+    }
+}
+
+/// Converts the image to a single 16-bit brightness channel
+pub fn to_luma16(img: &DynamicImage, channel: Channel) -> ImageBuffer<Luma<u16>, Vec<u16>> {
+    match channel {
+        Channel::Gray => img.to_luma16(),
+        _ => pick_channel(&img.to_rgb16(), channel),
+    }
+}
+
+/// Brightness of a decoded image at the bit depth of its file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DecodedImage {
+    Gray8(GrayImage),
+    Gray16(ImageBuffer<Luma<u16>, Vec<u16>>),
+}
+
+impl DecodedImage {
+    /// The brightness of the image, kept at 16 bits when [`is_16_bit`] says it has them
+    pub fn from_image(img: &DynamicImage, channel: Channel) -> Self {
+        if is_16_bit(img) {
+            DecodedImage::Gray16(to_luma16(img, channel))
+        } else {
+            DecodedImage::Gray8(to_luma(img, channel))
+        }
+    }
+
+    /// 8 or 16
+    pub fn bit_depth(&self) -> u8 {
+        match self {
+            DecodedImage::Gray8(_) => 8,
+            DecodedImage::Gray16(_) => 16,
+        }
+    }
+
+    /// Classifies every pixel as star or background, with `sensitivity` in the bit depth of the
+    /// image. Nothing is a star of an 8-bit image at a sensitivity above 255
+    pub fn threshold(&self, sensitivity: u16) -> Bitmap {
+        match self {
+            DecodedImage::Gray8(img) => match u8::try_from(sensitivity) {
+                Ok(sensitivity) => threshold_image(img, Threshold::new(sensitivity)),
+                Err(_) => Bitmap::new(img.width() as usize, img.height() as usize),
+            },
+            DecodedImage::Gray16(img) => threshold_image(img, Threshold::new(sensitivity)),
+        }
+    }
+
+    /// The brightness at 8 bits, which is what stars are measured on
+    pub fn to_luma8(&self) -> GrayImage {
+        match self {
+            DecodedImage::Gray8(img) => img.clone(),
+            DecodedImage::Gray16(img) => DynamicImage::ImageLuma16(img.clone()).to_luma8(),
+        }
+    }
+}
+
+/// Decodes the image file into its brightness without going through 8 bits first, so a 16-bit
+/// PNG or TIFF can still be thresholded at its full precision
+///
+/// Embedded colour profiles are not applied, the values are those stored in the file.
+pub fn decode_preserving_depth(path: impl AsRef<Path>) -> Result<DecodedImage, StarCounterError> {
+    let img = image::open(path)?;
+    Ok(DecodedImage::from_image(&img, Channel::Gray))
+}
+
+/// Converts the image to brightness with the given formula
+pub fn to_luma_with_method(img: &DynamicImage, method: GrayMethod) -> GrayImage {
+    match method {
+        GrayMethod::Luma => to_luma(img, Channel::Gray),
+        GrayMethod::Value => to_luma(img, Channel::Max),
+        _ => combine_channels(&img.to_rgb8(), method),
+    }
+}
+
+/// Same as [`to_luma_with_method`] at 16 bits
+pub fn to_luma16_with_method(
+    img: &DynamicImage,
+    method: GrayMethod,
+) -> ImageBuffer<Luma<u16>, Vec<u16>> {
+    match method {
+        GrayMethod::Luma => to_luma16(img, Channel::Gray),
+        GrayMethod::Value => to_luma16(img, Channel::Max),
+        _ => combine_channels(&img.to_rgb16(), method),
+    }
+}
+
+/// Converts the image to brightness with custom channel weights, e.g. for colour cameras where
+/// most of the signal is in green
+pub fn to_luma_weighted(img: &DynamicImage, weights: GrayWeights) -> GrayImage {
+    weigh_channels(&img.to_rgb8(), weights)
+}
+
+/// Same as [`to_luma_weighted`] at 16 bits
+pub fn to_luma16_weighted(
+    img: &DynamicImage,
+    weights: GrayWeights,
+) -> ImageBuffer<Luma<u16>, Vec<u16>> {
+    weigh_channels(&img.to_rgb16(), weights)
+}
+
+fn weigh_channels<T>(
+    rgb: &ImageBuffer<Rgb<T>, Vec<T>>,
+    weights: GrayWeights,
+) -> ImageBuffer<Luma<T>, Vec<T>>
+where
+    T: Primitive,
+    Rgb<T>: Pixel<Subpixel = T>,
+    Luma<T>: Pixel<Subpixel = T>,
+{
+    let total = weights.red + weights.green + weights.blue;
+    let [red, green, blue] = [weights.red, weights.green, weights.blue].map(|w| w / total);
+    let value = |channel: T| channel.to_f32().unwrap_or(0.0);
+    ImageBuffer::from_fn(rgb.width(), rgb.height(), |x, y| {
+        let [r, g, b] = rgb.get_pixel(x, y).0;
+        let luma = red * value(r) + green * value(g) + blue * value(b);
+        Luma([T::from(luma.round()).unwrap_or_else(T::max_value)])
+    })
+}
+
+/// The brightness by a formula of the channels that isn't a fixed weighting or one of them
+fn combine_channels<T>(
+    rgb: &ImageBuffer<Rgb<T>, Vec<T>>,
+    method: GrayMethod,
+) -> ImageBuffer<Luma<T>, Vec<T>>
+where
+    T: Primitive,
+    Rgb<T>: Pixel<Subpixel = T>,
+    Luma<T>: Pixel<Subpixel = T>,
+{
+    let value = |channel: T| channel.to_f32().unwrap_or(0.0);
+    ImageBuffer::from_fn(rgb.width(), rgb.height(), |x, y| {
+        let [r, g, b] = rgb.get_pixel(x, y).0.map(value);
+        let gray = match method {
+            GrayMethod::Average => (r + g + b) / 3.0,
+            GrayMethod::Lightness => (r.max(g).max(b) + r.min(g).min(b)) / 2.0,
+            GrayMethod::Luma | GrayMethod::Value => {
+                unreachable!("Luma and value are converted like channels")
+            }
+        };
+        Luma([T::from(gray.round()).unwrap_or_else(T::max_value)])
+    })
+}
+
+fn pick_channel<T>(
+    rgb: &ImageBuffer<Rgb<T>, Vec<T>>,
+    channel: Channel,
+) -> ImageBuffer<Luma<T>, Vec<T>>
+where
+    T: Primitive,
+    Rgb<T>: Pixel<Subpixel = T>,
+    Luma<T>: Pixel<Subpixel = T>,
+{
+    let pick = |[r, g, b]: [T; 3]| match channel {
+        Channel::Gray => unreachable!("Gray is converted by the image crate"),
+        Channel::Red => r,
+        Channel::Green => g,
+        Channel::Blue => b,
+        Channel::Max => max(max(r, g), b),
+    };
+    ImageBuffer::from_fn(rgb.width(), rgb.height(), |x, y| {
+        Luma([pick(rgb.get_pixel(x, y).0)])
+    })
+}
+
+fn max<T: PartialOrd>(a: T, b: T) -> T {
+    if b > a {
+        b
+    } else {
+        a
+    }
+}
@@ -0,0 +1,365 @@
+use std::ops::Range;
+
+use rayon::prelude::*;
+
+use crate::{Bitmap, Connectivity};
+
+/// The group every pixel of a bitmap belongs to
+///
+/// Background pixels have label 0 and groups are numbered from 1 in the order a row by row scan
+/// first reaches them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LabelMap {
+    width: usize,
+    height: usize,
+    labels: Vec<u32>,
+    count: usize,
+}
+
+impl LabelMap {
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> u32 {
+        debug_assert!(x < self.width && y < self.height);
+        self.labels[y * self.width + x]
+    }
+
+    /// Number of groups, which is also the largest label
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// The raw row-major labels
+    pub fn as_slice(&self) -> &[u32] {
+        &self.labels
+    }
+
+    /// Renumbers the groups in the order of [`StarStats::stars`](crate::StarStats), by
+    /// centroid `y` and then `x`, so label `n` is the star at index `n - 1`
+    pub fn sorted_by_centroid(mut self) -> Self {
+        let mut sums = vec![(0, 0, 0); self.count + 1];
+        for (index, &label) in self.labels.iter().enumerate() {
+            let (sum_x, sum_y, area) = &mut sums[label as usize];
+            *sum_x += index % self.width;
+            *sum_y += index / self.width;
+            *area += 1;
+        }
+        let mut order: Vec<usize> = (1..=self.count).collect();
+        order.sort_by_key(|&label| {
+            let (sum_x, sum_y, area) = sums[label];
+            (sum_y / area, sum_x / area)
+        });
+        let mut numbers = vec![0; self.count + 1];
+        for (number, label) in order.into_iter().enumerate() {
+            numbers[label] = number as u32 + 1;
+        }
+        for label in &mut self.labels {
+            *label = numbers[*label as usize];
+        }
+        self
+    }
+}
+
+/// Labels the connected groups of set pixels with the classic two-pass algorithm
+///
+/// The first pass gives every pixel the provisional label of an earlier neighbour, recording
+/// in a union-find which labels turned out to be the same group. The second pass replaces
+/// every label by the final number of its group.
+pub fn label_groups(stars: &Bitmap, connectivity: Connectivity) -> LabelMap {
+    let (mut labels, numbers, count) = provisional_labels(stars, connectivity);
+    for label in &mut labels {
+        *label = numbers[*label as usize];
+    }
+    LabelMap {
+        width: stars.width(),
+        height: stars.height(),
+        labels,
+        count,
+    }
+}
+
+/// Number of connected groups of set pixels, without labelling or measuring any of them
+///
+/// Only the labels of the row above are kept, and every union of two separate groups lowers the
+/// count by one, so memory grows with the width and the number of groups but not the height.
+/// Radii above 1 reach further back than a row, so they are counted with the first pass of
+/// [`label_groups`].
+pub fn count_components(stars: &Bitmap, connectivity: Connectivity) -> usize {
+    if let Connectivity::Radius(2..) = connectivity {
+        return provisional_labels(stars, connectivity).2;
+    }
+    let diagonals = !matches!(connectivity, Connectivity::Four | Connectivity::Radius(0));
+    let width = stars.width();
+    let (mut above, mut row) = (vec![0u32; width], vec![0u32; width]);
+    let mut parents = vec![0u32];
+    let mut count = 0;
+    for y in 0..stars.height() {
+        for (x, &star) in stars.row(y).iter().enumerate() {
+            if !star {
+                row[x] = 0;
+                continue;
+            }
+            let west = if x > 0 { row[x - 1] } else { 0 };
+            let mut neighbours = [west, above[x], 0, 0];
+            if diagonals {
+                neighbours[2] = if x > 0 { above[x - 1] } else { 0 };
+                neighbours[3] = if x + 1 < width { above[x + 1] } else { 0 };
+            }
+            let mut label = 0;
+            for neighbour in neighbours.into_iter().filter(|&neighbour| neighbour != 0) {
+                if label == 0 {
+                    label = neighbour;
+                } else if join(&mut parents, label, neighbour) {
+                    count -= 1;
+                }
+            }
+            if label == 0 {
+                label = parents.len() as u32;
+                parents.push(label);
+                count += 1;
+            }
+            row[x] = label;
+        }
+        std::mem::swap(&mut above, &mut row);
+    }
+    count
+}
+
+/// The first pass of [`label_groups`], returning the provisional label of every pixel, the
+/// final number of every provisional label and the number of groups
+pub(crate) fn provisional_labels(
+    stars: &Bitmap,
+    connectivity: Connectivity,
+) -> (Vec<u32>, Vec<u32>, usize) {
+    let (width, height) = (stars.width(), stars.height());
+    let mut labels = vec![0u32; width * height];
+    let mut parents = label_rows(stars, 0..height, &mut labels, connectivity);
+    let count = number_groups(&mut parents);
+    (labels, parents, count)
+}
+
+/// Gives the star pixels of `rows` provisional labels in `labels`, which holds just those rows,
+/// as if the row above them was background. Returns the union-find over the labels, where
+/// label 0 is the background
+fn label_rows(
+    stars: &Bitmap,
+    rows: Range<usize>,
+    labels: &mut [u32],
+    connectivity: Connectivity,
+) -> Vec<u32> {
+    let width = stars.width();
+    // Provisional label 0 is the background, so it is its own root
+    let mut parents = vec![0u32];
+
+    let background = vec![0; width];
+    for (y, row_y) in rows.enumerate() {
+        let (done, rest) = labels.split_at_mut(y * width);
+        let above = if y > 0 {
+            &done[(y - 1) * width..]
+        } else {
+            &background
+        };
+        let row = &mut rest[..width];
+        for (x, &star) in stars.row(row_y).iter().enumerate() {
+            if !star {
+                continue;
+            }
+            if let Connectivity::Radius(radius @ 2..) = connectivity {
+                row[x] = cluster_label(done, row, width, (x, y), radius, &mut parents);
+                continue;
+            }
+            let west = if x > 0 { row[x - 1] } else { 0 };
+            let north_west = if x > 0 { above[x - 1] } else { 0 };
+            let north = above[x];
+            let north_east = if x + 1 < width { above[x + 1] } else { 0 };
+            let label = match connectivity {
+                Connectivity::Four | Connectivity::Radius(0) => match (west, north) {
+                    (0, 0) => 0,
+                    (label, 0) | (0, label) => label,
+                    (west, north) => union(&mut parents, west, north),
+                },
+                // North touches every other neighbour, so a set north already joined them
+                _ if north != 0 => north,
+                _ if north_east != 0 => match (north_west, west) {
+                    (0, 0) => north_east,
+                    (0, other) | (other, _) => union(&mut parents, north_east, other),
+                },
+                _ => north_west.max(west),
+            };
+            row[x] = label_or_new(&mut parents, label);
+        }
+    }
+    parents
+}
+
+/// Replaces every label of the union-find by the final number of its group, returning the
+/// number of groups
+fn number_groups(parents: &mut [u32]) -> usize {
+    // Parents are always smaller than their children, so going up from the smallest label
+    // numbers every group before any label that refers to it. Roots are the smallest label of
+    // their group, which is the one the scan reached first
+    let mut count = 0;
+    for label in 1..parents.len() {
+        let parent = parents[label] as usize;
+        parents[label] = if parent == label {
+            count += 1;
+            count
+        } else {
+            parents[parent]
+        };
+    }
+    count as usize
+}
+
+/// Fewest rows of a strip of [`label_groups_parallel`], below which joining the strips costs
+/// more than labelling them on separate threads saves
+const MIN_STRIP_ROWS: usize = 64;
+
+/// Same as [`label_groups`], labelling horizontal strips of the bitmap on separate threads
+///
+/// Every strip is labelled on its own, then the labels of the groups that touch across the
+/// boundaries between strips are joined. The labels are exactly those of [`label_groups`],
+/// whatever the number of threads. Radii above 1 reach further than the row above, so they are
+/// labelled in one strip.
+pub fn label_groups_parallel(stars: &Bitmap, connectivity: Connectivity) -> LabelMap {
+    let (mut labels, numbers, count) = provisional_labels_parallel(stars, connectivity);
+    labels.par_iter_mut().for_each(|label| {
+        *label = numbers[*label as usize];
+    });
+    LabelMap {
+        width: stars.width(),
+        height: stars.height(),
+        labels,
+        count,
+    }
+}
+
+/// Same as [`provisional_labels`], labelling a strip of rows per thread
+pub(crate) fn provisional_labels_parallel(
+    stars: &Bitmap,
+    connectivity: Connectivity,
+) -> (Vec<u32>, Vec<u32>, usize) {
+    let (width, height) = (stars.width(), stars.height());
+    let strip_rows = height
+        .div_ceil(rayon::current_num_threads())
+        .max(MIN_STRIP_ROWS);
+    let diagonals = match connectivity {
+        Connectivity::Four | Connectivity::Radius(0) => false,
+        Connectivity::Eight | Connectivity::Radius(1) => true,
+        Connectivity::Radius(_) => return provisional_labels(stars, connectivity),
+    };
+    if width == 0 || strip_rows >= height {
+        return provisional_labels(stars, connectivity);
+    }
+
+    let mut labels = vec![0u32; width * height];
+    let strips: Vec<Vec<u32>> = labels
+        .par_chunks_mut(strip_rows * width)
+        .enumerate()
+        .map(|(strip, labels)| {
+            let start = strip * strip_rows;
+            let rows = start..(start + strip_rows).min(height);
+            label_rows(stars, rows, labels, connectivity)
+        })
+        .collect();
+
+    // Later strips continue the labels of the earlier ones, which keeps the labels in scan
+    // order and every parent smaller than its children
+    let mut offsets = Vec::with_capacity(strips.len());
+    let mut parents = vec![0u32];
+    for strip in &strips {
+        let offset = parents.len() as u32 - 1;
+        offsets.push(offset);
+        parents.extend(strip[1..].iter().map(|&parent| parent + offset));
+    }
+    labels
+        .par_chunks_mut(strip_rows * width)
+        .zip(&offsets)
+        .for_each(|(labels, &offset)| {
+            for label in labels.iter_mut().filter(|label| **label != 0) {
+                *label += offset;
+            }
+        });
+
+    for y in (strip_rows..height).step_by(strip_rows) {
+        let (above, row) = labels[(y - 1) * width..(y + 1) * width].split_at(width);
+        for (x, &label) in row.iter().enumerate().filter(|(_, &label)| label != 0) {
+            let from = if diagonals { x.saturating_sub(1) } else { x };
+            let to = if diagonals { (x + 1).min(width - 1) } else { x };
+            for &neighbour in above[from..=to].iter().filter(|&&label| label != 0) {
+                union(&mut parents, label, neighbour);
+            }
+        }
+    }
+    let count = number_groups(&mut parents);
+    (labels, parents, count)
+}
+
+/// The label of a star pixel given the label of its neighbours, which is a new one if none of
+/// them are stars
+fn label_or_new(parents: &mut Vec<u32>, label: u32) -> u32 {
+    if label == 0 {
+        let label = parents.len() as u32;
+        parents.push(label);
+        label
+    } else {
+        label
+    }
+}
+
+/// Joins every labelled pixel at most `radius` away from `(x, y)` that the scan already passed,
+/// which are the `radius` rows above and the pixels to the left in its own row
+///
+/// Unlike the fixed neighbourhoods this looks at `radius * (2 * radius + 1) + radius` pixels for
+/// every star pixel, so its cost grows with the square of the radius.
+fn cluster_label(
+    done: &[u32],
+    row: &[u32],
+    width: usize,
+    (x, y): (usize, usize),
+    radius: usize,
+    parents: &mut Vec<u32>,
+) -> u32 {
+    let (from, to) = (x.saturating_sub(radius), (x + radius + 1).min(width));
+    let above = done[y.saturating_sub(radius) * width..]
+        .chunks_exact(width)
+        .flat_map(|above| &above[from..to]);
+    let mut label = 0;
+    for &neighbour in above.chain(&row[from..x]) {
+        label = match (label, neighbour) {
+            (label, 0) => label,
+            (0, neighbour) => neighbour,
+            (label, neighbour) => union(parents, label, neighbour),
+        };
+    }
+    label_or_new(parents, label)
+}
+
+fn find(parents: &mut [u32], mut label: u32) -> u32 {
+    while parents[label as usize] != label {
+        parents[label as usize] = parents[parents[label as usize] as usize];
+        label = parents[label as usize];
+    }
+    label
+}
+
+/// Joins the groups of both labels, returning whether they were separate before
+fn join(parents: &mut [u32], a: u32, b: u32) -> bool {
+    let (a, b) = (find(parents, a), find(parents, b));
+    parents[a.max(b) as usize] = a.min(b);
+    a != b
+}
+
+/// Joins the groups of both labels, returning the root of the joined group
+fn union(parents: &mut [u32], a: u32, b: u32) -> u32 {
+    let (a, b) = (find(parents, a), find(parents, b));
+    let (root, child) = (a.min(b), a.max(b));
+    parents[child as usize] = root;
+    root
+}
@@ -0,0 +1,1236 @@
+use std::{fmt, str::FromStr, time::Instant};
+
+use image::{GrayImage, ImageBuffer, Luma, Primitive};
+use log::{debug, trace};
+use rayon::prelude::*;
+use sha2::{Digest, Sha256};
+
+mod adaptive;
+mod bitmap;
+mod calibration;
+mod compare;
+mod error;
+mod field;
+mod fwhm;
+mod grayscale;
+mod labels;
+mod merge;
+pub mod morphology;
+#[cfg(feature = "pyo3")]
+mod python;
+mod render;
+mod retry;
+mod roi;
+mod split;
+mod stretch;
+mod threshold;
+mod tiled;
+
+pub use adaptive::{adaptive_threshold, subtract_background};
+pub use bitmap::Bitmap;
+pub use calibration::subtract_dark;
+pub use compare::{match_stars, StarMatches};
+pub use error::StarCounterError;
+pub use field::{detect_with, group_at, Detections, StarField, StarIter};
+pub use fwhm::{fwhm, median_fwhm, Fwhm, ROUND_AXIS_RATIO};
+pub use grayscale::{
+    alpha_channel, decode_preserving_depth, is_16_bit, luma16_from_float, to_luma, to_luma16,
+    to_luma16_weighted, to_luma16_with_method, to_luma_weighted, to_luma_with_method, Channel,
+    DecodedImage, GrayMethod, GrayWeights,
+};
+use labels::provisional_labels_parallel;
+pub use labels::{count_components, label_groups, label_groups_parallel, LabelMap};
+pub use merge::merge_close_stars;
+pub use render::{
+    annotate, colorize, density_map, mark_centroids, overlay, render_label_colors, render_labels,
+    render_to_png_bytes, thumbnail,
+};
+pub use retry::{is_transient, read_with_retries};
+pub use roi::Roi;
+pub use split::split_peaks;
+pub use stretch::{normalize, stretch, Stretch};
+pub use threshold::{
+    assess_exposure, assess_groups, estimate_background, estimate_background_from_histogram,
+    histogram, otsu_threshold, otsu_threshold_from_histogram, percentile_threshold,
+    percentile_threshold_from_histogram, BackgroundEstimate, ExposureWarning, MERGED_FRACTION,
+    OVEREXPOSED_FRACTION,
+};
+pub use tiled::{count_groups_tiled, RowLabeller};
+
+/// Which neighbours of a pixel are considered connected to it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Connectivity {
+    /// Only up, down, left and right
+    Four,
+    /// Also the diagonals
+    Eight,
+    /// Every pixel at most this many pixels away horizontally and vertically, so nearby groups
+    /// are counted as one cluster. A radius of 1 is the same as [`Connectivity::Eight`] and 0 the
+    /// same as [`Connectivity::Four`]
+    ///
+    /// Labelling looks at every pixel within the radius of each star pixel, so the cost grows
+    /// with the square of the radius and large radii get expensive on dense fields.
+    Radius(usize),
+}
+
+impl FromStr for Connectivity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "4" => Ok(Connectivity::Four),
+            "8" => Ok(Connectivity::Eight),
+            _ => Err(format!("connectivity must be 4 or 8, got {s}")),
+        }
+    }
+}
+
+impl fmt::Display for Connectivity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Connectivity::Four => write!(f, "4"),
+            Connectivity::Eight => write!(f, "8"),
+            Connectivity::Radius(radius) => write!(f, "radius {radius}"),
+        }
+    }
+}
+
+/// Which opposite edges of the image are neighbours, for panoramas that go all the way round
+/// the sky so a star on the seam isn't counted twice
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Wrap {
+    /// The edges are the end of the image
+    #[default]
+    None,
+    /// The left and right edges touch
+    Horizontal,
+    /// The left and right edges touch, and so do the top and bottom ones
+    Both,
+}
+
+impl Wrap {
+    /// Whether the left and right edges touch
+    pub fn horizontal(&self) -> bool {
+        *self != Wrap::None
+    }
+
+    /// Whether the top and bottom edges touch
+    pub fn vertical(&self) -> bool {
+        *self == Wrap::Both
+    }
+}
+
+impl FromStr for Wrap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Wrap::None),
+            "horizontal" => Ok(Wrap::Horizontal),
+            "both" => Ok(Wrap::Both),
+            _ => Err(format!("wrap must be none, horizontal or both, got {s}")),
+        }
+    }
+}
+
+impl fmt::Display for Wrap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Wrap::None => "none",
+            Wrap::Horizontal => "horizontal",
+            Wrap::Both => "both",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// Whether the pixel is bright enough to be considered part of a star
+pub fn is_white<T: Primitive>(pixel: &Luma<T>, sensitivity: T) -> bool {
+    pixel.0[0] > sensitivity
+}
+
+/// Whether the pixel is brighter than `low` but not brighter than `high`
+pub fn is_in_band<T: Primitive>(pixel: &Luma<T>, low: T, high: T) -> bool {
+    is_white(pixel, low) && pixel.0[0] <= high
+}
+
+/// How pixels are classified as star or background. `T` is the subpixel type of the image,
+/// so the sensitivity of a 16-bit image is in range from 0 to 65535
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Threshold<T = u8> {
+    pub sensitivity: T,
+    /// Pixels brighter than this aren't stars either, e.g. to leave out saturated blooms
+    pub max_sensitivity: Option<T>,
+    /// Stars are darker than the background, so pixels below `sensitivity` are stars.
+    /// `max_sensitivity` is ignored then
+    pub invert: bool,
+}
+
+impl<T: Primitive> Threshold<T> {
+    pub fn new(sensitivity: T) -> Self {
+        Threshold {
+            sensitivity,
+            max_sensitivity: None,
+            invert: false,
+        }
+    }
+
+    /// Whether the pixel is part of a star
+    pub fn is_star(&self, pixel: &Luma<T>) -> bool {
+        if self.invert {
+            pixel.0[0] < self.sensitivity
+        } else if let Some(max_sensitivity) = self.max_sensitivity {
+            is_in_band(pixel, self.sensitivity, max_sensitivity)
+        } else {
+            is_white(pixel, self.sensitivity)
+        }
+    }
+}
+
+/// Classifies every pixel of the image as star or background
+pub fn threshold_image<T: Primitive + Send + Sync>(
+    img: &ImageBuffer<Luma<T>, Vec<T>>,
+    threshold: Threshold<T>,
+) -> Bitmap {
+    threshold_image_with_progress(img, threshold, || {})
+}
+
+/// Same as [`threshold_image`], calling `on_row` from the worker threads after each row
+pub fn threshold_image_with_progress<T: Primitive + Send + Sync>(
+    img: &ImageBuffer<Luma<T>, Vec<T>>,
+    threshold: Threshold<T>,
+    on_row: impl Fn() + Sync,
+) -> Bitmap {
+    let (width, height) = (img.width() as usize, img.height() as usize);
+    let mut data = vec![false; width * height];
+    if data.is_empty() {
+        return Bitmap::new(width, height);
+    }
+    data.par_chunks_mut(width)
+        .zip(img.par_chunks(width))
+        .for_each(|(row, pixels)| {
+            for (star, &pixel) in row.iter_mut().zip(pixels) {
+                *star = threshold.is_star(&Luma([pixel]));
+            }
+            on_row();
+        });
+    Bitmap::from_vec(width, height, data)
+}
+
+/// Smallest rectangle containing every pixel of a star, inclusive on both ends. A star across
+/// the seam of a [wrapping](Wrap) image continues past the right or bottom edge, so `x_max` or
+/// `y_max` can be outside of it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BoundingBox {
+    pub x_min: usize,
+    pub y_min: usize,
+    pub x_max: usize,
+    pub y_max: usize,
+}
+
+impl BoundingBox {
+    pub fn width(&self) -> usize {
+        self.x_max - self.x_min + 1
+    }
+
+    pub fn height(&self) -> usize {
+        self.y_max - self.y_min + 1
+    }
+
+    pub fn area(&self) -> usize {
+        self.width() * self.height()
+    }
+}
+
+/// A single detected star
+#[derive(Clone, Debug, PartialEq)]
+pub struct Star {
+    /// Number of pixels in the group
+    pub area: usize,
+    /// Mean position of the group's pixels, as `(x, y)`
+    pub centroid: (usize, usize),
+    /// Position of the group's pixels weighted by their original luma, as `(x, y)`. It is the
+    /// unrounded mean position when no image was given or the group is completely black
+    pub weighted_centroid: (f64, f64),
+    pub bounding_box: BoundingBox,
+    /// Sum of the original luma of the group's pixels, 0 when no image was given
+    pub brightness: u64,
+    /// Brightest original luma in the group, 0 when no image was given
+    pub peak: u8,
+    /// Spread of the group's pixels around the weighted centroid, weighted like it
+    pub moments: SecondMoments,
+}
+
+/// Second central moments of a star, the variances of its pixel positions along `x` and `y`
+/// and their covariance. A single pixel has all of them 0
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SecondMoments {
+    pub xx: f64,
+    pub yy: f64,
+    pub xy: f64,
+}
+
+impl SecondMoments {
+    /// Variance of the position within a pixel, so a star measures as covering whole pixels
+    /// rather than their centres and lines one pixel wide have a width
+    const PIXEL_VARIANCE: f64 = 1.0 / 12.0;
+
+    /// Variances along the major and minor axis of the star, largest first
+    fn principal(&self) -> (f64, f64) {
+        let (xx, yy) = (
+            self.xx + Self::PIXEL_VARIANCE,
+            self.yy + Self::PIXEL_VARIANCE,
+        );
+        let mean = (xx + yy) / 2.0;
+        let spread = ((xx - yy) / 2.0).hypot(self.xy);
+        (mean + spread, (mean - spread).max(0.0))
+    }
+}
+
+/// Running sums over the pixels of a group, which can be combined when two parts of a group
+/// turn out to be connected
+#[derive(Clone, Debug)]
+pub(crate) struct Moments {
+    area: usize,
+    sum_x: usize,
+    sum_y: usize,
+    brightness: u64,
+    peak: u8,
+    weighted_x: u64,
+    weighted_y: u64,
+    sum_xx: usize,
+    sum_yy: usize,
+    sum_xy: usize,
+    weighted_xx: u128,
+    weighted_yy: u128,
+    weighted_xy: u128,
+    bounding_box: BoundingBox,
+}
+
+impl Moments {
+    /// Empty sums for a group that will contain `(x, y)`
+    pub(crate) fn new(x: usize, y: usize) -> Self {
+        Moments {
+            area: 0,
+            sum_x: 0,
+            sum_y: 0,
+            brightness: 0,
+            peak: 0,
+            weighted_x: 0,
+            weighted_y: 0,
+            sum_xx: 0,
+            sum_yy: 0,
+            sum_xy: 0,
+            weighted_xx: 0,
+            weighted_yy: 0,
+            weighted_xy: 0,
+            bounding_box: BoundingBox {
+                x_min: x,
+                y_min: y,
+                x_max: x,
+                y_max: y,
+            },
+        }
+    }
+
+    /// Adds a pixel with its original luma, if there is an image to measure it from
+    pub(crate) fn add(&mut self, x: usize, y: usize, luma: Option<u8>) {
+        self.area += 1;
+        self.sum_x += x;
+        self.sum_y += y;
+        self.sum_xx += x * x;
+        self.sum_yy += y * y;
+        self.sum_xy += x * y;
+        if let Some(value) = luma {
+            self.brightness += value as u64;
+            self.peak = self.peak.max(value);
+            self.weighted_x += x as u64 * value as u64;
+            self.weighted_y += y as u64 * value as u64;
+            let (x, y, value) = (x as u128, y as u128, value as u128);
+            self.weighted_xx += x * x * value;
+            self.weighted_yy += y * y * value;
+            self.weighted_xy += x * y * value;
+        }
+        let bounding_box = &mut self.bounding_box;
+        bounding_box.x_min = bounding_box.x_min.min(x);
+        bounding_box.y_min = bounding_box.y_min.min(y);
+        bounding_box.x_max = bounding_box.x_max.max(x);
+        bounding_box.y_max = bounding_box.y_max.max(y);
+    }
+
+    pub(crate) fn merge(&mut self, other: &Moments) {
+        self.area += other.area;
+        self.sum_x += other.sum_x;
+        self.sum_y += other.sum_y;
+        self.brightness += other.brightness;
+        self.peak = self.peak.max(other.peak);
+        self.weighted_x += other.weighted_x;
+        self.weighted_y += other.weighted_y;
+        self.sum_xx += other.sum_xx;
+        self.sum_yy += other.sum_yy;
+        self.sum_xy += other.sum_xy;
+        self.weighted_xx += other.weighted_xx;
+        self.weighted_yy += other.weighted_yy;
+        self.weighted_xy += other.weighted_xy;
+        let (bounding_box, other) = (&mut self.bounding_box, other.bounding_box);
+        bounding_box.x_min = bounding_box.x_min.min(other.x_min);
+        bounding_box.y_min = bounding_box.y_min.min(other.y_min);
+        bounding_box.x_max = bounding_box.x_max.max(other.x_max);
+        bounding_box.y_max = bounding_box.y_max.max(other.y_max);
+    }
+
+    /// The star made of the added pixels, of which there must be at least one
+    pub(crate) fn into_star(self) -> Star {
+        let area = self.area;
+        // Sums of x, y, x squared, y squared, x times y and the total weight
+        let sums = if self.brightness > 0 {
+            [
+                self.weighted_x as f64,
+                self.weighted_y as f64,
+                self.weighted_xx as f64,
+                self.weighted_yy as f64,
+                self.weighted_xy as f64,
+                self.brightness as f64,
+            ]
+        } else {
+            [
+                self.sum_x as f64,
+                self.sum_y as f64,
+                self.sum_xx as f64,
+                self.sum_yy as f64,
+                self.sum_xy as f64,
+                area as f64,
+            ]
+        };
+        let [x, y, xx, yy, xy, weight] = sums;
+        let weighted_centroid = (x / weight, y / weight);
+        let (mean_x, mean_y) = weighted_centroid;
+        // Rounding can leave a tiny negative variance for a group in a single row or column
+        let moments = SecondMoments {
+            xx: (xx / weight - mean_x * mean_x).max(0.0),
+            yy: (yy / weight - mean_y * mean_y).max(0.0),
+            xy: xy / weight - mean_x * mean_y,
+        };
+        Star {
+            area,
+            centroid: (self.sum_x / area, self.sum_y / area),
+            weighted_centroid,
+            bounding_box: self.bounding_box,
+            brightness: self.brightness,
+            peak: self.peak,
+            moments,
+        }
+    }
+}
+
+impl Star {
+    /// How many times the longer side of the bounding box is longer than the shorter one, 1.0
+    /// for a square. Cosmic rays and satellite trails are much more elongated than stars
+    pub fn elongation(&self) -> f64 {
+        let (width, height) = (self.bounding_box.width(), self.bounding_box.height());
+        width.max(height) as f64 / width.min(height) as f64
+    }
+
+    /// How many times longer the major axis of the star is than its minor axis, from its
+    /// [`SecondMoments`]. Unlike [`Star::elongation`] this doesn't depend on the direction of
+    /// the star, so a diagonal trail is as elongated as a horizontal one
+    pub fn axis_ratio(&self) -> f64 {
+        let (major, minor) = self.moments.principal();
+        (major / minor).sqrt()
+    }
+
+    /// Angle of the major axis in degrees from the `x` axis, between -90 and 90. Since `y`
+    /// grows downwards, positive angles are clockwise on the image
+    pub fn orientation(&self) -> f64 {
+        let moments = self.moments;
+        0.5 * (2.0 * moments.xy)
+            .atan2(moments.xx - moments.yy)
+            .to_degrees()
+    }
+
+    /// Semi-major and semi-minor axis of the ellipse with the same [`SecondMoments`] as the
+    /// star, in pixels. A filled ellipse has them twice its standard deviations along its
+    /// axes, so a round star gets the radius of a disc of its area
+    pub fn semi_axes(&self) -> (f64, f64) {
+        let (major, minor) = self.moments.principal();
+        (2.0 * major.sqrt(), 2.0 * minor.sqrt())
+    }
+
+    /// Fraction of the bounding box covered by the star, about 0.79 for a round star
+    pub fn fill_ratio(&self) -> f64 {
+        self.area as f64 / self.bounding_box.area() as f64
+    }
+
+    /// Moves the centroids of a star measured past the right or bottom edge of a `width` by
+    /// `height` image that [wraps](Wrap) back into it
+    fn wrap_centroid(&mut self, width: usize, height: usize) {
+        self.centroid = (self.centroid.0 % width, self.centroid.1 % height);
+        let (x, y) = self.weighted_centroid;
+        self.weighted_centroid = (x.rem_euclid(width as f64), y.rem_euclid(height as f64));
+    }
+
+    /// Whether the star reaches white, so it was overexposed and may have bled into its
+    /// neighbours. Never true when no image was given
+    pub fn is_saturated(&self) -> bool {
+        self.peak == u8::MAX
+    }
+}
+
+/// Result of counting the stars in a grid
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StarStats {
+    pub count: u64,
+    /// Every counted star, sorted by centroid `y` and then `x`
+    pub stars: Vec<Star>,
+}
+
+impl StarStats {
+    /// Pixel area of every star
+    pub fn sizes(&self) -> Vec<usize> {
+        self.stars.iter().map(|star| star.area).collect()
+    }
+
+    /// Number of star centroids in each cell of a `columns` by `rows` grid over a `width` by
+    /// `height` image, row by row from the top left
+    pub fn grid_counts(
+        &self,
+        width: usize,
+        height: usize,
+        columns: usize,
+        rows: usize,
+    ) -> Vec<Vec<u64>> {
+        let mut counts = vec![vec![0; columns]; rows];
+        if width == 0 || height == 0 {
+            return counts;
+        }
+        for star in &self.stars {
+            let (x, y) = star.centroid;
+            let column = (x * columns / width).min(columns.saturating_sub(1));
+            let row = (y * rows / height).min(rows.saturating_sub(1));
+            if let Some(count) = counts.get_mut(row).and_then(|row| row.get_mut(column)) {
+                *count += 1;
+            }
+        }
+        counts
+    }
+
+    /// Moves every star by the offset, e.g. to go from a crop's coordinates to the full image
+    pub fn translate(&mut self, dx: usize, dy: usize) {
+        for star in &mut self.stars {
+            star.centroid.0 += dx;
+            star.centroid.1 += dy;
+            star.weighted_centroid.0 += dx as f64;
+            star.weighted_centroid.1 += dy as f64;
+            star.bounding_box.x_min += dx;
+            star.bounding_box.x_max += dx;
+            star.bounding_box.y_min += dy;
+            star.bounding_box.y_max += dy;
+        }
+    }
+
+    /// Maps every star from a resized image back to the image it was resized from, which is
+    /// `factor_x` times wider and `factor_y` times taller. Areas and brightness grow with the
+    /// pixels, so they stay comparable to counting the original image
+    pub fn scale(&mut self, factor_x: f64, factor_y: f64) {
+        // Pixel centres are half a pixel in, so they don't scale from 0
+        let position = |position: f64, factor: f64| (position + 0.5) * factor - 0.5;
+        // Stretches the pixels from `min` to `max` inclusive, keeping at least one
+        let span = |min: usize, max: usize, factor: f64| {
+            let edge = |edge: usize| (edge as f64 * factor).round() as usize;
+            let min = edge(min);
+            (min, (edge(max + 1).saturating_sub(1)).max(min))
+        };
+        for star in &mut self.stars {
+            let (x, y) = star.weighted_centroid;
+            star.weighted_centroid = (position(x, factor_x), position(y, factor_y));
+            let (x, y) = star.centroid;
+            star.centroid = (
+                position(x as f64, factor_x).round().max(0.0) as usize,
+                position(y as f64, factor_y).round().max(0.0) as usize,
+            );
+            let bounding_box = &mut star.bounding_box;
+            (bounding_box.x_min, bounding_box.x_max) =
+                span(bounding_box.x_min, bounding_box.x_max, factor_x);
+            (bounding_box.y_min, bounding_box.y_max) =
+                span(bounding_box.y_min, bounding_box.y_max, factor_y);
+            let pixel_area = factor_x * factor_y;
+            star.area = (star.area as f64 * pixel_area).round() as usize;
+            star.brightness = (star.brightness as f64 * pixel_area).round() as u64;
+            let moments = &mut star.moments;
+            moments.xx *= factor_x * factor_x;
+            moments.yy *= factor_y * factor_y;
+            moments.xy *= factor_x * factor_y;
+        }
+    }
+
+    /// The `n` stars with the most summed brightness, brightest first and by their order in
+    /// [`StarStats::stars`] when equally bright
+    ///
+    /// Only the brightest are sorted, so this is quicker than sorting every star of a dense
+    /// field.
+    pub fn brightest(&self, n: usize) -> Vec<Star> {
+        self.top_n_by(n, |star| star.brightness)
+    }
+
+    /// The `n` stars with the most pixels, largest first and by their order in
+    /// [`StarStats::stars`] when equally large, like [`StarStats::brightest`] by area. The
+    /// largest groups are usually the moon, planets or stars that merged
+    pub fn largest(&self, n: usize) -> Vec<Star> {
+        self.top_n_by(n, |star| star.area as u64)
+    }
+
+    /// The `n` stars with the highest `key`, highest first and by their order in
+    /// [`StarStats::stars`] when equal
+    fn top_n_by(&self, n: usize, key: impl Fn(&Star) -> u64) -> Vec<Star> {
+        let mut stars: Vec<(usize, &Star)> = self.stars.iter().enumerate().collect();
+        let by_key = |(a_index, a): &(usize, &Star), (b_index, b): &(usize, &Star)| {
+            key(b).cmp(&key(a)).then(a_index.cmp(b_index))
+        };
+        if n < stars.len() {
+            stars.select_nth_unstable_by(n, by_key);
+            stars.truncate(n);
+        }
+        stars.sort_unstable_by(by_key);
+        stars.into_iter().map(|(_, star)| star.clone()).collect()
+    }
+
+    /// Mirrors every star top to bottom in an image that is `height` pixels high, so `y`
+    /// counts up from the centre of the bottom row instead of down from the top one. The
+    /// stars keep their order
+    pub fn flip_vertically(&mut self, height: usize) {
+        let last_row = height.saturating_sub(1);
+        for star in &mut self.stars {
+            star.centroid.1 = last_row.saturating_sub(star.centroid.1);
+            star.weighted_centroid.1 = last_row as f64 - star.weighted_centroid.1;
+            let bounding_box = &mut star.bounding_box;
+            (bounding_box.y_min, bounding_box.y_max) = (
+                last_row.saturating_sub(bounding_box.y_max),
+                last_row.saturating_sub(bounding_box.y_min),
+            );
+            // Adding zero keeps round stars at 0 instead of -0
+            star.moments.xy = -star.moments.xy + 0.0;
+        }
+    }
+
+    /// SHA-256 of the count and of the centroid and area of every star, as lowercase hex. The
+    /// stars are sorted first, so the same stars always give the same fingerprint whatever
+    /// order they were found in, e.g. to notice when a change finds different stars
+    pub fn fingerprint(&self) -> String {
+        let mut stars: Vec<_> = self
+            .stars
+            .iter()
+            .map(|star| (star.centroid.1, star.centroid.0, star.area))
+            .collect();
+        stars.sort_unstable();
+        let mut hasher = Sha256::new();
+        hasher.update(format!("{}\n", self.count));
+        for (y, x, area) in stars {
+            hasher.update(format!("{x},{y},{area}\n"));
+        }
+        hasher
+            .finalize()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect()
+    }
+}
+
+/// Parameters controlling how groups are found and which of them count as stars
+#[derive(Clone, Debug)]
+pub struct DetectOptions {
+    pub connectivity: Connectivity,
+    /// Which edges of the image touch, so groups are connected across them. Groups are then
+    /// flood filled one at a time, like with [`DetectOptions::limit`], and
+    /// [`count_groups_tiled`] can't wrap
+    pub wrap: Wrap,
+    /// Groups with fewer pixels than this are discarded
+    pub min_size: usize,
+    /// Groups with more pixels than this are discarded
+    pub max_size: Option<usize>,
+    /// Groups with a larger [`Star::elongation`] than this are discarded
+    pub max_elongation: Option<f64>,
+    /// Groups with a larger [`Star::axis_ratio`] than this are discarded
+    pub max_axis_ratio: Option<f64>,
+    /// Discard groups that are [saturated](Star::is_saturated)
+    pub exclude_saturated: bool,
+    /// Groups with a pixel closer than this to the edge of the image are discarded, since they
+    /// are probably cut off and their centroid is biased. 0 keeps them all
+    pub exclude_border: usize,
+    /// Stop scanning once this many stars have been found, for when only whether there are
+    /// at least that many matters. The star pixels that weren't scanned are cleared from the
+    /// bitmap. [`count_groups_tiled`] always scans the whole image
+    pub limit: Option<u64>,
+    /// Check that every star pixel was visited after the scan. This always happens in debug
+    /// builds, so tests are verified regardless of this setting
+    pub verify: bool,
+}
+
+impl DetectOptions {
+    /// Whether the star passes every filter
+    pub fn accepts(&self, star: &Star) -> bool {
+        star.area >= self.min_size
+            && self.max_size.is_none_or(|max| star.area <= max)
+            && self
+                .max_elongation
+                .is_none_or(|max| star.elongation() <= max)
+            && self
+                .max_axis_ratio
+                .is_none_or(|max| star.axis_ratio() <= max)
+            && !(self.exclude_saturated && star.is_saturated())
+    }
+
+    /// Whether the star is within [`DetectOptions::exclude_border`] of the edge of a `width` by
+    /// `height` image. Edges that [wrap](DetectOptions::wrap) aren't a border
+    pub fn touches_border(&self, star: &Star, width: usize, height: usize) -> bool {
+        let border = self.exclude_border;
+        let bounding_box = star.bounding_box;
+        border > 0
+            && ((!self.wrap.horizontal()
+                && (bounding_box.x_min < border || bounding_box.x_max + border >= width))
+                || (!self.wrap.vertical()
+                    && (bounding_box.y_min < border || bounding_box.y_max + border >= height)))
+    }
+}
+
+impl Default for DetectOptions {
+    fn default() -> Self {
+        DetectOptions {
+            connectivity: Connectivity::Eight,
+            wrap: Wrap::None,
+            min_size: 1,
+            max_size: None,
+            max_elongation: None,
+            max_axis_ratio: None,
+            exclude_saturated: false,
+            exclude_border: 0,
+            limit: None,
+            verify: false,
+        }
+    }
+}
+
+/// Log target of the first pixel of every group, as the scan finds them. These are logged at
+/// trace level, so they usually need to be enabled separately
+pub const SEED_TARGET: &str = "star_counter::seeds";
+
+/// Decodes an image in any supported format and counts its stars with the default
+/// [`DetectOptions`]
+///
+/// This doesn't touch the filesystem, so it also works on targets without one, like
+/// `wasm32-unknown-unknown`.
+pub fn count_from_bytes(data: &[u8], sensitivity: u8) -> Result<StarStats, StarCounterError> {
+    let img = image::load_from_memory(data)?;
+    let luma = to_luma(&img, Channel::Gray);
+    let detections = StarField::from_image(&luma)
+        .sensitivity(sensitivity)
+        .detect();
+    Ok(detections.into_stats())
+}
+
+/// Thresholds the image at `sensitivity` and counts its stars with the default
+/// [`DetectOptions`], the shortest way from an image to a count. [`StarField`] takes the other
+/// parameters and gives the stars themselves
+pub fn count_stars_in_image(img: &GrayImage, sensitivity: u8) -> u64 {
+    StarField::from_image(img)
+        .sensitivity(sensitivity)
+        .detect()
+        .count()
+}
+
+/// Counts the connected groups of set pixels in the bitmap, see [`label_groups`]
+///
+/// Groups that are discarded by the `options` are not counted and are erased from `stars`.
+/// When `luma` is given, the brightness of each star is measured from it, so it should be the
+/// image `stars` was thresholded from.
+pub fn count_groups(
+    stars: &mut Bitmap,
+    luma: Option<&GrayImage>,
+    options: &DetectOptions,
+) -> StarStats {
+    count_groups_with_progress(stars, luma, options, || {})
+}
+
+/// Same as [`count_groups`], calling `on_row` after each row has been scanned
+pub fn count_groups_with_progress(
+    stars: &mut Bitmap,
+    luma: Option<&GrayImage>,
+    options: &DetectOptions,
+    on_row: impl FnMut(),
+) -> StarStats {
+    collect_groups(stars, luma, options, None, None, on_row).expect("There is no maximum")
+}
+
+/// Returned instead of the stars when the bitmap has more groups than the maximum
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TooManyGroups {
+    /// Groups that were found before giving up, more than `max`
+    pub found: usize,
+    pub max: usize,
+}
+
+impl fmt::Display for TooManyGroups {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "found {} groups of star pixels, more than the maximum of {}",
+            self.found, self.max
+        )
+    }
+}
+
+impl std::error::Error for TooManyGroups {}
+
+/// Returned instead of the stars when the deadline passed before the whole bitmap was scanned
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PastDeadline {
+    /// Rows that were scanned before giving up
+    pub scanned: usize,
+    pub height: usize,
+}
+
+impl fmt::Display for PastDeadline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "ran past the deadline after scanning {} of {} rows",
+            self.scanned, self.height
+        )
+    }
+}
+
+impl std::error::Error for PastDeadline {}
+
+/// Why [`try_count_groups_until`] gave up
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CountError {
+    TooManyGroups(TooManyGroups),
+    PastDeadline(PastDeadline),
+}
+
+impl fmt::Display for CountError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CountError::TooManyGroups(err) => err.fmt(f),
+            CountError::PastDeadline(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for CountError {}
+
+/// Same as [`count_groups_with_progress`], but gives up before measuring any star when there
+/// are more than `max_groups` groups, counting the ones that the `options` would discard
+///
+/// Thresholding noise can leave millions of single pixel groups, whose measurements would take
+/// far more memory than the bitmap itself. With [`DetectOptions::limit`] the groups are counted
+/// as they are found and the scan stops at whichever of the two is reached first.
+pub fn try_count_groups_with_progress(
+    stars: &mut Bitmap,
+    luma: Option<&GrayImage>,
+    options: &DetectOptions,
+    max_groups: usize,
+    on_row: impl FnMut(),
+) -> Result<StarStats, TooManyGroups> {
+    collect_groups(stars, luma, options, Some(max_groups), None, on_row).map_err(|err| match err {
+        CountError::TooManyGroups(err) => err,
+        CountError::PastDeadline(_) => unreachable!("There is no deadline"),
+    })
+}
+
+/// Same as [`try_count_groups_with_progress`] with an optional maximum, also giving up once
+/// `deadline` has passed, e.g. so a server can't be kept busy by a pathological image
+///
+/// The time is checked after every row, so the scan stops within a row of the deadline.
+pub fn try_count_groups_until(
+    stars: &mut Bitmap,
+    luma: Option<&GrayImage>,
+    options: &DetectOptions,
+    max_groups: Option<usize>,
+    deadline: Option<Instant>,
+    on_row: impl FnMut(),
+) -> Result<StarStats, CountError> {
+    collect_groups(stars, luma, options, max_groups, deadline, on_row)
+}
+
+/// Fails when the deadline has passed, after `scanned` rows
+fn check_deadline(
+    deadline: Option<Instant>,
+    scanned: usize,
+    height: usize,
+) -> Result<(), CountError> {
+    match deadline {
+        Some(deadline) if Instant::now() >= deadline => {
+            Err(CountError::PastDeadline(PastDeadline { scanned, height }))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn collect_groups(
+    stars: &mut Bitmap,
+    luma: Option<&GrayImage>,
+    options: &DetectOptions,
+    max_groups: Option<usize>,
+    deadline: Option<Instant>,
+    mut on_row: impl FnMut(),
+) -> Result<StarStats, CountError> {
+    if options.limit.is_some() || options.wrap != Wrap::None {
+        let limit = options.limit.unwrap_or(u64::MAX);
+        return count_groups_up_to(stars, luma, options, limit, max_groups, deadline, on_row);
+    }
+    let width = stars.width();
+    // Reading the final number through the table saves rewriting every label
+    let (labels, numbers, count) = provisional_labels_parallel(stars, options.connectivity);
+    if let Some(max) = max_groups.filter(|&max| count > max) {
+        return Err(CountError::TooManyGroups(TooManyGroups {
+            found: count,
+            max,
+        }));
+    }
+    // Labelling is the first of the two passes
+    check_deadline(deadline, 0, stars.height())?;
+    let mut groups: Vec<Moments> = Vec::with_capacity(count);
+
+    for (y, row) in labels.chunks_exact(width.max(1)).enumerate() {
+        let luma = luma.map(|luma| &luma.as_raw()[y * width..(y + 1) * width]);
+        for (x, &label) in row.iter().enumerate() {
+            if label == 0 {
+                continue;
+            }
+            let group = numbers[label as usize] as usize;
+            if group > groups.len() {
+                trace!(target: SEED_TARGET, "Group found at {x} {y}");
+                groups.push(Moments::new(x, y));
+            }
+            groups[group - 1].add(x, y, luma.map(|luma| luma[x]));
+        }
+        on_row();
+        check_deadline(deadline, y + 1, stars.height())?;
+    }
+    if options.verify || cfg!(debug_assertions) {
+        let labelled = labels.iter().map(|&label| label != 0);
+        assert!(
+            stars.as_slice().iter().copied().eq(labelled),
+            "Haven't labelled all the stars!"
+        );
+    }
+
+    let mut stats = StarStats::default();
+    let mut discarded = vec![false; groups.len()];
+    for (group, moments) in groups.into_iter().enumerate() {
+        let star = moments.into_star();
+        if !options.accepts(&star) || options.touches_border(&star, width, stars.height()) {
+            debug!(
+                "Discarded group of {} pixels at {:?}",
+                star.area, star.centroid
+            );
+            discarded[group] = true;
+            continue;
+        }
+        debug!("Star of {} pixels at {:?}", star.area, star.centroid);
+        stats.count += 1;
+        stats.stars.push(star);
+    }
+    if discarded.contains(&true) {
+        for (index, &label) in labels.iter().enumerate() {
+            if label != 0 && discarded[numbers[label as usize] as usize - 1] {
+                stars.set(index % width, index / width, false);
+            }
+        }
+    }
+    stats
+        .stars
+        .sort_by_key(|star| (star.centroid.1, star.centroid.0));
+    Ok(stats)
+}
+
+/// Flood fills the groups one at a time until `limit` of them are stars, since the labels of
+/// the two-pass algorithm are only final once the whole bitmap is scanned
+///
+/// The coverage check is skipped because the pixels after the last star are never visited.
+/// They are cleared instead, so like the discarded groups they don't show in the bitmap.
+fn count_groups_up_to(
+    stars: &mut Bitmap,
+    luma: Option<&GrayImage>,
+    options: &DetectOptions,
+    limit: u64,
+    max_groups: Option<usize>,
+    deadline: Option<Instant>,
+    mut on_row: impl FnMut(),
+) -> Result<StarStats, CountError> {
+    let (width, height) = (stars.width(), stars.height());
+    let mut visited = Bitmap::new(width, height);
+    let mut stats = StarStats::default();
+    let mut found = 0;
+    'rows: for y in 0..height {
+        for x in 0..width {
+            if stats.count >= limit {
+                break 'rows;
+            }
+            if !stars.get(x, y) || visited.get(x, y) {
+                continue;
+            }
+            trace!(target: SEED_TARGET, "Group found at {x} {y}");
+            found += 1;
+            if let Some(max) = max_groups.filter(|&max| found > max) {
+                return Err(CountError::TooManyGroups(TooManyGroups { found, max }));
+            }
+            let group = mark_group_wrapping(
+                (x, y),
+                stars,
+                &mut visited,
+                options.connectivity,
+                options.wrap,
+            );
+            // A group across a seam is measured in one piece, as if the image continued past
+            // its right or bottom edge
+            let start_x = seam_start(
+                group.iter().map(|&(x, _)| x),
+                width,
+                options.wrap.horizontal(),
+            );
+            let start_y = seam_start(
+                group.iter().map(|&(_, y)| y),
+                height,
+                options.wrap.vertical(),
+            );
+            let unwrap =
+                |at: usize, start: usize, size: usize| at + if at < start { size } else { 0 };
+            let mut moments = Moments::new(unwrap(x, start_x, width), unwrap(y, start_y, height));
+            for &(x, y) in &group {
+                moments.add(
+                    unwrap(x, start_x, width),
+                    unwrap(y, start_y, height),
+                    luma.map(|luma| luma.get_pixel(x as u32, y as u32).0[0]),
+                );
+            }
+            let mut star = moments.into_star();
+            star.wrap_centroid(width, height);
+            if !options.accepts(&star) || options.touches_border(&star, width, height) {
+                debug!(
+                    "Discarded group of {} pixels at {:?}",
+                    star.area, star.centroid
+                );
+                for (x, y) in group {
+                    stars.set(x, y, false);
+                }
+                continue;
+            }
+            debug!("Star of {} pixels at {:?}", star.area, star.centroid);
+            stats.count += 1;
+            stats.stars.push(star);
+        }
+        on_row();
+        check_deadline(deadline, y + 1, height)?;
+    }
+    if stats.count >= limit {
+        stars.intersect(&visited);
+    }
+    stats
+        .stars
+        .sort_by_key(|star| (star.centroid.1, star.centroid.0));
+    Ok(stats)
+}
+
+/// First row or column of a group at the positions `at` along an axis of `size` that wraps
+/// around, just past the widest gap in the group, so counting on from there keeps the group in
+/// one piece. 0 when the group doesn't reach across the seam
+fn seam_start(at: impl Iterator<Item = usize>, size: usize, wraps: bool) -> usize {
+    if !wraps {
+        return 0;
+    }
+    let mut used = vec![false; size];
+    for at in at {
+        used[at] = true;
+    }
+    if !used[0] || !used[size - 1] {
+        return 0;
+    }
+    // Length of the widest gap and the position right after it
+    let (mut widest, mut start, mut gap) = (0, 0, 0);
+    for (at, &used) in used.iter().enumerate() {
+        gap = if used { 0 } else { gap + 1 };
+        if gap > widest {
+            (widest, start) = (gap, at + 1);
+        }
+    }
+    start
+}
+
+/// Flood fills the groups of the bitmap one at a time in the order of a row by row scan,
+/// calling `on_snapshot` with the pixels visited so far after every `every` groups and once
+/// more at the end, e.g. to render an animation of how the scan finds the stars
+///
+/// This is a replay for looking at the scan, it measures nothing. It returns the number of
+/// groups.
+pub fn trace_groups(
+    stars: &Bitmap,
+    connectivity: Connectivity,
+    every: usize,
+    mut on_snapshot: impl FnMut(&Bitmap),
+) -> usize {
+    let every = every.max(1);
+    let mut visited = Bitmap::new(stars.width(), stars.height());
+    let mut found = 0;
+    for y in 0..stars.height() {
+        for x in 0..stars.width() {
+            if !stars.get(x, y) || visited.get(x, y) {
+                continue;
+            }
+            mark_group((x, y), stars, &mut visited, connectivity);
+            found += 1;
+            if found % every == 0 {
+                on_snapshot(&visited);
+            }
+        }
+    }
+    if found % every != 0 || found == 0 {
+        on_snapshot(&visited);
+    }
+    found
+}
+
+/// Marks every star pixel connected to `start` as visited, returning their `(x, y)` coordinates
+///
+/// Fills whole horizontal runs of pixels at a time, so only the start of each run in the rows
+/// above and below needs to be remembered.
+pub fn mark_group(
+    start: (usize, usize),
+    stars: &Bitmap,
+    visited: &mut Bitmap,
+    connectivity: Connectivity,
+) -> Vec<(usize, usize)> {
+    mark_group_wrapping(start, stars, visited, connectivity, Wrap::None)
+}
+
+/// Same as [`mark_group`], also connecting the pixels across the edges that `wrap`
+pub fn mark_group_wrapping(
+    start: (usize, usize),
+    stars: &Bitmap,
+    visited: &mut Bitmap,
+    connectivity: Connectivity,
+    wrap: Wrap,
+) -> Vec<(usize, usize)> {
+    let (width, height) = (stars.width(), stars.height());
+    let unvisited = |visited: &Bitmap, x, y| stars.get(x, y) && !visited.get(x, y);
+    // The position `offset` pixels from `at` along an axis of `size`, going round to the other
+    // side when it wraps and `None` past the edge otherwise
+    let step = |at: usize, offset: isize, size: usize, wraps: bool| {
+        let to = at as isize + offset;
+        if wraps {
+            Some(to.rem_euclid(size as isize) as usize)
+        } else {
+            usize::try_from(to).ok().filter(|&to| to < size)
+        }
+    };
+    let column = |x, offset| step(x, offset, width, wrap.horizontal());
+    // How far past the ends of a run a pixel in a nearby row can be and still touch it, which
+    // is also how many rows away it can be
+    let reach = match connectivity {
+        Connectivity::Four => 0,
+        Connectivity::Eight => 1,
+        Connectivity::Radius(radius) => radius,
+    };
+    let rows = reach.max(1);
+
+    let mut group = Vec::new();
+    let mut seeds = vec![start];
+    while let Some((x, y)) = seeds.pop() {
+        if !unvisited(visited, x, y) {
+            continue;
+        }
+        // Offsets of the ends of the run from `x`, which can't be longer than the row
+        let covers_row = |left: isize, right: isize| (right - left + 1) as usize >= width;
+        let mut left = 0;
+        while !covers_row(left, 0)
+            && column(x, left - 1).is_some_and(|new_x| unvisited(visited, new_x, y))
+        {
+            left -= 1;
+        }
+        let mut right = 0;
+        while !covers_row(left, right)
+            && column(x, right + 1).is_some_and(|new_x| unvisited(visited, new_x, y))
+        {
+            right += 1;
+        }
+        for offset in left..=right {
+            let x = column(x, offset).expect("The run is in the image");
+            visited.set(x, y, true);
+            group.push((x, y));
+        }
+
+        let reach = reach as isize;
+        // Where the run reaches on nearby rows, at most once around a wrapping row
+        let from = left - reach;
+        let mut to = right + reach;
+        if wrap.horizontal() {
+            to = to.min(from + width as isize - 1);
+        }
+        // Beyond a radius of 1 the run's own row can have pixels within reach past a gap
+        let own_row = reach > 1;
+        let rows = rows as isize;
+        for dy in -rows..=rows {
+            let Some(new_y) = step(y, dy, height, wrap.vertical()) else {
+                continue;
+            };
+            if dy == 0 && !own_row {
+                continue;
+            }
+            let mut in_run = false;
+            for offset in from..=to {
+                let Some(new_x) = column(x, offset) else {
+                    in_run = false;
+                    continue;
+                };
+                let star = unvisited(visited, new_x, new_y);
+                if star && !in_run {
+                    seeds.push((new_x, new_y));
+                }
+                in_run = star;
+            }
+        }
+    }
+    group
+}
+
+/// Keeps the groups of `weak` pixels that contain at least one `strong` pixel, like the
+/// hysteresis of an edge detector, so a faint group is only a star when part of it is bright
+///
+/// Every strong pixel seeds a flood fill over the weak ones, so strong pixels that aren't weak
+/// as well are ignored. Both bitmaps must have the same size.
+pub fn hysteresis(weak: &Bitmap, strong: &Bitmap, connectivity: Connectivity) -> Bitmap {
+    let (width, height) = (weak.width(), weak.height());
+    assert_eq!((strong.width(), strong.height()), (width, height));
+    let mut kept = Bitmap::new(width, height);
+    for y in 0..height {
+        for x in 0..width {
+            if strong.get(x, y) && !kept.get(x, y) {
+                mark_group((x, y), weak, &mut kept, connectivity);
+            }
+        }
+    }
+    kept
+}
+
+/// Renders the bitmap as a black image with the stars in white
+///
+/// The image always has the size of the bitmap, also when it has no rows or columns.
+pub fn convert_to_image(stars: &Bitmap) -> ImageBuffer<Luma<u8>, Vec<u8>> {
+    let (width, height) = (stars.width(), stars.height());
+    let mut luma = GrayImage::new(width as u32, height as u32);
+    if luma.is_empty() {
+        return luma;
+    }
+
+    luma.par_chunks_mut(width)
+        .zip(stars.as_slice().par_chunks(width))
+        .for_each(|(pixels, row)| {
+            for (pixel, &star) in pixels.iter_mut().zip(row) {
+                if star {
+                    *pixel = 255;
+                }
+            }
+        });
+
+    luma
+}
@@ -1,134 +1,4577 @@
-use clap::Parser;
-use image::{io::Reader, GrayImage, ImageBuffer, Luma};
+use std::{
+    borrow::Cow,
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    env,
+    ffi::OsString,
+    fmt,
+    fs::{self, File},
+    io::{self, BufRead, BufReader, BufWriter, Cursor, IsTerminal, Read, Seek, Write},
+    iter, mem,
+    path::{Path, PathBuf},
+    process::{self, ExitCode},
+    str::FromStr,
+    sync::atomic::{self, AtomicBool},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use clap::{parser::ValueSource, ArgAction, ArgMatches, CommandFactory, Parser, ValueEnum};
+use image::{
+    codecs::{gif::GifDecoder, jpeg::JpegEncoder},
+    imageops,
+    io::Reader,
+    AnimationDecoder, DynamicImage, GrayImage, ImageBuffer, ImageFormat, Luma, Pixel, Primitive,
+    Rgb, Rgba,
+};
+use indicatif::{ProgressBar, ProgressStyle};
+use log::{debug, info, warn, LevelFilter};
+#[cfg(feature = "raw")]
+use rawloader::RawImageData;
+use rayon::prelude::*;
+use serde::Serialize;
+use star_counter::{
+    adaptive_threshold, alpha_channel, annotate, assess_exposure, assess_groups, colorize,
+    convert_to_image, count_components, count_groups_tiled, count_groups_with_progress,
+    density_map, estimate_background, histogram, hysteresis, is_16_bit, label_groups,
+    luma16_from_float, mark_centroids, mark_group_wrapping, match_stars, median_fwhm,
+    merge_close_stars, morphology, normalize, otsu_threshold, otsu_threshold_from_histogram,
+    overlay, percentile_threshold, read_with_retries, render_label_colors, render_labels,
+    split_peaks, stretch, subtract_background, subtract_dark, threshold_image,
+    threshold_image_with_progress, thumbnail, to_luma, to_luma16, to_luma16_weighted,
+    to_luma16_with_method, to_luma_weighted, to_luma_with_method, trace_groups,
+    try_count_groups_until, Bitmap, BoundingBox, Channel, Connectivity, CountError, DetectOptions,
+    Fwhm, GrayMethod, GrayWeights, Roi, Star, StarMatches, StarStats, Stretch, Threshold, Wrap,
+    SEED_TARGET,
+};
+use tiff::decoder::{Decoder, DecodingResult};
 
 #[derive(Parser, Debug)]
 struct Args {
-    /// File that is an image that should be processed
-    #[arg(short, long)]
-    pub file: String,
+    /// Image that should be processed, or a directory of images. Can be repeated, `-` reads stdin
+    #[arg(short, long, required_unless_present = "file_list")]
+    pub file: Vec<String>,
+
+    /// Also process the images listed in this file, one path per line. Blank lines and lines
+    /// starting with `#` are skipped, and relative paths are relative to the list
+    #[arg(long, value_name = "FILE")]
+    pub file_list: Option<PathBuf>,
+
+    /// Read defaults for any of these options from a TOML file, with keys named like the
+    /// options, e.g. `min-size = 4` or `denoise = 1.5`. Options on the command line override it
+    #[arg(long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
 
     /// White sensitivity in range from 0 (black) to 255 (white)
-    #[arg(short, long, default_value_t = 20)]
-    pub sensitivity: u8,
+    /// Can also be given as a fraction like 0.08 or a percentage like 8%, which keeps its
+    /// precision on 16-bit images
+    #[arg(
+        short,
+        long,
+        visible_alias = "min-sensitivity",
+        default_value_t = Sensitivity::Level(20),
+        value_parser = parse_sensitivity
+    )]
+    pub sensitivity: Sensitivity,
+
+    /// Pixels brighter than this aren't counted either, so only stars in the band above
+    /// --sensitivity are found. Given like --sensitivity, can't be combined with --invert
+    #[arg(long, value_parser = parse_sensitivity, conflicts_with = "invert")]
+    pub max_sensitivity: Option<Sensitivity>,
+
+    /// Pick the sensitivity for every image using Otsu's method, ignoring --sensitivity
+    #[arg(long)]
+    pub auto_threshold: bool,
+
+    /// Compare every pixel with the mean of the WINDOW by WINDOW pixels around it instead of
+    /// a fixed level, for skies that are brighter on one side. --sensitivity is then how much
+    /// brighter than that mean a star needs to be
+    #[arg(long, value_name = "WINDOW", conflicts_with = "auto_threshold")]
+    pub adaptive: Option<usize>,
+
+    /// Pick the sensitivity for every image so that its brightest P percent of pixels are
+    /// stars, ignoring --sensitivity. Follows the exposure like --auto-threshold but more
+    /// predictably for sparse fields
+    #[arg(
+        long,
+        value_name = "P",
+        value_parser = parse_percentile,
+        conflicts_with_all = ["auto_threshold", "adaptive", "invert"]
+    )]
+    pub percentile: Option<f64>,
+
+    /// Threshold with two levels instead of --sensitivity: groups of pixels above LOW are only
+    /// stars when one of their pixels is above HIGH. Noise near a single level flickers in and
+    /// out between similar frames, while stars rarely have no bright pixel at all. Given like
+    /// --sensitivity
+    #[arg(
+        long,
+        num_args = 2,
+        value_names = ["HIGH", "LOW"],
+        value_parser = parse_sensitivity,
+        conflicts_with_all = [
+            "sensitivity", "auto_threshold", "adaptive", "percentile", "invert", "tile_height",
+            "load_grid",
+        ]
+    )]
+    pub hysteresis: Option<Vec<Sensitivity>>,
+
+    /// Look for dark stars on a bright background, counting pixels below the sensitivity
+    #[arg(long)]
+    pub invert: bool,
+
+    /// Print the luma histogram and the suggested sensitivity instead of counting
+    #[arg(long)]
+    pub histogram: bool,
+
+    /// Print the median and noise of the sky instead of counting, along with the sensitivity
+    /// K times the noise above the median as a starting point for --sensitivity
+    #[arg(
+        long,
+        num_args = 0..=1,
+        default_missing_value = "3",
+        value_name = "K",
+        conflicts_with_all = [
+            "histogram", "sweep", "solve_for_count", "load_grid", "compare", "time_series",
+            "hot_pixel_scan", "count_only",
+        ]
+    )]
+    pub estimate_background: Option<f64>,
+
+    /// Count at every sensitivity from START to END in steps of STEP and print a table of the
+    /// counts instead of the usual report, to find where the count levels off. Every image is
+    /// decoded once and only thresholded again for each sensitivity
+    #[arg(
+        long,
+        num_args = 3,
+        value_names = ["START", "END", "STEP"],
+        conflicts_with_all = [
+            "sensitivity", "max_sensitivity", "auto_threshold", "percentile", "hysteresis",
+            "histogram", "tile_height", "load_grid", "cache_grid", "threshold_map",
+            "output_image", "annotate", "label_output", "thumbnails", "density_map", "csv",
+            "mark_centroids", "catalog", "regions", "compare", "time_series", "hot_pixel_scan",
+            "count_only",
+        ]
+    )]
+    pub sweep: Option<Vec<u8>>,
+
+    /// Find the sensitivity whose count is closest to this many stars and print it instead of
+    /// the usual report, e.g. to calibrate a new camera on a frame with a known number of stars.
+    /// The count only drops as the sensitivity rises, so the image is decoded once and
+    /// thresholded again at each step of a binary search
+    #[arg(
+        long,
+        value_name = "N",
+        conflicts_with_all = [
+            "sensitivity", "max_sensitivity", "auto_threshold", "percentile", "hysteresis",
+            "histogram", "sweep", "tile_height", "load_grid", "cache_grid", "threshold_map",
+            "output_image", "annotate", "label_output", "thumbnails", "density_map", "csv",
+            "mark_centroids", "catalog", "regions", "compare", "time_series", "hot_pixel_scan",
+            "count_only",
+        ]
+    )]
+    pub solve_for_count: Option<u64>,
 
     /// Optional name for the file that is output. Requires extension.
     #[arg(long)]
     pub output_name: Option<String>,
 
+    /// Directory to write the derived -starred and -annotated images into instead of next to
+    /// the inputs. It is created when it doesn't exist
+    #[arg(long, value_name = "DIR")]
+    pub output_dir: Option<PathBuf>,
+
+    /// Format of the derived -starred, -annotated and -centroids images instead of that of the
+    /// input. pgm and ppm are written without an image codec, for pipelines that can't read
+    /// anything else. --output-name keeps the format of its extension
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    pub output_format: Option<OutputFormat>,
+
     /// Whether to output a processed image that is high contrast
-    /// It is in format of the <current_file_name>-starred.jpg
+    /// It is in format of the <current_file_name>-starred.<current_extension>
     #[arg(short, long)]
     pub output_image: bool,
+
+    /// Write the sensitivity, connectivity, minimum size and star count into the metadata of the
+    /// high contrast image, so they travel with it. Works for png and jpeg
+    #[arg(long, requires = "output_image")]
+    pub embed_params: bool,
+
+    /// Quality of the high contrast image when it is a jpeg, from 1 to 100
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = DEFAULT_JPEG_QUALITY,
+        value_parser = clap::builder::RangedU64ValueParser::<u8>::new().range(1..=100),
+        requires = "output_image"
+    )]
+    pub output_quality: u8,
+
+    /// How hard to compress the high contrast image when it is a png
+    #[arg(
+        long,
+        value_enum,
+        value_name = "LEVEL",
+        default_value_t = PngCompression::Fast,
+        requires = "output_image"
+    )]
+    pub png_compression: PngCompression,
+
+    /// Color of the stars in the high contrast image, as `#rrggbb` or `#rrggbbaa`
+    #[arg(long, value_parser = parse_color, requires = "output_image")]
+    pub star_color: Option<Rgba<u8>>,
+
+    /// Color of the background in the high contrast image, as `#rrggbb` or `#rrggbbaa`.
+    /// A transparent background needs an output format with alpha, like png
+    #[arg(long, value_parser = parse_color, requires = "output_image")]
+    pub bg_color: Option<Rgba<u8>>,
+
+    /// Blend the stars over the original image at this opacity from 0 to 1 instead, in
+    /// --star-color or red, so the frame stays visible around them
+    #[arg(
+        long,
+        value_name = "ALPHA",
+        value_parser = parse_opacity,
+        requires = "output_image",
+        conflicts_with_all = ["bg_color", "load_grid"]
+    )]
+    pub overlay_opacity: Option<f64>,
+
+    /// Ignore the parts of the image that are black in this mask image, which needs to have
+    /// the same size as the image
+    #[arg(long, value_name = "FILE")]
+    pub mask: Option<PathBuf>,
+
+    /// Stretch the mask to the size of the image instead of failing when they differ
+    #[arg(long, requires = "mask")]
+    pub resize_mask: bool,
+
+    /// Known bad pixels of the camera, as `x,y` lines in pixels of the full image, which are
+    /// never part of a star. Blank lines and lines starting with `#` are skipped. Unlike
+    /// --hot-pixel-scan, which finds such pixels, this uses a list that is already known
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["tile_height", "load_grid"])]
+    pub bad_pixels: Option<PathBuf>,
+
+    /// Only search inside a circle of this radius in pixels, e.g. the field of view of a
+    /// telescope with dark corners. Without a radius it is the largest circle that fits in
+    /// the image
+    #[arg(long, value_name = "RADIUS", num_args = 0..=1, default_missing_value = "inscribed")]
+    pub circular_mask: Option<Radius>,
+
+    /// Centre of --circular-mask in pixels of the full image, instead of the centre of the
+    /// image
+    #[arg(long, num_args = 2, value_names = ["X", "Y"], requires = "circular_mask")]
+    pub mask_center: Option<Vec<f64>>,
+
+    /// Also leave out this many pixels around the centre of --circular-mask, making it a ring,
+    /// e.g. for the shadow of a secondary mirror
+    #[arg(long, value_name = "RADIUS", requires = "circular_mask")]
+    pub inner_radius: Option<f64>,
+
+    /// Pixels of images with an alpha channel that are more transparent than this are never
+    /// stars, however bright they are. The default only ignores fully transparent pixels
+    ///
+    /// Like --mask, this doesn't apply to images processed with --tile-height.
+    #[arg(
+        long,
+        value_name = "ALPHA",
+        default_value_t = 1,
+        value_parser = clap::builder::RangedU64ValueParser::<u8>::new().range(1..=255)
+    )]
+    pub alpha_cutoff: u8,
+
+    /// Write an image where the pixels of every star have its number from the CSV as value.
+    /// It is 16-bit with over 255 stars and RGB with over 65535, so png is a good format
+    #[arg(long, value_name = "FILE")]
+    pub label_output: Option<PathBuf>,
+
+    /// Colour every star of the label image differently instead of storing its number, to
+    /// look at how the stars were separated. The same seed always gives the same colours
+    #[arg(long, value_name = "SEED", requires = "label_output")]
+    pub color_seed: Option<u64>,
+
+    /// Save a crop of the image around every star to this directory, named after the image
+    /// file and the id of the star in the CSV
+    #[arg(long, value_name = "DIR")]
+    pub thumbnails: Option<PathBuf>,
+
+    /// Write the image shaded as a heatmap of how many stars are in each cell of a grid, to
+    /// show clustering or stars lost to vignetting
+    #[arg(long, value_name = "FILE")]
+    pub density_map: Option<PathBuf>,
+
+    /// Count the stars in each cell of a grid of this many columns and rows over the image, to
+    /// spot corners with fewer stars from vignetting or bad focus
+    #[arg(
+        long,
+        num_args = 2,
+        value_names = ["COLS", "ROWS"],
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    pub grid: Option<Vec<usize>>,
+
+    /// Merge stars whose centroids are closer than this many pixels into one, e.g. a bright
+    /// star and a faint companion next to it. Only the measured stars are merged, not their
+    /// pixels
+    #[arg(long, value_name = "PIXELS", conflicts_with = "label_output")]
+    pub merge_radius: Option<f64>,
+
+    /// Also print the median full width at half maximum of the round unsaturated stars in
+    /// pixels, which is smallest when the image is in focus
+    #[arg(
+        long,
+        conflicts_with_all = ["load_grid", "count_connected_components_only"]
+    )]
+    pub fwhm: bool,
+
+    /// Also print a SHA-256 fingerprint of the centroids and areas of the stars, which only
+    /// changes when different stars are found
+    #[arg(
+        long,
+        conflicts_with_all = ["count_only", "count_connected_components_only"]
+    )]
+    pub fingerprint: bool,
+
+    /// Count a star with several brightness peaks at least this many pixels apart as one star
+    /// per peak, e.g. two stars of a crowded field that merged into one group. Its pixels go
+    /// to the nearest peak
+    #[arg(
+        long,
+        value_name = "MIN_DISTANCE",
+        conflicts_with_all = [
+            "tile_height", "load_grid", "count_connected_components_only", "limit", "max_stars",
+            "label_output",
+        ]
+    )]
+    pub split_peaks: Option<f64>,
+
+    /// Width and height of the cells of the density map, in pixels
+    #[arg(
+        long,
+        value_name = "PIXELS",
+        default_value_t = 64,
+        requires = "density_map"
+    )]
+    pub density_cell: u32,
+
+    /// Whether to output the original image with a rectangle drawn around every star
+    /// It is in format of the <current_file_name>-annotated.<current_extension>
+    #[arg(long)]
+    pub annotate: bool,
+
+    /// Whether to output the original image with a small crosshair at the centroid of every
+    /// star, which hides less of the stars than --annotate. It is in format of the
+    /// <current_file_name>-centroids.<current_extension>
+    #[arg(long)]
+    pub mark_centroids: bool,
+
+    /// Number the crosshairs of --mark-centroids like the ids of --csv
+    #[arg(long, requires = "mark_centroids")]
+    pub label_ids: bool,
+
+    /// Write the --output-image, --annotate and --mark-centroids images this many times smaller,
+    /// for a quick look, while the stars are still found and reported at full resolution. Every
+    /// star keeps at least a pixel and its rectangle
+    #[arg(
+        long,
+        value_name = "FACTOR",
+        value_parser = clap::builder::RangedU64ValueParser::<u32>::new().range(1..)
+    )]
+    pub preview_scale: Option<u32>,
+
+    /// Whether stars are connected through 4 (no diagonals) or 8 neighbours
+    #[arg(long, default_value_t = Connectivity::Eight)]
+    pub connectivity: Connectivity,
+
+    /// Connect star pixels up to this many pixels apart horizontally and vertically, to count
+    /// close stars as one cluster. A radius of 1 is the same as 8 neighbours, and large radii
+    /// get slow on dense fields
+    #[arg(
+        long,
+        value_name = "R",
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..),
+        conflicts_with = "connectivity"
+    )]
+    pub neighbour_radius: Option<usize>,
+
+    /// Which opposite edges of the image touch, for all-sky panoramas whose left and right
+    /// edges are the same part of the sky: none, horizontal or both. Stars across an edge that
+    /// wraps are counted once, and their rectangles reach past the right or bottom edge
+    #[arg(
+        long,
+        default_value_t = Wrap::None,
+        conflicts_with_all = [
+            "tile_height", "split_peaks", "count_connected_components_only", "label_output",
+        ]
+    )]
+    pub wrap: Wrap,
+
+    /// Smallest number of pixels a group needs to be counted as a star
+    #[arg(long, default_value_t = 1)]
+    pub min_size: usize,
+
+    /// Largest number of pixels a group can have to be counted as a star
+    #[arg(long)]
+    pub max_size: Option<usize>,
+
+    /// Discard groups whose bounding box is more than this many times longer than it is wide,
+    /// like cosmic rays and satellite trails
+    #[arg(long, value_name = "FACTOR")]
+    pub max_elongation: Option<f64>,
+
+    /// Discard groups whose major axis is more than this many times longer than their minor
+    /// axis, measured from the brightness of their pixels. Catches diagonal trails that
+    /// --max-elongation misses
+    #[arg(long, value_name = "FACTOR")]
+    pub max_axis_ratio: Option<f64>,
+
+    /// Discard stars that reach white. They are overexposed and may have bled into their
+    /// neighbours, so their size and brightness are unreliable
+    #[arg(long)]
+    pub exclude_saturated: bool,
+
+    /// Discard stars with a pixel closer than this to the edge of the searched image, which are
+    /// probably cut off. 1 discards the stars that touch the edge
+    #[arg(long, value_name = "PIXELS", default_value_t = 0)]
+    pub exclude_border: usize,
+
+    /// Stop counting once this many stars were found and report at least that many, for a
+    /// quick check whether the sky is clear
+    #[arg(
+        long,
+        value_name = "N",
+        value_parser = clap::builder::RangedU64ValueParser::<u64>::new().range(1..)
+    )]
+    pub limit: Option<u64>,
+
+    /// Also report how many stars are brighter in total than the star covering this pixel,
+    /// given as `x,y` of the full image
+    #[arg(
+        long,
+        value_name = "X,Y",
+        conflicts_with_all = ["tile_height", "load_grid", "count_connected_components_only"]
+    )]
+    pub brighter_than: Option<Position>,
+
+    /// Fail when the image has more than this many groups of star pixels, before measuring
+    /// any of them, instead of running out of memory on pure noise. Groups that --min-size and
+    /// the other filters would discard count too. Unlike --limit this is an error
+    #[arg(
+        long,
+        value_name = "N",
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..),
+        conflicts_with = "tile_height"
+    )]
+    pub max_stars: Option<usize>,
+
+    /// Give up on an image with an error when finding its stars takes longer than this, so a
+    /// service can't be kept busy by a pathological input. Decoding isn't interrupted, but
+    /// counting stops within a row of the limit
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        value_parser = parse_timeout,
+        conflicts_with_all = ["tile_height", "count_connected_components_only"]
+    )]
+    pub timeout_seconds: Option<f64>,
+
+    /// Only count the connected groups of star pixels, without measuring any of them, which is
+    /// the quickest way to get the number. Every group counts, so the filters on the size and
+    /// shape of stars can't be used
+    #[arg(
+        long,
+        requires = "count_only",
+        conflicts_with_all = [
+            "min_size", "max_size", "max_elongation", "max_axis_ratio", "exclude_saturated",
+            "exclude_border", "limit", "max_stars", "merge_radius", "brightest", "tile_height",
+            "output_image", "annotate", "label_output", "thumbnails", "density_map", "csv",
+            "mark_centroids", "catalog", "regions",
+        ]
+    )]
+    pub count_connected_components_only: bool,
+
+    /// Shrink the thresholded pixels by N pixels in every direction before counting, which
+    /// removes specks and thin lines and separates stars that barely touch
+    #[arg(long, value_name = "N")]
+    pub erode: Option<usize>,
+
+    /// Grow the thresholded pixels by N pixels in every direction before counting, which joins
+    /// stars closer than twice that. It runs after --erode, so the same N for both removes
+    /// specks and bridges while the stars keep their size
+    #[arg(long, value_name = "N")]
+    pub dilate: Option<usize>,
+
+    /// Merge stars separated by a gap of up to twice this many pixels before counting
+    #[arg(long, value_name = "N")]
+    pub bridge: Option<usize>,
+
+    /// Threshold and count the image in strips of this many rows instead of all at once, which
+    /// needs less memory for huge images
+    #[arg(
+        long,
+        value_name = "ROWS",
+        conflicts_with_all = [
+            "output_image", "label_output", "bridge", "erode", "dilate", "mask", "adaptive",
+            "trace_seeds", "neighbour_radius", "limit", "circular_mask",
+        ]
+    )]
+    pub tile_height: Option<usize>,
+
+    /// Print the centroid of every star as `x,y`, one per line
+    #[arg(long)]
+    pub list_centroids: bool,
+
+    /// Also print the area of every star when listing centroids, as `x,y,area`
+    #[arg(long, requires = "list_centroids")]
+    pub with_area: bool,
+
+    /// Also print the summed brightness of every star when listing centroids, after the area
+    #[arg(long, requires = "list_centroids")]
+    pub with_brightness: bool,
+
+    /// Also print the bounding box of every star when listing centroids, as
+    /// `x_min,y_min,x_max,y_max` after the other columns
+    #[arg(long, requires = "list_centroids")]
+    pub with_bbox: bool,
+
+    /// Report brightness weighted centroids with subpixel precision
+    #[arg(long)]
+    pub subpixel: bool,
+
+    /// Where reported coordinates start, which flips `y` in the listed centroids, the JSON
+    /// report, the CSV file and the catalog
+    ///
+    /// Detection and the images written are unaffected. DS9 regions always use the bottom-left
+    /// origin of DS9 image coordinates, so they are the same either way.
+    #[arg(long, value_enum, default_value_t = Origin::TopLeft)]
+    pub origin: Origin,
+
+    /// Only report the N brightest stars, brightest first, which is what plate solvers like
+    /// astrometry.net want
+    ///
+    /// This applies to the listed centroids, the JSON report, the CSV file and the catalog. The
+    /// count is still that of every star.
+    #[arg(long, value_name = "N", conflicts_with = "compare")]
+    pub brightest: Option<usize>,
+
+    /// Also list the N largest stars, largest first, with their area, centroid and rectangle, to
+    /// spot the moon, planets and merged stars. They are picked from every star, also with
+    /// --brightest
+    #[arg(
+        long,
+        value_name = "N",
+        conflicts_with_all = ["count_only", "count_connected_components_only"]
+    )]
+    pub report_largest: Option<usize>,
+
+    /// Write every star to this CSV file as `id,x,y,area,brightness,x_min,y_min,x_max,y_max`
+    #[arg(long)]
+    pub csv: Option<PathBuf>,
+
+    /// Write every star to this numpy .npy file as a float64 array of `x, y, area, brightness`
+    /// rows, for loading it with `numpy.load`
+    #[arg(long, value_name = "FILE")]
+    pub npy: Option<PathBuf>,
+
+    /// Write a whitespace delimited star catalog with subpixel centroids to this file
+    #[arg(long)]
+    pub catalog: Option<PathBuf>,
+
+    /// Write a DS9 region file with an ellipse of the same shape around every star, to overlay
+    /// the detections in DS9 or other viewers
+    ///
+    /// Centres are the brightness weighted centroids in DS9 image coordinates, which start at
+    /// 1 in the centre of the bottom-left pixel. So `x` is one more than elsewhere and `y` is
+    /// counted up from the bottom row instead of down from the top. The axes are those of an
+    /// ellipse with the same spread of pixels as the star, and the angle of the major axis is
+    /// in degrees counterclockwise from `x` as DS9 expects, between 0 and 180.
+    #[arg(long, value_name = "FILE")]
+    pub regions: Option<PathBuf>,
+
+    /// Save which pixels are stars after thresholding, --bridge and --mask to this file, so
+    /// that --load-grid can count them again while tuning the options applied after that
+    #[arg(long, value_name = "FILE", conflicts_with = "tile_height")]
+    pub cache_grid: Option<PathBuf>,
+
+    /// Save which pixels passed the threshold, --erode, --dilate, --bridge and --mask as a white
+    /// on black image, before any star is discarded by its size or shape. Unlike --output-image,
+    /// which only shows the kept stars, this shows what the filters got to choose from
+    #[arg(long, value_name = "FILE", conflicts_with = "tile_height")]
+    pub threshold_map: Option<PathBuf>,
+
+    /// Save the pixels that the flood fill has visited as a white on black image to this
+    /// directory after every --trace-every groups it found, as `trace-00001.png` and on, to
+    /// assemble an animation of the scan. This fills the groups once more just for the images,
+    /// so it is only for looking at how the scan works
+    #[arg(long, value_name = "DIR", conflicts_with = "tile_height")]
+    pub trace_image: Option<PathBuf>,
+
+    /// Number of groups found between the images of --trace-image
+    #[arg(
+        long,
+        value_name = "K",
+        default_value_t = 1,
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..),
+        requires = "trace_image"
+    )]
+    pub trace_every: usize,
+
+    /// Count the pixels saved by --cache-grid instead of decoding and thresholding the image,
+    /// which is only read for its size. The grid must match the size and sensitivity, and the
+    /// stars have no brightness since the image isn't decoded
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = [
+            "cache_grid", "annotate", "mark_centroids", "thumbnails", "density_map", "histogram",
+            "compare", "time_series", "hot_pixel_scan", "stack", "tile_height", "bridge", "erode",
+            "dilate", "mask", "circular_mask",
+        ]
+    )]
+    pub load_grid: Option<PathBuf>,
+
+    /// Only count stars inside the `x,y,width,height` rectangle. The binarized output image
+    /// covers just this region, while star coordinates stay relative to the full image
+    ///
+    /// Given as `name=x,y,width,height`, the stars whose centroids fall inside the rectangle are
+    /// counted for it instead, without limiting the search. Any number of named regions can be
+    /// given, and a star inside several of them counts for each
+    #[arg(long, value_name = "[NAME=]X,Y,WIDTH,HEIGHT")]
+    pub roi: Vec<RoiArg>,
+
+    /// Blur the image with the given sigma before thresholding to remove single pixel noise
+    #[arg(long, num_args = 0..=1, default_missing_value = "1.0", value_name = "SIGMA")]
+    pub denoise: Option<f32>,
+
+    /// Find the stars on a copy of the image blurred with this sigma, which leaves out single
+    /// pixel noise like --denoise, but measure their brightness on the image itself
+    #[arg(long, value_name = "SIGMA", conflicts_with = "denoise")]
+    pub detect_blur: Option<f32>,
+
+    /// Subtract this dark frame, taken with the same exposure and the lens covered, from every
+    /// image first, which removes hot pixels and amp glow that would be counted as stars. It
+    /// needs to have the size of the images
+    #[arg(long, value_name = "FILE")]
+    pub dark: Option<PathBuf>,
+
+    /// Subtract the mean brightness of the WINDOW by WINDOW pixels around every pixel before
+    /// thresholding, which flattens gradients like light pollution so that --sensitivity is
+    /// how far above the sky a star is. The window should be much larger than the stars
+    #[arg(long, num_args = 0..=1, default_missing_value = "64", value_name = "WINDOW")]
+    pub subtract_background: Option<usize>,
+
+    /// Rescale the brightness linearly before thresholding, so the darkest pixel becomes black
+    /// and the brightest white, which makes --sensitivity behave the same for frames with less
+    /// contrast. The darkest and brightest CLIP percent of the pixels are left out when finding
+    /// the range, so a few hot pixels don't spoil it. Runs before --stretch
+    #[arg(
+        long,
+        value_name = "CLIP",
+        num_args = 0..=1,
+        default_missing_value = "0",
+        value_parser = parse_clip
+    )]
+    pub normalize: Option<f64>,
+
+    /// Apply a tone curve to the brightness before thresholding: none, log, sqrt or asinh.
+    /// They lift faint stars above --sensitivity while bright ones stay white, log the most
+    /// and sqrt the least. Brightness is measured on the stretched image too
+    #[arg(long, value_name = "CURVE", default_value_t = Stretch::None)]
+    pub stretch: Stretch,
+
+    /// Shrink the image this many times before thresholding, for a quick count of huge
+    /// frames. Positions and sizes are scaled back to the original image, but faint stars of a
+    /// single pixel may be averaged away
+    #[arg(
+        long,
+        value_name = "FACTOR",
+        value_parser = clap::builder::RangedU64ValueParser::<u32>::new().range(1..)
+    )]
+    pub downscale: Option<u32>,
+
+    /// Brightness used for every pixel: gray, red, green, blue or max of the three
+    #[arg(long, default_value_t = Channel::Gray)]
+    pub channel: Channel,
+
+    /// Weigh the red, green and blue channels like this for the brightness instead, as `R,G,B`.
+    /// Only their ratios matter, e.g. `1,2,1` to favour green on one-shot-colour cameras
+    #[arg(long, value_name = "R,G,B", conflicts_with = "channel")]
+    pub gray_weights: Option<GrayWeights>,
+
+    /// How colour images are turned into brightness: luma, average, lightness or value. Luma
+    /// is mostly green, while value, the brightest channel, keeps red and blue stars as bright
+    #[arg(
+        long,
+        value_name = "METHOD",
+        default_value_t = GrayMethod::Luma,
+        conflicts_with_all = ["channel", "gray_weights"]
+    )]
+    pub gray_method: GrayMethod,
+
+    /// Don't rotate the image according to its EXIF orientation before processing
+    #[arg(long)]
+    pub no_autorotate: bool,
+
+    /// Read an input up to this many more times when reading it fails in a way a network share
+    /// briefly can, e.g. with a timeout or an I/O error, waiting 100 ms before the first retry
+    /// and twice as long before every next one. Missing files and images that fail to decode
+    /// aren't retried
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    pub read_retries: u32,
+
+    /// Read every page of multi-page TIFFs, counting each page on its own or combining them
+    /// into one frame with less noise first. Without it only the first page is read
+    #[arg(long, value_enum, value_name = "MODE")]
+    pub stack: Option<Stack>,
+
+    /// Show a progress bar while processing, only when printing text to a terminal
+    #[arg(long)]
+    pub progress: bool,
+
+    /// How the results are printed to stdout
+    #[arg(long, value_enum, default_value_t = Format::Text)]
+    pub format: Format,
+
+    /// Indent the JSON printed with `--format json` for reading it, instead of one line per
+    /// file
+    #[arg(long)]
+    pub pretty: bool,
+
+    /// Number of threads used for processing, 0 uses all cores
+    #[arg(long, default_value_t = 0)]
+    pub threads: usize,
+
+    /// Number of files found at once, to keep every core busy with batches of small images.
+    /// Reports and output files are still written one file at a time in order
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 1,
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    pub jobs: usize,
+
+    /// Check that every star pixel was visited after counting, always done in debug builds
+    #[arg(long)]
+    pub verify: bool,
+
+    /// Also find the stars of this image and report which of them appeared or disappeared
+    /// compared with every input image
+    #[arg(long, value_name = "FILE", conflicts_with = "histogram")]
+    pub compare: Option<String>,
+
+    /// Stars of both images whose centroids are at most this many pixels apart are the same
+    #[arg(long, default_value_t = 2.0, requires = "compare")]
+    pub match_radius: f64,
+
+    /// After processing every file, write their counts, the total, mean and standard deviation
+    /// to this JSON file. Files that failed are listed with their error
+    #[arg(long, value_name = "FILE")]
+    pub summary: Option<PathBuf>,
+
+    /// Append a `file<TAB>count<TAB>time` line to this file for every counted file, with the
+    /// time in UTC, so runs build up a log. Files that failed are left out
+    #[arg(long, value_name = "FILE")]
+    pub count_file: Option<PathBuf>,
+
+    /// Exit with code 2 when a file has fewer stars than this, e.g. because clouds rolled in
+    #[arg(long, value_name = "COUNT")]
+    pub expect_min: Option<u64>,
+
+    /// Exit with code 2 when a file has more stars than this
+    #[arg(long, value_name = "COUNT")]
+    pub expect_max: Option<u64>,
+
+    /// Count every frame of the inputs in order and write their `frame,count` series as CSV to
+    /// this file, or to stdout without a value, instead of the usual report. Every frame of an
+    /// animated GIF is counted, and a directory is read as numbered frames
+    #[arg(
+        long,
+        value_name = "FILE",
+        num_args = 0..=1,
+        default_missing_value = STDOUT_PATH,
+        conflicts_with_all = [
+            "output_image", "annotate", "label_output", "thumbnails", "density_map", "csv",
+            "mark_centroids", "catalog", "regions",
+            "histogram", "compare", "summary", "timings",
+        ]
+    )]
+    pub time_series: Option<String>,
+
+    /// Instead of the usual report, list the pixels that are part of a star in more than
+    /// --hot-pixel-fraction of all the frames of the inputs. In a calibration set these are
+    /// stuck or hot pixels of the sensor rather than stars
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "time_series", "tile_height", "downscale",
+            "output_image", "annotate", "label_output", "thumbnails", "density_map", "csv",
+            "mark_centroids", "catalog", "regions",
+            "histogram", "compare", "summary", "timings",
+        ]
+    )]
+    pub hot_pixel_scan: bool,
+
+    /// Fraction of the frames a pixel must be part of a star in to be reported as hot
+    #[arg(
+        long,
+        value_name = "FRACTION",
+        default_value_t = 0.5,
+        requires = "hot_pixel_scan"
+    )]
+    pub hot_pixel_fraction: f64,
+
+    /// Print how long each processing stage took and how many megapixels were searched per
+    /// second to stderr, and add the throughput to the JSON reports
+    #[arg(long)]
+    pub timings: bool,
+
+    /// Print where every group of star pixels starts to stderr, as the scan finds them
+    #[arg(long)]
+    pub trace_seeds: bool,
+
+    /// Only print the star count, without any diagnostics
+    #[arg(short, long, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// Print nothing but the star count as a plain number, a line for every file, so scripts
+    /// can use it as it is. Diagnostics still go to stderr
+    #[arg(
+        long,
+        conflicts_with_all = ["format", "list_centroids", "grid", "compare", "histogram"]
+    )]
+    pub count_only: bool,
+
+    /// Print diagnostics about every group and the time taken to stderr
+    #[arg(short, long)]
+    pub verbose: bool,
 }
 
-fn main() {
-    let args = Args::parse();
-    let img = Reader::open(&args.file)
-        .unwrap()
-        .decode()
-        .unwrap()
-        .grayscale()
-        .to_luma8();
-    let (width, height) = img.dimensions();
+/// Sensitivity as given on the command line, resolved against the bit depth of each image
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Sensitivity {
+    /// Level in range from 0 to 255, scaled up for 16-bit images
+    Level(u8),
+    /// Fraction of the full range from 0.0 to 1.0
+    Fraction(f64),
+}
 
-    // Parse it to an array of bools, easier to work with
-    let mut stars: Vec<Vec<bool>> = vec![vec![false; height as usize]; width as usize];
-    for y in 0..height {
-        for x in 0..width {
-            let pixel = img.get_pixel(x, y);
-            stars[x as usize][y as usize] = is_white(pixel, args.sensitivity);
+impl Sensitivity {
+    fn to_u8(self) -> u8 {
+        match self {
+            Sensitivity::Level(level) => level,
+            Sensitivity::Fraction(fraction) => (fraction * u8::MAX as f64).round() as u8,
         }
     }
 
-    let res = count_groups(&stars);
-    println!("Found {} stars", res);
-    if args.output_image {
-        println!("Processing into output...");
-        let output = convert_to_image(&stars);
-        let output_file_name = if let Some(output_name) = args.output_name {
-            OutputFileName::Custom(output_name)
+    fn to_u16(self) -> u16 {
+        match self {
+            Sensitivity::Level(level) => level as u16 * 257,
+            Sensitivity::Fraction(fraction) => (fraction * u16::MAX as f64).round() as u16,
+        }
+    }
+
+    /// The level in an image of `bit_depth` bits
+    fn in_bit_depth(self, bit_depth: u8) -> u16 {
+        if bit_depth == 16 {
+            self.to_u16()
         } else {
-            OutputFileName::FromOriginal(args.file)
-        };
-        output.save(create_output_path(output_file_name)).unwrap();
-        println!("Done!");
+            self.to_u8() as u16
+        }
+    }
+}
+
+impl fmt::Display for Sensitivity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Sensitivity::Level(level) => write!(f, "{level}"),
+            Sensitivity::Fraction(fraction) => write!(f, "{fraction}"),
+        }
+    }
+}
+
+/// Parses a color given as `#rrggbb` or `#rrggbbaa`, the `#` being optional
+fn parse_color(s: &str) -> Result<Rgba<u8>, String> {
+    let hex = s.strip_prefix('#').unwrap_or(s);
+    if !matches!(hex.len(), 6 | 8) || !hex.is_ascii() {
+        return Err(format!(
+            "color must be given as #rrggbb or #rrggbbaa, got {s}"
+        ));
     }
+    let mut channels = [u8::MAX; 4];
+    for (channel, i) in channels.iter_mut().zip((0..hex.len()).step_by(2)) {
+        *channel = u8::from_str_radix(&hex[i..i + 2], 16)
+            .map_err(|err| format!("invalid color {s}: {err}"))?;
+    }
+    Ok(Rgba(channels))
 }
 
-fn is_white(pixel: &Luma<u8>, sensitivity: u8) -> bool {
-    pixel.0[0] > sensitivity
+/// Parses a sensitivity given as 0-255, a 0.0-1.0 fraction or a 0-100 percentage
+fn parse_sensitivity(s: &str) -> Result<Sensitivity, String> {
+    let fraction = if let Some(percentage) = s.strip_suffix('%') {
+        let percentage: f64 = percentage
+            .trim()
+            .parse()
+            .map_err(|err| format!("invalid percentage {s}: {err}"))?;
+        percentage / 100.0
+    } else if s.contains('.') {
+        s.parse()
+            .map_err(|err| format!("invalid fraction {s}: {err}"))?
+    } else {
+        return s
+            .parse()
+            .map(Sensitivity::Level)
+            .map_err(|err| format!("sensitivity must be in range 0 to 255, got {s}: {err}"));
+    };
+    if !(0.0..=1.0).contains(&fraction) {
+        return Err(format!("sensitivity {s} is outside of 0% to 100%"));
+    }
+    Ok(Sensitivity::Fraction(fraction))
 }
 
-fn count_groups(stars: &Vec<Vec<bool>>) -> u64 {
-    let width = stars.len();
-    let height = stars[0].len();
-    let mut visited = vec![vec![false; height]; width];
-    let mut groups = 0;
+/// Parses a percentage of the pixels from 0 to 100
+fn parse_percentile(s: &str) -> Result<f64, String> {
+    let percent: f64 = s
+        .trim_end_matches('%')
+        .parse()
+        .map_err(|err| format!("invalid percentage {s}: {err}"))?;
+    if !(0.0..=100.0).contains(&percent) {
+        return Err(format!("percentile {s} is outside of 0 to 100"));
+    }
+    Ok(percent)
+}
 
-    for y in 0..height {
-        for x in 0..width {
-            if stars[x][y] && !visited[x][y] {
-                groups += 1;
-                // println!("Group found at {} {}", x, y);
-                mark_group((x, y), stars, &mut visited);
-            }
-        }
-    }
-    assert_eq!(stars, &visited, "Haven't visited all the stars!");
-    groups
-}
-
-fn mark_group(start: (usize, usize), stars: &Vec<Vec<bool>>, visited: &mut Vec<Vec<bool>>) {
-    let mut to_visit = vec![start];
-    visited[start.0][start.1] = true;
-    while let Some((x, y)) = to_visit.pop() {
-        for offset_x in -1..=1 {
-            let Some(new_x) = x.checked_add_signed(offset_x) else { continue };
-            for offset_y in -1..=1 {
-                let Some(new_y) = y.checked_add_signed(offset_y) else { continue };
-                if let Some(true) = stars.get(new_x).and_then(|col| col.get(new_y)) {
-                    if !visited[new_x][new_y] {
-                        visited[new_x][new_y] = true;
-                        to_visit.push((new_x, new_y));
-                    }
-                }
-            }
+/// Parses an opacity from 0 for transparent to 1 for opaque
+fn parse_opacity(s: &str) -> Result<f64, String> {
+    let opacity: f64 = s
+        .parse()
+        .map_err(|err| format!("invalid opacity {s}: {err}"))?;
+    if !(0.0..=1.0).contains(&opacity) {
+        return Err(format!("opacity {s} is outside of 0 to 1"));
+    }
+    Ok(opacity)
+}
+
+fn parse_timeout(s: &str) -> Result<f64, String> {
+    let seconds: f64 = s
+        .parse()
+        .map_err(|err| format!("invalid number of seconds {s}: {err}"))?;
+    if !seconds.is_finite() {
+        return Err(format!("{s} must be a finite number of seconds above 0"));
+    }
+    if seconds <= 0.0 {
+        return Err(format!("{s} must be above 0"));
+    }
+    Ok(seconds)
+}
+
+/// Percent of the pixels at either end that --normalize leaves out
+fn parse_clip(s: &str) -> Result<f64, String> {
+    let percent = parse_percentile(s)?;
+    if percent >= 50.0 {
+        return Err(format!(
+            "{s} would leave out every pixel, it must be below 50"
+        ));
+    }
+    Ok(percent)
+}
+
+/// Radius of --circular-mask
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Radius {
+    /// Touching the nearest edges of the image
+    Inscribed,
+    Pixels(f64),
+}
+
+impl FromStr for Radius {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "inscribed" {
+            return Ok(Radius::Inscribed);
+        }
+        match s.parse::<f64>() {
+            Ok(radius) if radius.is_finite() && radius > 0.0 => Ok(Radius::Pixels(radius)),
+            _ => Err(format!(
+                "radius must be a number of pixels above 0, got {s}"
+            )),
         }
     }
 }
 
-fn convert_to_image(stars: &Vec<Vec<bool>>) -> ImageBuffer<Luma<u8>, Vec<u8>> {
-    let width = stars.len();
-    let height = stars[0].len();
-    let mut luma = GrayImage::new(width as u32, height as u32);
+/// Region of --roi, which only counts the stars inside it when it has a name
+#[derive(Clone, Debug)]
+struct RoiArg {
+    name: Option<String>,
+    roi: Roi,
+}
 
-    for y in 0..height {
-        for x in 0..width {
-            if stars[x][y] {
-                let pixel = luma.get_pixel_mut(x as u32, y as u32);
-                pixel.0[0] = 255;
+impl FromStr for RoiArg {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, roi) = match s.split_once('=') {
+            Some((name, _)) if name.trim().is_empty() => {
+                return Err(format!("ROI name is empty in {s}"));
             }
+            Some((name, roi)) => (Some(name.trim().to_string()), roi),
+            None => (None, s),
+        };
+        Ok(RoiArg {
+            name,
+            roi: roi.parse()?,
+        })
+    }
+}
+
+/// Pixel of the image
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Position {
+    x: u32,
+    y: u32,
+}
+
+impl FromStr for Position {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let coordinates = s
+            .split(',')
+            .map(|value| value.trim().parse::<u32>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| format!("invalid pixel {s}: {err}"))?;
+        let [x, y] = coordinates[..] else {
+            return Err(format!("pixel must be x,y, got {s}"));
+        };
+        Ok(Position { x, y })
+    }
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{},{}", self.x, self.y)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum Format {
+    /// Human readable summary
+    Text,
+    /// A single JSON object, with nothing else printed
+    Json,
+    /// One JSON object for every star on a line of its own, then one with the count of the
+    /// file, so they can be read as they come
+    Jsonl,
+}
+
+/// Quality the image crate saves jpegs with by default
+const DEFAULT_JPEG_QUALITY: u8 = 75;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum PngCompression {
+    /// Quickest to write but largest, which is what the image crate does by default
+    Fast,
+    /// Smaller at a few times the time
+    Balanced,
+    /// Smallest, but slowest to write
+    Best,
+}
+
+/// Format of the derived images
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    Png,
+    Jpg,
+    /// Binary netpbm graymap, which makes colour images gray
+    Pgm,
+    /// Binary netpbm pixmap
+    Ppm,
+}
+
+impl OutputFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Png => "png",
+            OutputFormat::Jpg => "jpg",
+            OutputFormat::Pgm => "pgm",
+            OutputFormat::Ppm => "ppm",
         }
     }
+}
 
-    luma
+/// The pixel that coordinates are counted from, with `x` always growing to the right
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum Origin {
+    /// `y` grows downwards like the rows of the image
+    TopLeft,
+    /// `y` grows upwards, as is usual in astronomy
+    BottomLeft,
 }
 
-enum OutputFileName {
-    FromOriginal(String),
-    Custom(String),
+/// How the pages of a multi-page TIFF are read
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum Stack {
+    /// Count every page as if it was a file of its own
+    None,
+    /// Average the pages, so faint stars stand out of the noise
+    Mean,
+    /// Add up the pages, clipping at the white of 16-bit images
+    Sum,
 }
 
-fn create_output_path(output_file_name: OutputFileName) -> String {
-    match output_file_name {
-        OutputFileName::FromOriginal(original_file_name) => {
-            original_file_name
-                .split_once(".")
-                .expect("File does not contain file extension")
-                .0
-                .to_string()
-                + "-starred.jpg"
-        }
-        OutputFileName::Custom(custom_file_name) => custom_file_name,
+/// Exit code when every file was counted but a count is outside `--expect-min` and
+/// `--expect-max`, so monitoring can tell it apart from a failure
+const UNEXPECTED_COUNT: u8 = 2;
+
+/// Exit code when Ctrl-C stopped a batch before every file was counted, the usual 128 plus
+/// the number of SIGINT
+const INTERRUPTED_EXIT: u8 = 130;
+
+/// Set by Ctrl-C while counting several files, which stops starting new ones
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Lets Ctrl-C finish the files in progress instead of leaving their outputs half written,
+/// while a second Ctrl-C still stops right away
+fn handle_interrupts() {
+    let handler = ctrlc::set_handler(|| {
+        if INTERRUPTED.swap(true, atomic::Ordering::SeqCst) {
+            process::exit(INTERRUPTED_EXIT.into());
+        }
+        eprintln!("Interrupted, finishing the files in progress. Press Ctrl-C again to stop now");
+    });
+    if let Err(err) = handler {
+        warn!("Ctrl-C will stop without finishing the files in progress: {err}");
+    }
+}
+
+fn interrupted() -> bool {
+    INTERRUPTED.load(atomic::Ordering::SeqCst)
+}
+
+fn main() -> ExitCode {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("Error: {err:#}");
+            return ExitCode::FAILURE;
+        }
+    };
+    match run(args) {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("Error: {err:#}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Parses the command line, filling in the options it doesn't give from `--config`
+fn parse_args() -> Result<Args> {
+    // Only looks for the config and which options were given here, a config can still provide
+    // required options. Any real error is reported by the full parse below
+    let matches = Args::command()
+        .ignore_errors(true)
+        .try_get_matches_from(env::args_os());
+    let Some((path, matches)) = matches
+        .ok()
+        .and_then(|matches| Some((matches.get_one::<PathBuf>("config")?.clone(), matches)))
+    else {
+        return Ok(Args::parse());
+    };
+    let defaults = config_args(&path, &matches)
+        .with_context(|| format!("Failed to read config {}", path.display()))?;
+    let mut args = env::args_os();
+    let argv = args.next().into_iter().chain(defaults).chain(args);
+    Ok(Args::parse_from(argv))
+}
+
+/// Turns the config file into command line arguments for every option that the command line
+/// doesn't override
+fn config_args(path: &Path, matches: &ArgMatches) -> Result<Vec<OsString>> {
+    let config: toml::Table = toml::from_str(&fs::read_to_string(path)?)?;
+    let command = Args::command();
+    let mut argv = Vec::new();
+    for (key, value) in config {
+        let id = key.replace('-', "_");
+        let arg = command
+            .get_arguments()
+            .find(|arg| arg.get_id() == id.as_str() && id != "config")
+            .with_context(|| format!("Unknown option `{key}`"))?;
+        // Options given on the command line override the config, also the ones they conflict with
+        let given = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+        if given(&id)
+            || command
+                .get_arg_conflicts_with(arg)
+                .iter()
+                .any(|conflict| given(conflict.get_id().as_str()))
+        {
+            continue;
+        }
+        let long = arg.get_long().expect("Every option has a long name");
+        let values = match value {
+            toml::Value::Array(values) => values,
+            value => vec![value],
+        };
+        for value in values {
+            let value = match value {
+                toml::Value::String(value) => value,
+                toml::Value::Boolean(set) if matches!(arg.get_action(), ArgAction::SetTrue) => {
+                    if set {
+                        argv.push(format!("--{long}").into());
+                    }
+                    continue;
+                }
+                toml::Value::Array(_) | toml::Value::Table(_) => {
+                    bail!("Option `{key}` must be a single value or a list of them")
+                }
+                value => value.to_string(),
+            };
+            argv.push(format!("--{long}={value}").into());
+        }
+    }
+    Ok(argv)
+}
+
+impl Args {
+    /// The unnamed --roi, which is the only region searched for stars
+    fn crop(&self) -> Option<Roi> {
+        self.roi
+            .iter()
+            .find(|roi| roi.name.is_none())
+            .map(|roi| roi.roi)
+    }
+}
+
+/// Fails on options that contradict each other and could only ever find no stars or fail
+/// every file, which clap can't tell from their values alone
+///
+/// Sensitivities are compared at 16 bits, the finest they can be resolved at. Rounding them to
+/// 8 bits can still make them equal, which is checked again for every 8-bit image.
+fn check_args(args: &Args) -> Result<()> {
+    if args.roi.iter().filter(|roi| roi.name.is_none()).count() > 1 {
+        bail!("Only one --roi can be without a name, as stars are only searched in that one");
+    }
+    let mut names = HashSet::new();
+    for name in args.roi.iter().filter_map(|roi| roi.name.as_deref()) {
+        if !names.insert(name) {
+            bail!("--roi {name} is given more than once");
+        }
+        if args.count_connected_components_only {
+            bail!(
+                "--count-connected-components-only doesn't measure the stars, so it can't count \
+                 them in --roi {name}"
+            );
+        }
+    }
+    let fixed_sensitivity = !args.auto_threshold && args.percentile.is_none();
+    if let Some(max) = args.max_sensitivity {
+        match args.hysteresis.as_deref() {
+            Some(&[high, _]) if high.to_u16() >= max.to_u16() => bail!(
+                "The high level of --hysteresis {high} must be below --max-sensitivity {max}, \
+                 or no pixel can start a star"
+            ),
+            Some(_) => {}
+            None if fixed_sensitivity && args.sensitivity.to_u16() >= max.to_u16() => bail!(
+                "--max-sensitivity {max} must be above the sensitivity {}",
+                args.sensitivity
+            ),
+            None => {}
+        }
+    }
+    if let Some(&[high, low]) = args.hysteresis.as_deref() {
+        if high.to_u16() <= low.to_u16() {
+            bail!("The high level of --hysteresis {high} must be above the low one {low}");
+        }
+    }
+    if let Some(&[start, end, step]) = args.sweep.as_deref() {
+        if step == 0 {
+            bail!("The step of --sweep must be above 0");
+        }
+        if start > end {
+            bail!("--sweep must start at most at its end, got {start} to {end}");
+        }
+    }
+    if let Some(max) = args.max_size {
+        if max < args.min_size {
+            bail!(
+                "--max-size {max} can't be below --min-size {}",
+                args.min_size
+            );
+        }
+    }
+    if let Some(max) = args.max_elongation.filter(|&max| max < 1.0) {
+        bail!("--max-elongation {max} is below 1, which even a single pixel has");
+    }
+    if let Some(max) = args.max_axis_ratio.filter(|&max| max < 1.0) {
+        bail!("--max-axis-ratio {max} is below 1, which even a single pixel has");
+    }
+    if let (Some(Radius::Pixels(outer)), Some(inner)) = (args.circular_mask, args.inner_radius) {
+        if inner >= outer {
+            bail!("--inner-radius {inner} must be below the --circular-mask radius {outer}");
+        }
+    }
+    if let Some(distance) = args.split_peaks.filter(|&distance| distance <= 0.0) {
+        bail!("--split-peaks {distance} must be above 0");
+    }
+    if let (Some(min), Some(max)) = (args.expect_min, args.expect_max) {
+        if min > max {
+            bail!("--expect-min {min} can't be above --expect-max {max}");
+        }
+    }
+    Ok(())
+}
+
+fn run(args: Args) -> Result<ExitCode> {
+    let level = if args.quiet {
+        LevelFilter::Error
+    } else if args.verbose {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    };
+    let mut builder = env_logger::Builder::new();
+    builder.filter_level(level);
+    if args.trace_seeds && !args.quiet {
+        builder.filter_module(SEED_TARGET, LevelFilter::Trace);
+    }
+    builder
+        .parse_default_env()
+        .format_timestamp(None)
+        .format_target(false)
+        .init();
+
+    check_args(&args)?;
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(args.threads)
+        .build_global()
+        .context("Failed to set up the thread pool")?;
+
+    let mut inputs = args.file.clone();
+    if let Some(path) = &args.file_list {
+        inputs.extend(read_file_list(path)?);
+    }
+    let files = collect_files(&inputs)?;
+    // Pages counted on their own are inputs of their own as well
+    let single = files.len() == 1 && args.stack != Some(Stack::None);
+    let single_file_options = [
+        ("--output-name", args.output_name.is_some()),
+        ("--csv", args.csv.is_some()),
+        ("--npy", args.npy.is_some()),
+        ("--catalog", args.catalog.is_some()),
+        ("--label-output", args.label_output.is_some()),
+        ("--density-map", args.density_map.is_some()),
+        ("--regions", args.regions.is_some()),
+        ("--trace-image", args.trace_image.is_some()),
+        ("--threshold-map", args.threshold_map.is_some()),
+        ("--cache-grid", args.cache_grid.is_some()),
+        ("--load-grid", args.load_grid.is_some()),
+    ];
+    for (option, given) in single_file_options {
+        if given && !single {
+            bail!("{option} can only be used with a single input file");
+        }
+    }
+
+    let dark = load_dark(&args)?;
+    let dark = dark.as_ref();
+    if args.hot_pixel_scan {
+        scan_hot_pixels(&args, dark, &files)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    if let Some(path) = &args.time_series {
+        let expected = write_time_series(&args, dark, &files, path)?;
+        return Ok(if expected {
+            ExitCode::SUCCESS
+        } else {
+            ExitCode::from(UNEXPECTED_COUNT)
+        });
+    }
+
+    let mut count_file = args
+        .count_file
+        .as_ref()
+        .map(|path| {
+            File::options()
+                .create(true)
+                .append(true)
+                .open(path)
+                .with_context(|| format!("Failed to open {}", path.display()))
+        })
+        .transpose()?;
+    let mut failed = false;
+    let mut unexpected = false;
+    let mut summary = Vec::new();
+    let mut record = |file: String, result: Result<Option<u64>>| match result {
+        Ok(count) => {
+            if let Some(count) = count {
+                unexpected |= !is_expected_count(&args, &file, count);
+                if let Some(count_file) = &mut count_file {
+                    if let Err(err) = append_count(count_file, &file, count) {
+                        let path = args.count_file.as_ref().expect("The count file was opened");
+                        eprintln!("Error: Failed to write {}: {err}", path.display());
+                        failed = true;
+                    }
+                }
+            }
+            summary.push(SummaryFile {
+                file,
+                count,
+                error: None,
+            });
+        }
+        Err(err) => {
+            eprintln!("Error: {err:#}");
+            failed = true;
+            summary.push(SummaryFile {
+                file,
+                count: None,
+                error: Some(format!("{err:#}")),
+            });
+        }
+    };
+    let separate_pages = |file: &str| args.stack == Some(Stack::None) && is_tiff(file);
+    if files.len() > 1 {
+        handle_interrupts();
+    }
+    for batch in files.chunks(args.jobs) {
+        if interrupted() {
+            break;
+        }
+        // Pages are found one at a time while they are decoded, after the rest of the batch
+        let analyzed: Vec<Option<Result<Analyzed>>> = batch
+            .par_iter()
+            .map(|file| (!separate_pages(file)).then(|| analyze_file(&args, dark, file)))
+            .collect();
+        for (file, analyzed) in batch.iter().zip(analyzed) {
+            let Some(analyzed) = analyzed else {
+                let pages = match load_tiff_pages(file, args.read_retries) {
+                    Ok(pages) => pages,
+                    Err(err) => {
+                        record(file.clone(), Err(err));
+                        continue;
+                    }
+                };
+                for (index, page) in pages.enumerate() {
+                    if interrupted() {
+                        break;
+                    }
+                    let name = page_name(file, index);
+                    if args.format == Format::Text && !args.count_only {
+                        println!("{name}:");
+                    }
+                    let result = page
+                        .and_then(|page| {
+                            analyze_decoded(&args, dark, page, Instant::now(), Timings::new())
+                        })
+                        .and_then(|analyzed| report_file(&args, dark, &name, analyzed));
+                    record(name, result);
+                }
+                continue;
+            };
+            if args.format == Format::Text && !args.count_only && files.len() > 1 {
+                println!("{file}:");
+            }
+            record(
+                file.clone(),
+                analyzed.and_then(|analyzed| report_file(&args, dark, file, analyzed)),
+            );
+        }
+    }
+    let interrupted = interrupted();
+    if interrupted {
+        warn!(
+            "Stopped early because of Ctrl-C, after {} of the inputs",
+            summary.len()
+        );
+    }
+    if let Some(path) = &args.summary {
+        write_summary(path, summary, interrupted)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+    }
+    Ok(if interrupted {
+        ExitCode::from(INTERRUPTED_EXIT)
+    } else if failed {
+        ExitCode::FAILURE
+    } else if unexpected {
+        ExitCode::from(UNEXPECTED_COUNT)
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+/// Appends the line of a counted file to the --count-file, in a single write so runs that share
+/// the file don't interleave their lines, and waits for it to reach the disk or the share
+fn append_count(count_file: &mut File, file: &str, count: u64) -> io::Result<()> {
+    let line = format!("{file}\t{count}\t{}\n", utc_timestamp(SystemTime::now()));
+    count_file.write_all(line.as_bytes())?;
+    count_file.sync_data()
+}
+
+/// The time as RFC 3339 in UTC to the second, like `2024-05-01T21:30:00Z`
+fn utc_timestamp(time: SystemTime) -> String {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs());
+    let (days, seconds) = (seconds / 86_400, seconds % 86_400);
+    // Days since the epoch to the civil date, after Howard Hinnant's days_from_civil inverse,
+    // counting years from March so the leap day is at the end
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = if month_from_march < 10 {
+        month_from_march + 3
+    } else {
+        month_from_march - 9
+    };
+    let year = year_of_era + era * 400 + (month <= 2) as u64;
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// Whether the count is within `--expect-min` and `--expect-max`, warning when it isn't
+fn is_expected_count(args: &Args, file: &str, count: u64) -> bool {
+    if let Some(min) = args.expect_min.filter(|&min| count < min) {
+        warn!("{file}: found {count} stars, expected at least {min}");
+        false
+    } else if let Some(max) = args.expect_max.filter(|&max| count > max) {
+        warn!("{file}: found {count} stars, expected at most {max}");
+        false
+    } else {
+        true
+    }
+}
+
+/// Counts of every processed file along with their statistics
+#[derive(Serialize)]
+struct Summary {
+    schema_version: u32,
+    /// Version of star-counter that wrote the summary
+    version: &'static str,
+    files: Vec<SummaryFile>,
+    total: u64,
+    /// Over the files that were counted, missing when there are none
+    mean: Option<f64>,
+    /// Population standard deviation of the counts
+    std_dev: Option<f64>,
+    /// Whether Ctrl-C stopped the run, so `files` are only those counted before it
+    interrupted: bool,
+}
+
+#[derive(Serialize)]
+struct SummaryFile {
+    file: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    count: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+fn write_summary(path: &Path, files: Vec<SummaryFile>, interrupted: bool) -> Result<()> {
+    let counts: Vec<u64> = files.iter().filter_map(|file| file.count).collect();
+    let total = counts.iter().sum();
+    let mean = (!counts.is_empty()).then(|| total as f64 / counts.len() as f64);
+    let std_dev = mean.map(|mean| {
+        let variance = counts
+            .iter()
+            .map(|&count| (count as f64 - mean).powi(2))
+            .sum::<f64>()
+            / counts.len() as f64;
+        variance.sqrt()
+    });
+    let summary = Summary {
+        schema_version: SCHEMA_VERSION,
+        version: env!("CARGO_PKG_VERSION"),
+        files,
+        total,
+        mean,
+        std_dev,
+        interrupted,
+    };
+    let mut writer = BufWriter::new(File::create(path)?);
+    serde_json::to_writer_pretty(&mut writer, &summary)?;
+    writeln!(writer)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// The paths listed in a --file-list. Files that don't exist are still returned, so they fail
+/// like any other input instead of stopping the whole run
+fn read_file_list(path: &Path) -> Result<Vec<String>> {
+    let list =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let dir = path.parent().unwrap_or(Path::new(""));
+    Ok(list
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| dir.join(line).to_string_lossy().into_owned())
+        .collect())
+}
+
+/// Expands directories into the images they contain, in natural order so numbered frames stay
+/// in sequence, keeping other paths as they are
+fn collect_files(inputs: &[String]) -> Result<Vec<String>> {
+    let mut files = Vec::new();
+    for input in inputs {
+        if !Path::new(input).is_dir() {
+            files.push(input.clone());
+            continue;
+        }
+        let mut images = Vec::new();
+        for entry in fs::read_dir(input).with_context(|| format!("Failed to read {input}"))? {
+            let path = entry
+                .with_context(|| format!("Failed to read {input}"))?
+                .path();
+            if path.is_file() && ImageFormat::from_path(&path).is_ok() {
+                images.push(path.to_string_lossy().into_owned());
+            }
+        }
+        images.sort_by(|a, b| natural_cmp(a, b));
+        files.extend(images);
+    }
+    Ok(files)
+}
+
+/// Orders names like a person would, comparing runs of digits by their value so that
+/// `frame2.png` comes before `frame10.png`
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a, b);
+    loop {
+        let (Some(first_a), Some(first_b)) = (a.chars().next(), b.chars().next()) else {
+            return a.len().cmp(&b.len());
+        };
+        let ordering = if first_a.is_ascii_digit() && first_b.is_ascii_digit() {
+            let (digits_a, rest_a) =
+                a.split_at(a.find(|c: char| !c.is_ascii_digit()).unwrap_or(a.len()));
+            let (digits_b, rest_b) =
+                b.split_at(b.find(|c: char| !c.is_ascii_digit()).unwrap_or(b.len()));
+            (a, b) = (rest_a, rest_b);
+            let (value_a, value_b) = (
+                digits_a.trim_start_matches('0'),
+                digits_b.trim_start_matches('0'),
+            );
+            // Longer numbers are larger, and of equal ones the zero padded sorts last
+            value_a
+                .len()
+                .cmp(&value_b.len())
+                .then_with(|| value_a.cmp(value_b))
+                .then_with(|| digits_a.len().cmp(&digits_b.len()))
+        } else {
+            (a, b) = (&a[first_a.len_utf8()..], &b[first_b.len_utf8()..]);
+            first_a.cmp(&first_b)
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+}
+
+/// Path given instead of a file to read the image from stdin
+const STDIN_PATH: &str = "-";
+
+/// Path given instead of a file to write to stdout
+const STDOUT_PATH: &str = "-";
+
+fn load_image(file: &str, autorotate: bool, retries: u32) -> Result<DynamicImage> {
+    if file == STDIN_PATH {
+        let mut data = Vec::new();
+        io::stdin()
+            .read_to_end(&mut data)
+            .context("Failed to read stdin")?;
+        if data.is_empty() {
+            bail!("No image data on stdin");
+        }
+        let img = image::load_from_memory(&data).context("Failed to decode image from stdin")?;
+        let orientation = autorotate
+            .then(|| exif_orientation(&mut Cursor::new(&data)))
+            .flatten();
+        return Ok(apply_orientation(img, orientation));
+    }
+    let data = read_file(file, retries)?;
+    let img = if is_raw(file) {
+        decode_raw(file, &data)?
+    } else if is_tiff(file) {
+        // Decoded like the pages of --stack, which reads float TIFFs unlike the image crate
+        Decoder::new(Cursor::new(&data))
+            .map_err(anyhow::Error::from)
+            .and_then(|mut decoder| read_tiff_page(&mut decoder))
+            .with_context(|| format!("Failed to decode {file}"))?
+    } else {
+        let mut reader = Reader::new(Cursor::new(&data));
+        match ImageFormat::from_path(file) {
+            Ok(format) => reader.set_format(format),
+            // Files without a known extension are recognised by their first bytes
+            Err(_) => reader = reader.with_guessed_format()?,
+        }
+        reader
+            .decode()
+            .with_context(|| format!("Failed to decode {file}"))?
+    };
+    let orientation = autorotate
+        .then(|| exif_orientation(&mut Cursor::new(&data)))
+        .flatten();
+    Ok(apply_orientation(img, orientation))
+}
+
+/// Wait before the first retry of --read-retries, doubling before every next one
+const READ_BACKOFF: Duration = Duration::from_millis(100);
+
+/// The whole file, read again up to `retries` times when that fails in a way a network share
+/// briefly can
+fn read_file(file: &str, retries: u32) -> Result<Vec<u8>> {
+    read_with_retries(|| File::open(file), retries, READ_BACKOFF)
+        .with_context(|| format!("Failed to read {file}"))
+}
+
+/// Extensions of the camera raw formats that are decoded with rawloader
+const RAW_EXTENSIONS: &[&str] = &[
+    "3fr", "arw", "cr2", "crw", "dcr", "dng", "erf", "iiq", "kdc", "mef", "mos", "mrw", "nef",
+    "nrw", "orf", "pef", "raf", "rw2", "sr2", "srf", "srw",
+];
+
+fn is_raw(file: &str) -> bool {
+    Path::new(file).extension().is_some_and(|extension| {
+        RAW_EXTENSIONS
+            .iter()
+            .any(|raw| extension.eq_ignore_ascii_case(raw))
+    })
+}
+
+/// Decodes a camera raw file into a 16-bit image of its sensor values, stretched from the black
+/// to the white level of the camera and with its masked borders cropped
+///
+/// The Bayer pattern is not demosaiced, so every pixel is the luminance behind its own colour
+/// filter. Stars cover several pixels, so this barely changes what is found, and coordinates
+/// stay those of the sensor.
+#[cfg(feature = "raw")]
+fn decode_raw(file: &str, data: &[u8]) -> Result<DynamicImage> {
+    let raw = rawloader::decode(&mut Cursor::new(data))
+        .with_context(|| format!("Failed to decode {file}"))?;
+    let [top, right, bottom, left] = raw.crops;
+    let width = raw.width.saturating_sub(left + right);
+    let height = raw.height.saturating_sub(top + bottom);
+    let cpp = raw.cpp;
+    // Between 0 at the black and 1 at the white level of the colour
+    let level = |index: usize, colour: usize| {
+        let value = match &raw.data {
+            RawImageData::Integer(data) => data[index] as f32,
+            RawImageData::Float(data) => data[index],
+        };
+        let black = raw.blacklevels[colour] as f32;
+        let white = raw.whitelevels[colour] as f32;
+        (value - black) / (white - black).max(1.0)
+    };
+    let mut pixels = Vec::with_capacity(width * height);
+    for y in top..top + height {
+        for x in left..left + width {
+            let index = (y * raw.width + x) * cpp;
+            let level = if cpp == 1 {
+                level(index, raw.cfa.color_at(y, x).min(3))
+            } else {
+                // Several components per pixel are already demosaiced, so they are averaged
+                (0..cpp).map(|c| level(index + c, c.min(3))).sum::<f32>() / cpp as f32
+            };
+            pixels.push((level.clamp(0.0, 1.0) * u16::MAX as f32).round() as u16);
+        }
+    }
+    let img = ImageBuffer::from_raw(width as u32, height as u32, pixels)
+        .with_context(|| format!("{file} has no pixels"))?;
+    Ok(DynamicImage::ImageLuma16(img))
+}
+
+#[cfg(not(feature = "raw"))]
+fn decode_raw(file: &str, _data: &[u8]) -> Result<DynamicImage> {
+    bail!("{file} is a camera raw file, which needs star-counter built with the raw feature")
+}
+
+/// The image of the file, which is the stack of all its pages for multi-page TIFFs when
+/// `--stack` combines them
+fn load_input(args: &Args, file: &str) -> Result<DynamicImage> {
+    match args.stack {
+        Some(stack @ (Stack::Mean | Stack::Sum)) if is_tiff(file) => {
+            stack_pages(file, load_tiff_pages(file, args.read_retries)?, stack)
+        }
+        _ => load_image(file, !args.no_autorotate, args.read_retries),
+    }
+}
+
+/// Every frame of an animated GIF or page of a TIFF unless they are stacked, or the image
+/// itself for other files
+fn load_frames(args: &Args, file: &str) -> Result<Box<dyn Iterator<Item = Result<DynamicImage>>>> {
+    if is_tiff(file) && !matches!(args.stack, Some(Stack::Mean | Stack::Sum)) {
+        return load_tiff_pages(file, args.read_retries);
+    }
+    let is_gif = file != STDIN_PATH && ImageFormat::from_path(file).ok() == Some(ImageFormat::Gif);
+    if !is_gif {
+        return Ok(Box::new(iter::once(load_input(args, file))));
+    }
+    let reader = Cursor::new(read_file(file, args.read_retries)?);
+    let decoder = GifDecoder::new(reader).with_context(|| format!("Failed to decode {file}"))?;
+    let file = file.to_owned();
+    Ok(Box::new(decoder.into_frames().map(move |frame| {
+        let frame = frame.with_context(|| format!("Failed to decode a frame of {file}"))?;
+        Ok(DynamicImage::ImageRgba8(frame.into_buffer()))
+    })))
+}
+
+fn is_tiff(file: &str) -> bool {
+    file != STDIN_PATH && ImageFormat::from_path(file).ok() == Some(ImageFormat::Tiff)
+}
+
+/// Every page of a TIFF file, stopping at the first one that fails to decode
+fn load_tiff_pages(
+    file: &str,
+    retries: u32,
+) -> Result<Box<dyn Iterator<Item = Result<DynamicImage>>>> {
+    let reader = Cursor::new(read_file(file, retries)?);
+    let mut decoder = Decoder::new(reader).with_context(|| format!("Failed to decode {file}"))?;
+    let file = file.to_owned();
+    let mut index = 0;
+    let mut done = false;
+    Ok(Box::new(iter::from_fn(move || {
+        if done {
+            return None;
+        }
+        let page = (|| {
+            if index > 0 {
+                decoder.next_image()?;
+            }
+            read_tiff_page(&mut decoder)
+        })()
+        .with_context(|| format!("Failed to decode page {} of {file}", index + 1));
+        done = page.is_err() || !decoder.more_images();
+        index += 1;
+        Some(page)
+    })))
+}
+
+/// The page the decoder is at
+fn read_tiff_page<R: Read + Seek>(decoder: &mut Decoder<R>) -> Result<DynamicImage> {
+    let (width, height) = decoder.dimensions()?;
+    let color = decoder.colortype()?;
+    tiff_page(width, height, color, decoder.read_image()?)
+}
+
+/// The decoded samples of a TIFF page as an image, for the color types the counter reads. Float
+/// pages become 16 bits with their NaN pixels transparent by [`luma16_from_float`]
+fn tiff_page(
+    width: u32,
+    height: u32,
+    color: tiff::ColorType,
+    data: DecodingResult,
+) -> Result<DynamicImage> {
+    use tiff::ColorType::{Gray, GrayA, RGB, RGBA};
+    let img = match (color, data) {
+        (Gray(8), DecodingResult::U8(data)) => {
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageLuma8)
+        }
+        (Gray(16), DecodingResult::U16(data)) => {
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageLuma16)
+        }
+        (GrayA(8), DecodingResult::U8(data)) => {
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageLumaA8)
+        }
+        (GrayA(16), DecodingResult::U16(data)) => {
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageLumaA16)
+        }
+        (RGB(8), DecodingResult::U8(data)) => {
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgb8)
+        }
+        (RGB(16), DecodingResult::U16(data)) => {
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgb16)
+        }
+        (RGBA(8), DecodingResult::U8(data)) => {
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgba8)
+        }
+        (RGBA(16), DecodingResult::U16(data)) => {
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgba16)
+        }
+        (Gray(32), DecodingResult::F32(data)) => {
+            luma16_from_float(width, height, &data).map(DynamicImage::ImageLumaA16)
+        }
+        (Gray(64), DecodingResult::F64(data)) => {
+            let data: Vec<f32> = data.into_iter().map(|value| value as f32).collect();
+            luma16_from_float(width, height, &data).map(DynamicImage::ImageLumaA16)
+        }
+        (color, _) => bail!("Unsupported TIFF color type {color:?}"),
+    };
+    img.context("Page has fewer samples than its size needs")
+}
+
+/// Combines the pages into a single 16-bit frame, which is gray when the first page is
+///
+/// The mean is rounded to the nearest level, and the sum clips at white so stars that are
+/// bright in a single page saturate.
+fn stack_pages(
+    file: &str,
+    pages: impl Iterator<Item = Result<DynamicImage>>,
+    stack: Stack,
+) -> Result<DynamicImage> {
+    let mut sums: Vec<u64> = Vec::new();
+    let mut first = None;
+    let mut count = 0;
+    for page in pages {
+        let page = page?;
+        let (width, height, gray) = *first.get_or_insert((
+            page.width(),
+            page.height(),
+            page.color().channel_count() <= 2,
+        ));
+        if (page.width(), page.height()) != (width, height) {
+            bail!(
+                "Page {} of {file} is {}x{}, but the first page is {width}x{height}",
+                count + 1,
+                page.width(),
+                page.height()
+            );
+        }
+        let samples = if gray {
+            page.to_luma16().into_raw()
+        } else {
+            page.to_rgb16().into_raw()
+        };
+        if sums.is_empty() {
+            sums = samples.into_iter().map(u64::from).collect();
+        } else {
+            for (sum, sample) in sums.iter_mut().zip(samples) {
+                *sum += u64::from(sample);
+            }
+        }
+        count += 1;
+    }
+    let Some((width, height, gray)) = first else {
+        bail!("{file} has no pages");
+    };
+    debug!("Stacked {count} pages of {file}");
+    let values: Vec<u16> = sums
+        .into_iter()
+        .map(|sum| match stack {
+            Stack::Mean => ((sum + count / 2) / count) as u16,
+            _ => sum.min(u16::MAX as u64) as u16,
+        })
+        .collect();
+    Ok(if gray {
+        DynamicImage::ImageLuma16(
+            ImageBuffer::from_raw(width, height, values)
+                .expect("Pages have the size of the first page"),
+        )
+    } else {
+        DynamicImage::ImageRgb16(
+            ImageBuffer::from_raw(width, height, values)
+                .expect("Pages have the size of the first page"),
+        )
+    })
+}
+
+/// Name of a page of a multi-page file, so its outputs are named after the page
+fn page_name(file: &str, index: usize) -> String {
+    let path = Path::new(file);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(extension) => format!("{stem}-page{}.{}", index + 1, extension.to_string_lossy()),
+        None => format!("{stem}-page{}", index + 1),
+    };
+    path.with_file_name(name).to_string_lossy().into_owned()
+}
+
+/// The EXIF orientation tag, if the image has one
+fn exif_orientation(container: &mut (impl BufRead + Seek)) -> Option<u32> {
+    let exif = exif::Reader::new().read_from_container(container).ok()?;
+    exif.get_field(exif::Tag::Orientation, exif::In::PRIMARY)?
+        .value
+        .get_uint(0)
+}
+
+/// Rotates and flips the image so it is displayed upright according to its EXIF orientation
+fn apply_orientation(img: DynamicImage, orientation: Option<u32>) -> DynamicImage {
+    match orientation {
+        Some(2) => img.fliph(),
+        Some(3) => img.rotate180(),
+        Some(4) => img.flipv(),
+        Some(5) => img.rotate90().fliph(),
+        Some(6) => img.rotate90(),
+        Some(7) => img.rotate270().fliph(),
+        Some(8) => img.rotate270(),
+        _ => img,
+    }
+}
+
+/// The connectivity of `--connectivity`, or of `--neighbour-radius` when it is given
+fn connectivity(args: &Args) -> Connectivity {
+    args.neighbour_radius
+        .map_or(args.connectivity, Connectivity::Radius)
+}
+
+/// Loads the mask for an image of the given size, cropping it like the image. Pixels that are
+/// at least half as bright as white can be stars
+fn load_mask(path: &Path, width: u32, height: u32, args: &Args) -> Result<Bitmap> {
+    let mask = image::open(path)
+        .with_context(|| format!("Failed to open mask {}", path.display()))?
+        .to_luma8();
+    let mask = if mask.dimensions() != (width, height) {
+        if !args.resize_mask {
+            bail!(
+                "Mask {} is {}x{} but the image is {width}x{height}, use --resize-mask to \
+                 stretch it",
+                path.display(),
+                mask.width(),
+                mask.height()
+            );
+        }
+        imageops::resize(&mask, width, height, imageops::FilterType::Nearest)
+    } else {
+        mask
+    };
+    Ok(threshold_image(
+        &crop_like_image(mask, args),
+        Threshold::new(u8::MAX / 2),
+    ))
+}
+
+/// Pixels of an image with an alpha channel that are at least as opaque as --alpha-cutoff,
+/// cropped like the image. `None` when none of them are more transparent
+fn alpha_mask(alpha: GrayImage, args: &Args) -> Option<Bitmap> {
+    let cutoff = args.alpha_cutoff;
+    if alpha.iter().all(|&alpha| alpha >= cutoff) {
+        return None;
+    }
+    Some(threshold_image(
+        &crop_like_image(alpha, args),
+        Threshold::new(cutoff - 1),
+    ))
+}
+
+/// The pixels inside --circular-mask, or its ring with --inner-radius, on a `width` by `height`
+/// image, cropped like the image
+fn circular_mask(radius: Radius, width: u32, height: u32, args: &Args) -> Bitmap {
+    let center = match args.mask_center.as_deref() {
+        Some(&[x, y]) => (x, y),
+        // The centre of the middle pixels, so the circle is symmetric
+        _ => ((width as f64 - 1.0) / 2.0, (height as f64 - 1.0) / 2.0),
+    };
+    let outer_radius = match radius {
+        Radius::Inscribed => width.min(height) as f64 / 2.0,
+        Radius::Pixels(radius) => radius,
+    };
+    let aperture = Bitmap::annulus(
+        width as usize,
+        height as usize,
+        center,
+        args.inner_radius.unwrap_or(0.0),
+        outer_radius,
+    );
+    threshold_image(
+        &crop_like_image(convert_to_image(&aperture), args),
+        Threshold::new(u8::MAX / 2),
+    )
+}
+
+/// Reads the `x,y` pixels of --bad-pixels, which must be inside a `width` by `height` image
+fn read_bad_pixels(path: &Path, width: u32, height: u32) -> Result<Vec<(u32, u32)>> {
+    let list = fs::read_to_string(path)?;
+    let mut pixels = Vec::new();
+    for (number, line) in list.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let pixel = line
+            .split_once(',')
+            .and_then(|(x, y)| Some((x.trim().parse().ok()?, y.trim().parse().ok()?)));
+        let Some((x, y)) = pixel else {
+            bail!("Line {} should be a pixel as x,y, got {line}", number + 1);
+        };
+        if x >= width || y >= height {
+            bail!(
+                "Pixel {x},{y} on line {} is outside the {width}x{height} image",
+                number + 1
+            );
+        }
+        pixels.push((x, y));
+    }
+    Ok(pixels)
+}
+
+/// Where a pixel of the full image ends up in the image that is searched after --roi and
+/// --downscale, `None` when it is outside --roi
+fn searched_pixel(args: &Args, x: u32, y: u32) -> Option<(usize, usize)> {
+    let (x, y) = match args.crop() {
+        Some(roi) => {
+            let (x, y) = (x.checked_sub(roi.x)?, y.checked_sub(roi.y)?);
+            if x >= roi.width || y >= roi.height {
+                return None;
+            }
+            (x, y)
+        }
+        None => (x, y),
+    };
+    let factor = args.downscale.unwrap_or(1);
+    Some(((x / factor) as usize, (y / factor) as usize))
+}
+
+/// Crops and shrinks a full size mask by --roi and --downscale, so it matches the searched
+/// image
+fn crop_like_image(mut mask: GrayImage, args: &Args) -> GrayImage {
+    if let Some(roi) = args.crop() {
+        mask = imageops::crop_imm(&mask, roi.x, roi.y, roi.width, roi.height).to_image();
+    }
+    if let Some(factor) = args.downscale.filter(|&factor| factor > 1) {
+        mask = downscale(&mask, factor);
+    }
+    mask
+}
+
+/// Shrinks the bitmap like [`downscale`], with a pixel set when any pixel of its block is, so
+/// no star disappears
+fn shrink_bitmap(stars: &Bitmap, factor: u32) -> Bitmap {
+    let factor = factor as usize;
+    let (width, height) = (stars.width(), stars.height());
+    let mut small = Bitmap::new(width.div_ceil(factor), height.div_ceil(factor));
+    for y in 0..height {
+        for (x, &star) in stars.row(y).iter().enumerate() {
+            if star {
+                small.set(x / factor, y / factor, true);
+            }
+        }
+    }
+    small
+}
+
+/// Shrinks the image by averaging every `factor` by `factor` block of pixels, which is much
+/// faster than the filters of [`imageops::resize`]. Blocks at the right and bottom edges can
+/// be smaller, so every pixel is in exactly one block
+fn downscale<T: Primitive>(
+    img: &ImageBuffer<Luma<T>, Vec<T>>,
+    factor: u32,
+) -> ImageBuffer<Luma<T>, Vec<T>> {
+    let (width, height) = (img.width(), img.height());
+    let (small_width, small_height) = (width.div_ceil(factor), height.div_ceil(factor));
+    let (width, factor) = (width as usize, factor as usize);
+    let mut data = Vec::with_capacity(small_width as usize * small_height as usize);
+    let mut sums = vec![0u64; small_width as usize];
+    for rows in img.chunks(width * factor).filter(|_| width > 0) {
+        sums.fill(0);
+        for row in rows.chunks_exact(width) {
+            for (x, value) in row.iter().enumerate() {
+                sums[x / factor] += value.to_u64().unwrap_or(0);
+            }
+        }
+        let block_height = rows.len() / width;
+        for (x, &sum) in sums.iter().enumerate() {
+            let block_width = (width - x * factor).min(factor);
+            let mean = sum / (block_width * block_height) as u64;
+            data.push(T::from(mean).unwrap_or_else(T::max_value));
+        }
+    }
+    ImageBuffer::from_raw(small_width, small_height, data).expect("Every block has a pixel")
+}
+
+/// Crops, denoises, flattens the background, normalizes, stretches and downscales the image as
+/// requested
+fn preprocess<'a, T>(
+    full: &'a ImageBuffer<Luma<T>, Vec<T>>,
+    args: &Args,
+) -> Result<Cow<'a, ImageBuffer<Luma<T>, Vec<T>>>>
+where
+    T: Primitive + Send + Sync + 'static,
+    Luma<T>: Pixel<Subpixel = T>,
+{
+    let mut img = if let Some(roi) = args.crop() {
+        roi.check_bounds(full.width(), full.height())?;
+        Cow::Owned(imageops::crop_imm(full, roi.x, roi.y, roi.width, roi.height).to_image())
+    } else {
+        Cow::Borrowed(full)
+    };
+    if let Some(sigma) = args.denoise {
+        img = Cow::Owned(imageops::blur(&*img, sigma));
+    }
+    if let Some(window) = args.subtract_background {
+        img = Cow::Owned(subtract_background(&img, window));
+    }
+    if let Some(clip) = args.normalize {
+        img = Cow::Owned(normalize(&img, clip));
+    }
+    if args.stretch != Stretch::None {
+        img = Cow::Owned(stretch(&img, args.stretch));
+    }
+    if let Some(factor) = args.downscale.filter(|&factor| factor > 1) {
+        img = Cow::Owned(downscale(&img, factor));
+    }
+    Ok(img)
+}
+
+/// The brightness of the image in the chosen channel or with the chosen weights
+fn gray(args: &Args, img: &DynamicImage) -> GrayImage {
+    match args.gray_weights {
+        Some(weights) => to_luma_weighted(img, weights),
+        None if args.gray_method != GrayMethod::Luma => to_luma_with_method(img, args.gray_method),
+        None => to_luma(img, args.channel),
+    }
+}
+
+/// Same as [`gray`] at 16 bits
+fn gray16(args: &Args, img: &DynamicImage) -> ImageBuffer<Luma<u16>, Vec<u16>> {
+    match args.gray_weights {
+        Some(weights) => to_luma16_weighted(img, weights),
+        None if args.gray_method != GrayMethod::Luma => {
+            to_luma16_with_method(img, args.gray_method)
+        }
+        None => to_luma16(img, args.channel),
+    }
+}
+
+/// Everything found in a single image
+struct Analysis {
+    /// The whole image in the chosen channel, also when only a region was searched. Missing
+    /// when the stars were loaded with --load-grid
+    full: Option<GrayImage>,
+    width: u32,
+    height: u32,
+    stats: StarStats,
+    /// Pixels of the kept stars, unless the image was processed in strips
+    stars: Option<Bitmap>,
+    /// Sensitivity the image was thresholded with, in its own bit depth
+    sensitivity: u16,
+    bit_depth: u8,
+    searched_pixels: u64,
+    /// Number of merges of close stars, when asked for
+    merged: Option<usize>,
+    /// Whether counting stopped at --limit, so there may be more stars
+    limited: bool,
+    /// Number of stars brighter than the one at --brighter-than
+    brighter: Option<u64>,
+    /// Median width of the stars in pixels of the full image, with --fwhm
+    fwhm: Option<Fwhm>,
+}
+
+/// Decodes the image and finds its stars, or returns `None` after printing its histogram
+fn analyze(
+    args: &Args,
+    dark: Option<&DarkFrame>,
+    file: &str,
+    timings: &mut Timings,
+) -> Result<Option<Analysis>> {
+    let decoded = load_input(args, file)?;
+    timings.lap("decode");
+    analyze_image(args, dark, decoded, timings)
+}
+
+/// The --dark frame, loaded once per run in the channel the images are counted in
+struct DarkFrame {
+    path: PathBuf,
+    gray: GrayImage,
+    gray16: ImageBuffer<Luma<u16>, Vec<u16>>,
+}
+
+/// Loads the --dark frame if there is one, so every image of the run is calibrated against the
+/// same decoded frame
+fn load_dark(args: &Args) -> Result<Option<DarkFrame>> {
+    let Some(path) = &args.dark else {
+        return Ok(None);
+    };
+    let dark = load_image(
+        &path.to_string_lossy(),
+        !args.no_autorotate,
+        args.read_retries,
+    )?;
+    Ok(Some(DarkFrame {
+        path: path.clone(),
+        gray: gray(args, &dark),
+        gray16: gray16(args, &dark),
+    }))
+}
+
+fn analyze_image(
+    args: &Args,
+    dark: Option<&DarkFrame>,
+    decoded: DynamicImage,
+    timings: &mut Timings,
+) -> Result<Option<Analysis>> {
+    let deadline = deadline(args);
+    let mut full = gray(args, &decoded);
+    let (width, height) = full.dimensions();
+    let text = args.format == Format::Text && !args.count_only;
+
+    // Stars are measured on 8 bits, but 16-bit images are thresholded at full precision
+    let mut full16 = is_16_bit(&decoded).then(|| gray16(args, &decoded));
+    let alpha = alpha_channel(&decoded);
+    drop(decoded);
+    if let Some(dark) = dark {
+        let (dark_width, dark_height) = dark.gray.dimensions();
+        if (dark_width, dark_height) != (width, height) {
+            bail!(
+                "Dark frame {} is {dark_width}x{dark_height} but the image is {width}x{height}",
+                dark.path.display()
+            );
+        }
+        full = subtract_dark(&full, &dark.gray).expect("The sizes match");
+        if let Some(full16) = &mut full16 {
+            *full16 = subtract_dark(full16, &dark.gray16).expect("The sizes match");
+        }
+    }
+    timings.lap("grayscale");
+    let img = preprocess(&full, args)?;
+    let img = &*img;
+    let img16 = full16
+        .as_ref()
+        .map(|full16| preprocess(full16, args))
+        .transpose()?;
+    timings.lap("preprocess");
+
+    if args.histogram {
+        print_histogram(img);
+        return Ok(None);
+    }
+    if let Some(k) = args.estimate_background {
+        print_background(img, k);
+        return Ok(None);
+    }
+
+    // Stars are still measured on the sharp image
+    let measured = img;
+    let blurred = args.detect_blur.map(|sigma| imageops::blur(img, sigma));
+    let blurred16 = args
+        .detect_blur
+        .zip(img16.as_deref())
+        .map(|(sigma, img16)| imageops::blur(img16, sigma));
+    let img = blurred.as_ref().unwrap_or(img);
+    let img16 = blurred16.as_ref().or(img16.as_deref());
+    if args.detect_blur.is_some() {
+        timings.lap("blur");
+    }
+
+    let resolved = if let Some(percent) = args.percentile {
+        let threshold = percentile_threshold(img, percent);
+        info!("Using sensitivity {threshold} for the brightest {percent}% of pixels");
+        Some(threshold)
+    } else if args.auto_threshold {
+        let threshold = otsu_threshold(img);
+        info!("Using automatic sensitivity {threshold}");
+        Some(threshold)
+    } else {
+        None
+    };
+    let sensitivity = if let Some(threshold) = resolved {
+        if img16.is_some() {
+            Sensitivity::Fraction(threshold as f64 / u8::MAX as f64)
+        } else {
+            Sensitivity::Level(threshold)
+        }
+    } else if let Some(&[_, low]) = args.hysteresis.as_deref() {
+        low
+    } else {
+        args.sensitivity
+    };
+    let high = match args.hysteresis.as_deref() {
+        Some(&[high, low]) => {
+            let below = if img16.is_some() {
+                high.to_u16() <= low.to_u16()
+            } else {
+                high.to_u8() <= low.to_u8()
+            };
+            if below {
+                bail!("The high level of --hysteresis {high} must be above the low one {low}");
+            }
+            Some(high)
+        }
+        _ => None,
+    };
+    if let Some(max_sensitivity) = args.max_sensitivity {
+        let below = if img16.is_some() {
+            max_sensitivity.to_u16() <= sensitivity.to_u16()
+        } else {
+            max_sensitivity.to_u8() <= sensitivity.to_u8()
+        };
+        if below {
+            bail!(
+                "--max-sensitivity {max_sensitivity} must be above the sensitivity {sensitivity}"
+            );
+        }
+    }
+    // Inverted and adaptive thresholds don't compare pixels against the sensitivity itself,
+    // and a sweep or search uses many
+    if !args.invert
+        && args.adaptive.is_none()
+        && args.sweep.is_none()
+        && args.solve_for_count.is_none()
+    {
+        // The 8-bit luma of a 16-bit image is rounded, so a pixel just above the sensitivity
+        // can end up at it
+        let sensitivity = sensitivity.to_u8().saturating_sub(img16.is_some() as u8);
+        if let Some(warning) = assess_exposure(img, sensitivity) {
+            warn!("{warning}");
+        }
+    }
+
+    // Bars of files analysed at the same time would draw over each other
+    let progress =
+        args.progress && args.jobs == 1 && text && !args.quiet && io::stdout().is_terminal();
+
+    let options = detect_options(args);
+    let mut mask = args
+        .mask
+        .as_ref()
+        .map(|mask| load_mask(mask, width, height, args))
+        .transpose()?;
+    let apertures = [
+        alpha.and_then(|alpha| alpha_mask(alpha, args)),
+        args.circular_mask
+            .map(|radius| circular_mask(radius, width, height, args)),
+    ];
+    for aperture in apertures.into_iter().flatten() {
+        match &mut mask {
+            Some(mask) => mask.intersect(&aperture),
+            None => mask = Some(aperture),
+        }
+    }
+    let bad_pixels = match &args.bad_pixels {
+        Some(path) => read_bad_pixels(path, width, height)
+            .with_context(|| format!("Failed to read {}", path.display()))?
+            .into_iter()
+            .filter_map(|(x, y)| searched_pixel(args, x, y))
+            .collect(),
+        None => Vec::new(),
+    };
+    let detection = Detection {
+        args,
+        options: &options,
+        luma: measured,
+        mask: mask.as_ref(),
+        bad_pixels: &bad_pixels,
+        progress,
+        deadline,
+    };
+    if let Some(levels) = args.sweep.as_deref() {
+        sweep(&detection, img, img16, levels, timings)?;
+        return Ok(None);
+    }
+    if let Some(target) = args.solve_for_count {
+        solve_for_count(&detection, img, img16, target, timings)?;
+        return Ok(None);
+    }
+    let (mut stats, stars, resolved_sensitivity, bit_depth) = if let Some(img16) = &img16 {
+        let threshold = Threshold {
+            sensitivity: sensitivity.to_u16(),
+            max_sensitivity: args.max_sensitivity.map(Sensitivity::to_u16),
+            invert: args.invert,
+        };
+        let high = high.map(Sensitivity::to_u16);
+        let (stats, stars) = detection.run(&**img16, threshold, high, timings)?;
+        (stats, stars, threshold.sensitivity, 16)
+    } else {
+        let threshold = Threshold {
+            sensitivity: sensitivity.to_u8(),
+            max_sensitivity: args.max_sensitivity.map(Sensitivity::to_u8),
+            invert: args.invert,
+        };
+        let high = high.map(Sensitivity::to_u8);
+        let (stats, stars) = detection.run(img, threshold, high, timings)?;
+        (stats, stars, threshold.sensitivity as u16, 8)
+    };
+    // The searched region at full resolution, before any downscaling
+    let (searched_width, searched_height) = args
+        .crop()
+        .map_or((width, height), |roi| (roi.width, roi.height));
+    let limited = limit_reached(args, &stats);
+    let brighter = args
+        .brighter_than
+        .map(|at| count_brighter(args, at, &stats, stars.as_ref(), measured))
+        .transpose()?;
+    let fwhm = args
+        .fwhm
+        .then(|| {
+            let background = estimate_background(measured).median as f64;
+            median_fwhm(measured, &stats.stars, background)
+        })
+        .flatten()
+        .map(|fwhm| Fwhm {
+            median: fwhm.median * args.downscale.unwrap_or(1) as f64,
+            ..fwhm
+        });
+    let merged = place_stars(args, &mut stats);
+    let searched_pixels = searched_width as u64 * searched_height as u64;
+    if let Some(warning) = assess_groups(&stats, searched_pixels) {
+        warn!("{warning}");
+    }
+    Ok(Some(Analysis {
+        full: Some(full),
+        width,
+        height,
+        stats,
+        stars,
+        sensitivity: resolved_sensitivity,
+        bit_depth,
+        searched_pixels,
+        merged,
+        limited,
+        brighter,
+        fwhm,
+    }))
+}
+
+/// Number of stars with a greater brightness than the star covering the pixel `at`, out of
+/// the stars found in the searched image `luma` before they are placed in the full image
+fn count_brighter(
+    args: &Args,
+    at: Position,
+    stats: &StarStats,
+    stars: Option<&Bitmap>,
+    luma: &GrayImage,
+) -> Result<u64> {
+    let stars = stars.context("--brighter-than needs the pixels of the stars")?;
+    let start = searched_pixel(args, at.x, at.y)
+        .filter(|&(x, y)| stars.contains(x, y) && stars.get(x, y))
+        .ok_or_else(|| anyhow!("There is no star at --brighter-than {at}"))?;
+    let mut visited = Bitmap::new(stars.width(), stars.height());
+    let reference: u64 =
+        mark_group_wrapping(start, stars, &mut visited, connectivity(args), args.wrap)
+            .into_iter()
+            .map(|(x, y)| luma.get_pixel(x as u32, y as u32).0[0] as u64)
+            .sum();
+    info!("The star at {at} has brightness {reference}");
+    Ok(stats
+        .stars
+        .iter()
+        .filter(|star| star.brightness > reference)
+        .count() as u64)
+}
+
+fn detect_options(args: &Args) -> DetectOptions {
+    DetectOptions {
+        connectivity: connectivity(args),
+        wrap: args.wrap,
+        min_size: args.min_size,
+        max_size: args.max_size,
+        max_elongation: args.max_elongation,
+        max_axis_ratio: args.max_axis_ratio,
+        exclude_saturated: args.exclude_saturated,
+        // Downscaled pixels cover several of the original ones
+        exclude_border: args
+            .exclude_border
+            .div_ceil(args.downscale.unwrap_or(1) as usize),
+        limit: args.limit,
+        verify: args.verify,
+    }
+}
+
+/// Whether counting stopped at --limit, before merging could lower the count
+fn limit_reached(args: &Args, stats: &StarStats) -> bool {
+    args.limit.is_some_and(|limit| stats.count >= limit)
+}
+
+/// Moves the stars found in the searched region to where they are in the full image, then
+/// merges the close ones if asked to, returning the number of merges
+fn place_stars(args: &Args, stats: &mut StarStats) -> Option<usize> {
+    if let Some(factor) = args.downscale.filter(|&factor| factor > 1) {
+        stats.scale(factor as f64, factor as f64);
+    }
+    if let Some(roi) = args.crop() {
+        stats.translate(roi.x as usize, roi.y as usize);
+    }
+    args.merge_radius
+        .map(|radius| merge_close_stars(stats, radius))
+}
+
+/// Counts the stars of a grid saved by --cache-grid, checking that it fits the image
+fn analyze_grid(args: &Args, file: &str, path: &Path) -> Result<Analysis> {
+    let deadline = deadline(args);
+    if file == STDIN_PATH {
+        bail!("--load-grid needs an image file to check the grid against");
+    }
+    let (width, height) =
+        image::image_dimensions(file).with_context(|| format!("Failed to read {file}"))?;
+    let grid = read_grid(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let (searched_width, searched_height) = match args.crop() {
+        Some(roi) => {
+            roi.check_bounds(width, height)?;
+            (roi.width, roi.height)
+        }
+        None => (width, height),
+    };
+    let factor = args.downscale.unwrap_or(1);
+    let size = (
+        searched_width.div_ceil(factor) as usize,
+        searched_height.div_ceil(factor) as usize,
+    );
+    let grid_size = (grid.stars.width(), grid.stars.height());
+    if grid_size != size {
+        bail!(
+            "{} is a grid of {}x{} pixels, but {file} is searched in {}x{}",
+            path.display(),
+            grid_size.0,
+            grid_size.1,
+            size.0,
+            size.1
+        );
+    }
+    // Automatic sensitivities depend on the image, which isn't decoded to check them
+    if !args.auto_threshold && args.percentile.is_none() {
+        let sensitivity = args.sensitivity.in_bit_depth(grid.bit_depth);
+        if sensitivity != grid.sensitivity {
+            bail!(
+                "{} was thresholded at sensitivity {}, not {sensitivity}",
+                path.display(),
+                grid.sensitivity
+            );
+        }
+    }
+    let mut stars = grid.stars;
+    if let Some(path) = &args.threshold_map {
+        write_threshold_map(path, &stars)?;
+    }
+    let options = detect_options(args);
+    let mut stats = count_stars(args, &mut stars, None, &options, deadline, || {})?;
+    let limited = limit_reached(args, &stats);
+    let merged = place_stars(args, &mut stats);
+    let searched_pixels = searched_width as u64 * searched_height as u64;
+    if let Some(warning) = assess_groups(&stats, searched_pixels) {
+        warn!("{warning}");
+    }
+    Ok(Analysis {
+        full: None,
+        width,
+        height,
+        stats,
+        stars: Some(stars),
+        sensitivity: grid.sensitivity,
+        bit_depth: grid.bit_depth,
+        searched_pixels,
+        merged,
+        limited,
+        brighter: None,
+        fwhm: None,
+    })
+}
+
+/// First line of a file written by --cache-grid, followed by the size, bit depth and
+/// sensitivity of the grid
+const GRID_MAGIC: &str = "star-counter grid 1";
+
+/// Star pixels saved by --cache-grid along with the threshold that found them
+struct Grid {
+    stars: Bitmap,
+    /// In the bit depth of the image
+    sensitivity: u16,
+    bit_depth: u8,
+}
+
+/// Writes a text header line and then the [runs](Bitmap::runs) of the grid, each as a LEB128
+/// varint so that the many short runs take a byte
+fn write_grid(path: &Path, grid: &Grid) -> Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    let stars = &grid.stars;
+    writeln!(
+        writer,
+        "{GRID_MAGIC} {} {} {} {}",
+        stars.width(),
+        stars.height(),
+        grid.bit_depth,
+        grid.sensitivity
+    )?;
+    for mut run in stars.runs() {
+        while run >= 0x80 {
+            writer.write_all(&[(run & 0x7f) as u8 | 0x80])?;
+            run >>= 7;
+        }
+        writer.write_all(&[run as u8])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn read_grid(path: &Path) -> Result<Grid> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut header = String::new();
+    reader.read_line(&mut header)?;
+    let Some(fields) = header.trim_end().strip_prefix(GRID_MAGIC) else {
+        bail!("Not a grid saved by --cache-grid");
+    };
+    let fields: Vec<&str> = fields.split_whitespace().collect();
+    let [width, height, bit_depth, sensitivity] = fields[..] else {
+        bail!("Grid header should have a size, bit depth and sensitivity");
+    };
+    let (width, height): (usize, usize) = (width.parse()?, height.parse()?);
+    let (bit_depth, sensitivity) = (bit_depth.parse()?, sensitivity.parse()?);
+
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+    let mut runs = Vec::new();
+    let (mut run, mut shift) = (0usize, 0);
+    for byte in data {
+        if shift >= usize::BITS {
+            bail!("Grid has a run that is too long");
+        }
+        run |= ((byte & 0x7f) as usize) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            runs.push(run);
+            (run, shift) = (0, 0);
+        }
+    }
+    if shift > 0 {
+        bail!("Grid ends in the middle of a run");
+    }
+    let stars = Bitmap::from_runs(width, height, &runs)
+        .with_context(|| format!("Grid runs don't cover its {width}x{height} pixels"))?;
+    Ok(Grid {
+        stars,
+        sensitivity,
+        bit_depth,
+    })
+}
+
+/// Counts the stars in every frame of the files, writing the series as CSV to `path`. Returns
+/// whether every count was within `--expect-min` and `--expect-max`
+fn write_time_series(
+    args: &Args,
+    dark: Option<&DarkFrame>,
+    files: &[String],
+    path: &str,
+) -> Result<bool> {
+    let mut writer: Box<dyn Write> = if path == STDOUT_PATH {
+        Box::new(io::stdout().lock())
+    } else {
+        Box::new(BufWriter::new(
+            File::create(path).with_context(|| format!("Failed to create {path}"))?,
+        ))
+    };
+    writeln!(writer, "frame,count")?;
+    let mut expected = true;
+    let mut index = 0;
+    for file in files {
+        for frame in load_frames(args, file)? {
+            let analysis = analyze_image(args, dark, frame?, &mut Timings::new())?
+                .expect("--time-series conflicts with --histogram");
+            let count = analysis.stats.stars.len() as u64;
+            expected &= is_expected_count(args, &format!("{file} frame {index}"), count);
+            writeln!(writer, "{index},{count}")?;
+            index += 1;
+        }
+    }
+    writer.flush()?;
+    Ok(expected)
+}
+
+/// A pixel that was part of a star in too many frames
+#[derive(Serialize)]
+struct HotPixel {
+    x: u32,
+    y: u32,
+    frames: u32,
+}
+
+#[derive(Serialize)]
+struct HotPixelReport {
+    schema_version: u32,
+    frames: u32,
+    hot_pixels: Vec<HotPixel>,
+}
+
+/// Finds the stars in every frame of the files and prints the pixels that are part of a star
+/// in more than `--hot-pixel-fraction` of them. Every frame must have the same size
+fn scan_hot_pixels(args: &Args, dark: Option<&DarkFrame>, files: &[String]) -> Result<()> {
+    let mut counts: Vec<u32> = Vec::new();
+    let mut size = None;
+    let mut frames = 0;
+    for file in files {
+        for frame in load_frames(args, file)? {
+            let analysis = analyze_image(args, dark, frame?, &mut Timings::new())?
+                .expect("--hot-pixel-scan conflicts with --histogram");
+            let stars = analysis
+                .stars
+                .expect("--hot-pixel-scan conflicts with --tile-height");
+            let dimensions = (analysis.width, analysis.height);
+            if *size.get_or_insert(dimensions) != dimensions {
+                let (width, height) = size.unwrap();
+                bail!(
+                    "{file} is {}x{}, but the first frame is {width}x{height}",
+                    dimensions.0,
+                    dimensions.1
+                );
+            }
+            counts.resize(stars.width() * stars.height(), 0);
+            for y in 0..stars.height() {
+                let row = &mut counts[y * stars.width()..][..stars.width()];
+                for (count, &star) in row.iter_mut().zip(stars.row(y)) {
+                    *count += star as u32;
+                }
+            }
+            frames += 1;
+        }
+    }
+
+    // The stars are only searched for in the region of interest
+    let (offset_x, offset_y, width) = match args.crop() {
+        Some(roi) => (roi.x, roi.y, roi.width),
+        None => (0, 0, size.map_or(0, |(width, _)| width)),
+    };
+    let hot_pixels: Vec<HotPixel> = counts
+        .iter()
+        .enumerate()
+        .filter(|&(_, &count)| count as f64 > args.hot_pixel_fraction * frames as f64)
+        .map(|(index, &count)| HotPixel {
+            x: offset_x + index as u32 % width,
+            y: offset_y + index as u32 / width,
+            frames: count,
+        })
+        .collect();
+    match args.format {
+        Format::Text => {
+            println!("Found {} hot pixels in {frames} frames", hot_pixels.len());
+            for pixel in &hot_pixels {
+                println!(
+                    "  {},{}: in {} of the frames ({:.1}%)",
+                    pixel.x,
+                    pixel.y,
+                    pixel.frames,
+                    pixel.frames as f64 / frames as f64 * 100.0
+                );
+            }
+        }
+        Format::Json | Format::Jsonl => {
+            let report = HotPixelReport {
+                schema_version: SCHEMA_VERSION,
+                frames,
+                hot_pixels,
+            };
+            print_json_value(args, &report)?;
+        }
+    }
+    Ok(())
+}
+
+/// The stars found in a file that wasn't reported yet, so that files can be analysed at the
+/// same time and still be reported in order
+struct Analyzed {
+    /// Missing when only the histogram was printed
+    analysis: Option<Analysis>,
+    start: Instant,
+    timings: Timings,
+}
+
+fn analyze_file(args: &Args, dark: Option<&DarkFrame>, file: &str) -> Result<Analyzed> {
+    let start = Instant::now();
+    let mut timings = Timings::new();
+    if let Some(path) = &args.load_grid {
+        let analysis = analyze_grid(args, file, path)?;
+        timings.lap("components");
+        return Ok(Analyzed {
+            analysis: Some(analysis),
+            start,
+            timings,
+        });
+    }
+    let decoded = load_input(args, file)?;
+    timings.lap("decode");
+    analyze_decoded(args, dark, decoded, start, timings)
+}
+
+fn analyze_decoded(
+    args: &Args,
+    dark: Option<&DarkFrame>,
+    decoded: DynamicImage,
+    start: Instant,
+    mut timings: Timings,
+) -> Result<Analyzed> {
+    let analysis = analyze_image(args, dark, decoded, &mut timings)?;
+    Ok(Analyzed {
+        analysis,
+        start,
+        timings,
+    })
+}
+
+/// Reports the stars found in a file and writes its outputs, returning their count. There is
+/// no count when only the histogram was printed
+fn report_file(
+    args: &Args,
+    dark: Option<&DarkFrame>,
+    file: &str,
+    analyzed: Analyzed,
+) -> Result<Option<u64>> {
+    let Analyzed {
+        analysis,
+        start,
+        mut timings,
+    } = analyzed;
+    let Some(analysis) = analysis else {
+        if args.timings {
+            timings.print(None);
+        }
+        return Ok(None);
+    };
+    let Analysis {
+        full,
+        width,
+        height,
+        stats,
+        stars,
+        sensitivity,
+        bit_depth,
+        searched_pixels,
+        merged,
+        limited,
+        brighter,
+        fwhm,
+    } = analysis;
+    let comparison = args
+        .compare
+        .as_deref()
+        .map(|other| compare(args, dark, other, &stats))
+        .transpose()?;
+    let reported = reported_stats(args, &stats, height);
+    let report = FileReport {
+        file,
+        width,
+        height,
+        sensitivity,
+        bit_depth,
+        searched_pixels,
+        stats: &reported,
+        merged,
+        limited,
+        comparison: comparison.as_ref(),
+        brighter,
+        fingerprint: args.fingerprint.then(|| stats.fingerprint()),
+        fwhm,
+        megapixels_per_second: args
+            .timings
+            .then(|| timings.megapixels_per_second(searched_pixels))
+            .flatten(),
+        grid: args
+            .grid
+            .as_deref()
+            .map(|grid| stats.grid_counts(width as usize, height as usize, grid[0], grid[1])),
+        rois: roi_counts(args, &stats, width, height)?,
+        largest: args
+            .report_largest
+            .map(|n| largest_stars(args, &stats, n, height)),
+    };
+    debug!(
+        "star-counter {} counted {file} with {}",
+        env!("CARGO_PKG_VERSION"),
+        serde_json::to_string(&JsonParameters::new(args, sensitivity, bit_depth))?
+    );
+    match args.format {
+        Format::Text if args.count_only => println!("{}", stats.count),
+        Format::Text => print_text(args, &report),
+        Format::Json => print_json(args, &report)?,
+        Format::Jsonl => print_json_lines(args, &report)?,
+    }
+    timings.lap("report");
+    write_outputs(
+        args,
+        file,
+        full.as_ref(),
+        height,
+        &stats,
+        stars.as_ref(),
+        sensitivity,
+    )?;
+    timings.lap("render");
+    if args.timings {
+        timings.print(Some(searched_pixels));
+    }
+    debug!("Processed {file} in {:.2?}", start.elapsed());
+    Ok(Some(stats.count))
+}
+
+/// Writes every output file that was asked for from the single detection pass. A failed
+/// output doesn't stop the others from being written, and every failure is reported
+fn write_outputs(
+    args: &Args,
+    file: &str,
+    full: Option<&GrayImage>,
+    height: u32,
+    stats: &StarStats,
+    stars: Option<&Bitmap>,
+    sensitivity: u16,
+) -> Result<()> {
+    let mut errors = Vec::new();
+    let mut output = |result: Result<()>| errors.extend(result.err());
+    if args.output_image {
+        let params = args.embed_params.then(|| {
+            vec![
+                (
+                    "Software",
+                    format!("star-counter {}", env!("CARGO_PKG_VERSION")),
+                ),
+                ("Sensitivity", sensitivity.to_string()),
+                ("Connectivity", connectivity(args).to_string()),
+                ("Min size", args.min_size.to_string()),
+                ("Star count", stats.count.to_string()),
+            ]
+        });
+        output(write_output_image(
+            args,
+            file,
+            stars,
+            full,
+            params.as_deref(),
+        ));
+    }
+    if let Some(label_output) = &args.label_output {
+        let stars = stars.expect("--tile-height conflicts with --label-output");
+        let labels = label_groups(stars, connectivity(args)).sorted_by_centroid();
+        let rendered = match args.color_seed {
+            Some(seed) => DynamicImage::ImageRgb8(render_label_colors(&labels, seed)),
+            None => render_labels(&labels),
+        };
+        output(
+            rendered
+                .save(label_output)
+                .with_context(|| format!("Failed to save {}", label_output.display())),
+        );
+    }
+    if let Some(thumbnails) = &args.thumbnails {
+        let full = full.expect("--load-grid conflicts with --thumbnails");
+        output(write_thumbnails(thumbnails, file, full, stats));
+    }
+    if args.annotate {
+        let full = full.expect("--load-grid conflicts with --annotate");
+        output(write_annotated(args, file, full, stats));
+    }
+    if args.mark_centroids {
+        let full = full.expect("--load-grid conflicts with --mark-centroids");
+        output(write_marked_centroids(args, file, full, stats));
+    }
+    if let Some(density_map_path) = &args.density_map {
+        let full = full.expect("--load-grid conflicts with --density-map");
+        output(
+            density_map(full, stats, args.density_cell)
+                .save(density_map_path)
+                .with_context(|| format!("Failed to save {}", density_map_path.display())),
+        );
+    }
+    let reported = reported_stats(args, stats, height);
+    if let Some(csv) = &args.csv {
+        output(
+            write_csv(csv, &reported, args.subpixel)
+                .with_context(|| format!("Failed to write {}", csv.display())),
+        );
+    }
+    if let Some(npy) = &args.npy {
+        output(
+            write_npy(npy, &reported, args.subpixel)
+                .with_context(|| format!("Failed to write {}", npy.display())),
+        );
+    }
+    if let Some(catalog) = &args.catalog {
+        output(
+            write_catalog(catalog, &reported, args.origin)
+                .with_context(|| format!("Failed to write {}", catalog.display())),
+        );
+    }
+    if let Some(regions) = &args.regions {
+        output(
+            write_regions(regions, stats, height)
+                .with_context(|| format!("Failed to write {}", regions.display())),
+        );
+    }
+
+    let Some(last) = errors.pop() else {
+        return Ok(());
+    };
+    for err in errors {
+        eprintln!("Error: {err:#}");
+    }
+    Err(last)
+}
+
+/// Writes the high contrast image of `--output-image`, with the parameters that produced it
+/// in its metadata when given
+fn write_output_image(
+    args: &Args,
+    file: &str,
+    stars: Option<&Bitmap>,
+    full: Option<&GrayImage>,
+    params: Option<&[(&str, String)]>,
+) -> Result<()> {
+    info!("Processing into output...");
+    let stars = stars.expect("--tile-height conflicts with --output-image");
+    let preview;
+    let stars = match args.preview_scale.filter(|&factor| factor > 1) {
+        Some(factor) => {
+            preview = shrink_bitmap(stars, factor);
+            &preview
+        }
+        None => stars,
+    };
+    let output = if let Some(opacity) = args.overlay_opacity {
+        let full = full.expect("--load-grid conflicts with --overlay-opacity");
+        let color = args
+            .star_color
+            .map_or(Rgb([255, 0, 0]), |color| color.to_rgb());
+        let base = overlay_base(args, full);
+        DynamicImage::ImageRgb8(overlay(&base, stars, color, opacity))
+    } else if args.star_color.is_some() || args.bg_color.is_some() {
+        let star = args.star_color.unwrap_or(Rgba([255, 255, 255, 255]));
+        let background = args.bg_color.unwrap_or(Rgba([0, 0, 0, 255]));
+        let output = DynamicImage::ImageRgba8(colorize(stars, star, background));
+        // Formats like jpg can't store alpha, so only keep it when it is used
+        if star.0[3] == u8::MAX && background.0[3] == u8::MAX {
+            DynamicImage::ImageRgb8(output.into_rgb8())
+        } else {
+            output
+        }
+    } else {
+        DynamicImage::ImageLuma8(convert_to_image(stars))
+    };
+    let output_file_name = if let Some(output_name) = &args.output_name {
+        OutputFileName::Custom(output_name.clone())
+    } else {
+        OutputFileName::FromOriginal(file.to_string())
+    };
+    let output_path = create_output_path(
+        output_file_name,
+        "starred",
+        args.output_dir.as_deref(),
+        args.output_format,
+    )?;
+    save_output(args, &output, &output_path, params.unwrap_or_default())
+        .with_context(|| format!("Failed to save {}", output_path.display()))?;
+    info!("Done!");
+    Ok(())
+}
+
+/// The original image cropped and shrunk like the bitmap of the stars, which is searched in the
+/// --roi at the --downscale and written at the --preview-scale
+fn overlay_base(args: &Args, full: &GrayImage) -> GrayImage {
+    let cropped = match args.crop() {
+        Some(roi) => imageops::crop_imm(full, roi.x, roi.y, roi.width, roi.height).to_image(),
+        None => full.clone(),
+    };
+    let factor = args.downscale.unwrap_or(1) * args.preview_scale.unwrap_or(1);
+    if factor > 1 {
+        downscale(&cropped, factor)
+    } else {
+        cropped
+    }
+}
+
+/// Saves the image with the quality or compression that was asked for, and the parameters in
+/// its metadata unless there are none
+fn save_output(
+    args: &Args,
+    img: &DynamicImage,
+    path: &Path,
+    params: &[(&str, String)],
+) -> Result<()> {
+    match ImageFormat::from_path(path)? {
+        ImageFormat::Png => {
+            let rgba;
+            let (color, data) = match img {
+                DynamicImage::ImageLuma8(img) => (png::ColorType::Grayscale, img.as_raw()),
+                DynamicImage::ImageRgb8(img) => (png::ColorType::Rgb, img.as_raw()),
+                img => {
+                    rgba = img.to_rgba8();
+                    (png::ColorType::Rgba, rgba.as_raw())
+                }
+            };
+            let writer = BufWriter::new(File::create(path)?);
+            let mut encoder = png::Encoder::new(writer, img.width(), img.height());
+            encoder.set_color(color);
+            encoder.set_depth(png::BitDepth::Eight);
+            encoder.set_compression(match args.png_compression {
+                PngCompression::Fast => png::Compression::Fast,
+                PngCompression::Balanced => png::Compression::Default,
+                PngCompression::Best => png::Compression::Best,
+            });
+            // Filters like the image crate does, so the default output is unchanged
+            encoder.set_filter(png::FilterType::Sub);
+            encoder.set_adaptive_filter(png::AdaptiveFilterType::Adaptive);
+            for (key, value) in params {
+                encoder.add_text_chunk(key.to_string(), value.clone())?;
+            }
+            encoder.write_header()?.write_image_data(data)?;
+        }
+        ImageFormat::Jpeg => {
+            let mut data = Vec::new();
+            JpegEncoder::new_with_quality(&mut data, args.output_quality).encode(
+                img.as_bytes(),
+                img.width(),
+                img.height(),
+                img.color(),
+            )?;
+            if params.is_empty() {
+                fs::write(path, data)?;
+                return Ok(());
+            }
+            let comment: Vec<String> = params
+                .iter()
+                .map(|(key, value)| format!("{key}={value}"))
+                .collect();
+            let comment = comment.join("; ");
+            // A COM segment right after the start of image marker, its length counting itself
+            let length = u16::try_from(comment.len() + 2).context("Parameters are too long")?;
+            let mut segment = vec![0xFF, 0xFE];
+            segment.extend(length.to_be_bytes());
+            segment.extend(comment.as_bytes());
+            data.splice(2..2, segment);
+            fs::write(path, data)?;
+        }
+        _ if params.is_empty() => save_image(img, path)?,
+        _ => bail!("--embed-params can only write png or jpeg"),
+    }
+    Ok(())
+}
+
+/// Saves the image in the format of the extension of the path, writing pgm and ppm by hand
+fn save_image(img: &DynamicImage, path: &Path) -> Result<()> {
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_ascii_lowercase());
+    let (magic, data) = match extension.as_ref().and_then(|extension| extension.to_str()) {
+        Some("pgm") => ("P5", img.to_luma8().into_raw()),
+        Some("ppm") => ("P6", img.to_rgb8().into_raw()),
+        _ => return Ok(img.save(path)?),
+    };
+    let mut writer = BufWriter::new(File::create(path)?);
+    write!(writer, "{magic}\n{} {}\n255\n", img.width(), img.height())?;
+    writer.write_all(&data)?;
+    writer.flush()?;
+    Ok(())
+}
+
+fn write_annotated(args: &Args, file: &str, full: &GrayImage, stats: &StarStats) -> Result<()> {
+    let output_path = create_output_path(
+        OutputFileName::FromOriginal(file.to_string()),
+        "annotated",
+        args.output_dir.as_deref(),
+        args.output_format,
+    )?;
+    let annotated = match args.preview_scale.filter(|&factor| factor > 1) {
+        Some(factor) => {
+            let mut stats = stats.clone();
+            let scale = 1.0 / factor as f64;
+            stats.scale(scale, scale);
+            annotate(&downscale(full, factor), &stats)
+        }
+        None => annotate(full, stats),
+    };
+    save_image(&DynamicImage::ImageRgb8(annotated), &output_path)
+        .with_context(|| format!("Failed to save {}", output_path.display()))
+}
+
+/// Writes the image with crosshairs at the stars, numbered like the ids of --csv when asked to,
+/// so only the --brightest are marked when it is given
+fn write_marked_centroids(
+    args: &Args,
+    file: &str,
+    full: &GrayImage,
+    stats: &StarStats,
+) -> Result<()> {
+    let output_path = create_output_path(
+        OutputFileName::FromOriginal(file.to_string()),
+        "centroids",
+        args.output_dir.as_deref(),
+        args.output_format,
+    )?;
+    let mut stats = Cow::Borrowed(stats);
+    if let Some(n) = args.brightest {
+        stats.to_mut().stars = stats.brightest(n);
+    }
+    let marked = match args.preview_scale.filter(|&factor| factor > 1) {
+        Some(factor) => {
+            let scale = 1.0 / factor as f64;
+            stats.to_mut().scale(scale, scale);
+            mark_centroids(&downscale(full, factor), &stats, args.label_ids)
+        }
+        None => mark_centroids(full, &stats, args.label_ids),
+    };
+    save_image(&DynamicImage::ImageRgb8(marked), &output_path)
+        .with_context(|| format!("Failed to save {}", output_path.display()))
+}
+
+/// Durations of the processing stages of a single file, in the order they ran
+struct Timings {
+    last: Instant,
+    stages: Vec<(&'static str, Duration)>,
+}
+
+impl Timings {
+    fn new() -> Self {
+        Timings {
+            last: Instant::now(),
+            stages: Vec::new(),
+        }
+    }
+
+    /// Records the time since the previous stage ended as the duration of `stage`
+    fn lap(&mut self, stage: &'static str) {
+        let now = Instant::now();
+        self.stages.push((stage, now - self.last));
+        self.last = now;
+    }
+
+    /// Time spent thresholding the image and labelling its stars
+    fn detection(&self) -> Duration {
+        self.stages
+            .iter()
+            .filter(|(stage, _)| matches!(*stage, "threshold" | "components"))
+            .map(|(_, duration)| *duration)
+            .sum()
+    }
+
+    /// Millions of `pixels` that were searched per second of detection, or `None` when it took
+    /// no measurable time
+    fn megapixels_per_second(&self, pixels: u64) -> Option<f64> {
+        let seconds = self.detection().as_secs_f64();
+        (seconds > 0.0).then(|| pixels as f64 / 1e6 / seconds)
+    }
+
+    /// Prints every stage and the total as a table to stderr, followed by the throughput of
+    /// the detection when the number of searched `pixels` is known
+    fn print(&self, pixels: Option<u64>) {
+        let total: Duration = self.stages.iter().map(|(_, duration)| *duration).sum();
+        eprintln!("{:<12} {:>12}", "stage", "time");
+        for (stage, duration) in self.stages.iter().chain([&("total", total)]) {
+            eprintln!("{stage:<12} {:>12}", format!("{duration:.2?}"));
+        }
+        if let Some(rate) = pixels.and_then(|pixels| self.megapixels_per_second(pixels)) {
+            eprintln!("{:<12} {:>12}", "mpix/s", format!("{rate:.1}"));
+        }
+    }
+}
+
+/// Everything needed to find the stars in an image besides the image and its threshold, which
+/// differ between 8 and 16-bit images
+struct Detection<'a> {
+    args: &'a Args,
+    options: &'a DetectOptions,
+    /// The image stars are measured on
+    luma: &'a GrayImage,
+    /// Pixels that can be part of a star, matching the region of `luma`
+    mask: Option<&'a Bitmap>,
+    /// Pixels of `luma` that are never part of a star
+    bad_pixels: &'a [(usize, usize)],
+    progress: bool,
+    /// When to give up for --timeout-seconds
+    deadline: Option<Instant>,
+}
+
+impl Detection<'_> {
+    /// The stars and, unless the image was processed in strips, the bitmap of their pixels.
+    /// With a `high` level only the groups that reach above it are kept
+    fn run<T: Primitive + Send + Sync>(
+        &self,
+        img: &ImageBuffer<Luma<T>, Vec<T>>,
+        threshold: Threshold<T>,
+        high: Option<T>,
+        timings: &mut Timings,
+    ) -> Result<(StarStats, Option<Bitmap>)> {
+        if self
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            bail!(timed_out(self.args, "before thresholding"));
+        }
+        if let Some(tile_height) = self.args.tile_height {
+            let stats =
+                count_groups_tiled(img, threshold, Some(self.luma), self.options, tile_height);
+            timings.lap("components");
+            return Ok((stats, None));
+        }
+
+        // Parse it to an array of bools, easier to work with
+        let rows = img.height() as u64;
+        let bar = progress_bar(self.progress, rows, "Thresholding");
+        let mut stars = if let Some(window) = self.args.adaptive {
+            adaptive_threshold(img, window, threshold)
+        } else {
+            threshold_image_with_progress(img, threshold, || bar.inc(1))
+        };
+        bar.finish_and_clear();
+        if let Some(high) = high {
+            let strong = threshold_image(
+                img,
+                Threshold {
+                    sensitivity: high,
+                    ..threshold
+                },
+            );
+            stars = hysteresis(&stars, &strong, self.options.connectivity);
+        }
+        if let Some(radius) = self.args.erode {
+            stars = morphology::erode(&stars, radius);
+        }
+        if let Some(radius) = self.args.dilate {
+            stars = morphology::dilate(&stars, radius);
+        }
+        if let Some(bridge) = self.args.bridge {
+            stars = morphology::close(&stars, bridge);
+        }
+        if let Some(mask) = self.mask {
+            stars.intersect(mask);
+        }
+        for &(x, y) in self.bad_pixels {
+            stars.set(x, y, false);
+        }
+        timings.lap("threshold");
+        if let Some(path) = &self.args.cache_grid {
+            let grid = Grid {
+                stars,
+                sensitivity: threshold.sensitivity.to_u16().unwrap_or(0),
+                bit_depth: (mem::size_of::<T>() * 8) as u8,
+            };
+            write_grid(path, &grid)
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+            stars = grid.stars;
+        }
+        if let Some(path) = &self.args.threshold_map {
+            write_threshold_map(path, &stars)?;
+        }
+        if let Some(dir) = &self.args.trace_image {
+            write_trace(
+                dir,
+                &stars,
+                self.options.connectivity,
+                self.args.trace_every,
+            )?;
+            timings.lap("trace");
+        }
+
+        let stats = if let Some(min_distance) = self.args.split_peaks {
+            split_peaks(&mut stars, self.luma, self.options, min_distance)
+        } else {
+            let bar = progress_bar(self.progress, rows, "Counting");
+            let stats = count_stars(
+                self.args,
+                &mut stars,
+                Some(self.luma),
+                self.options,
+                self.deadline,
+                || bar.inc(1),
+            )?;
+            bar.finish_and_clear();
+            stats
+        };
+        timings.lap("components");
+        Ok((stats, Some(stars)))
+    }
+}
+
+/// Counts the groups of the bitmap, failing when there are more than --max-stars, or only how
+/// many there are with --count-connected-components-only
+fn count_stars(
+    args: &Args,
+    stars: &mut Bitmap,
+    luma: Option<&GrayImage>,
+    options: &DetectOptions,
+    deadline: Option<Instant>,
+    on_row: impl FnMut(),
+) -> Result<StarStats> {
+    if args.count_connected_components_only {
+        let count = count_components(stars, options.connectivity) as u64;
+        return Ok(StarStats {
+            count,
+            stars: Vec::new(),
+        });
+    }
+    if args.max_stars.is_none() && deadline.is_none() {
+        return Ok(count_groups_with_progress(stars, luma, options, on_row));
+    }
+    try_count_groups_until(stars, luma, options, args.max_stars, deadline, on_row).map_err(|err| {
+        match err {
+            CountError::TooManyGroups(err) => {
+                anyhow!("Stopped counting, {err}. Try a higher --sensitivity")
+            }
+            CountError::PastDeadline(err) => anyhow!(timed_out(args, &err.to_string())),
+        }
+    })
+}
+
+/// When --timeout-seconds gives up on an image that started being processed now
+fn deadline(args: &Args) -> Option<Instant> {
+    args.timeout_seconds
+        .map(|seconds| Instant::now() + Duration::from_secs_f64(seconds))
+}
+
+/// The error of giving up for --timeout-seconds, with what was done when it happened
+fn timed_out(args: &Args, when: &str) -> String {
+    format!(
+        "Gave up after --timeout-seconds {}, {when}",
+        args.timeout_seconds.unwrap_or_default()
+    )
+}
+
+/// Saves the pixels that passed the threshold for --threshold-map
+fn write_threshold_map(path: &Path, stars: &Bitmap) -> Result<()> {
+    convert_to_image(stars)
+        .save(path)
+        .with_context(|| format!("Failed to save {}", path.display()))
+}
+
+/// Saves the snapshots of the flood fill for --trace-image
+fn write_trace(dir: &Path, stars: &Bitmap, connectivity: Connectivity, every: usize) -> Result<()> {
+    fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    let mut written = 0;
+    let mut result = Ok(());
+    trace_groups(stars, connectivity, every, |visited| {
+        if result.is_err() {
+            return;
+        }
+        written += 1;
+        let path = dir.join(format!("trace-{written:05}.png"));
+        result = convert_to_image(visited)
+            .save(&path)
+            .with_context(|| format!("Failed to save {}", path.display()));
+    });
+    result?;
+    info!("Saved {written} trace images to {}", dir.display());
+    Ok(())
+}
+
+/// Pixels of the image around the bounding box of every star in its thumbnail
+const THUMBNAIL_PADDING: u32 = 4;
+
+/// Saves a crop of the full image around each star as `<file name>-<id>.png` in `dir`
+fn write_thumbnails(dir: &Path, file: &str, full: &GrayImage, stats: &StarStats) -> Result<()> {
+    fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    let stem = if file == STDIN_PATH {
+        "stdin".as_ref()
+    } else {
+        Path::new(file)
+            .file_stem()
+            .with_context(|| format!("{file} has no file name"))?
+    };
+    for (id, star) in stats.stars.iter().enumerate() {
+        let mut file_name = stem.to_os_string();
+        file_name.push(format!("-{}.png", id + 1));
+        let path = dir.join(file_name);
+        thumbnail(full, star, THUMBNAIL_PADDING)
+            .save(&path)
+            .with_context(|| format!("Failed to save {}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// The stars that are reported, which are only the --brightest if given, with the coordinates
+/// they are reported in, which are flipped for `--origin bottom-left` in an image that is
+/// `height` pixels high
+fn reported_stats<'a>(args: &Args, stats: &'a StarStats, height: u32) -> Cow<'a, StarStats> {
+    let mut reported = Cow::Borrowed(stats);
+    if let Some(n) = args.brightest {
+        reported.to_mut().stars = stats.brightest(n);
+    }
+    if args.origin == Origin::BottomLeft {
+        reported.to_mut().flip_vertically(height as usize);
+    }
+    reported
+}
+
+/// The `n` largest of every star, flipped like [`reported_stats`] for `--origin bottom-left`
+fn largest_stars(args: &Args, stats: &StarStats, n: usize, height: u32) -> Vec<Star> {
+    let mut largest = StarStats {
+        count: stats.count,
+        stars: stats.largest(n),
+    };
+    if args.origin == Origin::BottomLeft {
+        largest.flip_vertically(height as usize);
+    }
+    largest.stars
+}
+
+/// Writes a whitespace delimited source list, using the brightness weighted centroids
+fn write_catalog(path: &Path, stats: &StarStats, origin: Origin) -> Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    let (corner, direction, rotation) = match origin {
+        Origin::TopLeft => ("top-left", "down", "clockwise"),
+        Origin::BottomLeft => ("bottom-left", "up", "counterclockwise"),
+    };
+    writeln!(
+        writer,
+        "# id x y flux area axis_ratio angle (pixels, origin at the centre of the {corner} \
+         pixel, y {direction}, angle in degrees {rotation} from x)"
+    )?;
+    for (id, star) in stats.stars.iter().enumerate() {
+        let (x, y) = star.weighted_centroid;
+        writeln!(
+            writer,
+            "{} {x:.3} {y:.3} {} {} {:.3} {:.1}",
+            id + 1,
+            star.brightness,
+            star.area,
+            star.axis_ratio(),
+            star.orientation()
+        )?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Writes a DS9 region file in image coordinates, flipping `y` to count up from the bottom row
+/// of an image that is `height` pixels high
+fn write_regions(path: &Path, stats: &StarStats, height: u32) -> Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    writeln!(writer, "# Region file format: DS9")?;
+    writeln!(writer, "image")?;
+    for star in &stats.stars {
+        let (x, y) = star.weighted_centroid;
+        // The centre of pixel (0, 0) at the top-left is (1, height) in DS9
+        let (x, y) = (x + 1.0, height as f64 - y);
+        let (major, minor) = star.semi_axes();
+        // Counterclockwise with y pointing up is clockwise on the image like the orientation
+        let angle = (180.0 - star.orientation()) % 180.0;
+        writeln!(
+            writer,
+            "ellipse({x:.3},{y:.3},{major:.3},{minor:.3},{angle:.3})"
+        )?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Prints a bar for every luma value that occurs in the image and the Otsu threshold
+fn print_histogram(img: &GrayImage) {
+    const BAR_WIDTH: u64 = 60;
+    let histogram = histogram(img);
+    let highest = histogram.iter().copied().max().unwrap_or(0).max(1);
+    for (value, &count) in histogram.iter().enumerate() {
+        if count == 0 {
+            continue;
+        }
+        let bar = "#".repeat((count * BAR_WIDTH).div_ceil(highest) as usize);
+        println!("{value:>3} {count:>10} {bar}");
+    }
+    println!(
+        "Suggested sensitivity (Otsu): {}",
+        otsu_threshold_from_histogram(&histogram)
+    );
+}
+
+fn print_background(img: &GrayImage, k: f64) {
+    let background = estimate_background(img);
+    println!(
+        "Sky background: median {}, MAD {}, sigma {:.2}",
+        background.median, background.mad, background.sigma
+    );
+    println!(
+        "Suggested sensitivity (median + {k} sigma): {}",
+        background.suggested_sensitivity(k)
+    );
+}
+
+/// Prints the star count at every sensitivity of --sweep, thresholding the 16-bit image
+/// when there is one
+fn sweep(
+    detection: &Detection,
+    img: &GrayImage,
+    img16: Option<&ImageBuffer<Luma<u16>, Vec<u16>>>,
+    levels: &[u8],
+    timings: &mut Timings,
+) -> Result<()> {
+    let &[start, end, step] = levels else {
+        unreachable!("--sweep takes three values");
+    };
+    let args = detection.args;
+    println!("sensitivity -> count");
+    for level in (start..=end).step_by(step as usize) {
+        let sensitivity = Sensitivity::Level(level);
+        let (mut stats, _) = if let Some(img16) = img16 {
+            let threshold = Threshold {
+                invert: args.invert,
+                ..Threshold::new(sensitivity.to_u16())
+            };
+            detection.run(img16, threshold, None, timings)?
+        } else {
+            let threshold = Threshold {
+                invert: args.invert,
+                ..Threshold::new(sensitivity.to_u8())
+            };
+            detection.run(img, threshold, None, timings)?
+        };
+        place_stars(args, &mut stats);
+        println!("{level:>11} -> {}", stats.count);
+    }
+    Ok(())
+}
+
+/// Prints the sensitivity whose count is closest to `target`, searching every level of the
+/// 16-bit image when there is one
+///
+/// Of two levels equally close, the higher one is taken, being less likely to count noise.
+fn solve_for_count(
+    detection: &Detection,
+    img: &GrayImage,
+    img16: Option<&ImageBuffer<Luma<u16>, Vec<u16>>>,
+    target: u64,
+    timings: &mut Timings,
+) -> Result<()> {
+    let args = detection.args;
+    let max = if img16.is_some() {
+        u16::MAX
+    } else {
+        u8::MAX as u16
+    };
+    // Inverted, the count only drops as the sensitivity falls, so the search runs downwards
+    let level = |step: u16| if args.invert { max - step } else { step };
+    let mut counts = HashMap::new();
+    let mut count_at = |step: u16| -> Result<u64> {
+        if let Some(&count) = counts.get(&step) {
+            return Ok(count);
+        }
+        let level = level(step);
+        let (mut stats, _) = if let Some(img16) = img16 {
+            let threshold = Threshold {
+                invert: args.invert,
+                ..Threshold::new(level)
+            };
+            detection.run(img16, threshold, None, timings)?
+        } else {
+            let threshold = Threshold {
+                invert: args.invert,
+                ..Threshold::new(level as u8)
+            };
+            detection.run(img, threshold, None, timings)?
+        };
+        place_stars(args, &mut stats);
+        debug!("Sensitivity {level} of {max} counts {} stars", stats.count);
+        counts.insert(step, stats.count);
+        Ok(stats.count)
+    };
+    // The first step that counts at most the target, or the last one when all count more
+    let (mut low, mut high) = (0, max);
+    while low < high {
+        let middle = low + (high - low) / 2;
+        if count_at(middle)? <= target {
+            high = middle;
+        } else {
+            low = middle + 1;
+        }
+    }
+    let mut best = (low, count_at(low)?);
+    if low > 0 {
+        let count = count_at(low - 1)?;
+        if count.abs_diff(target) < best.1.abs_diff(target) {
+            best = (low - 1, count);
+        }
+    }
+    let (step, count) = best;
+    let level = level(step);
+    // A level that 8 bits can't resolve is given as a fraction, which is read back exactly
+    let sensitivity = if img16.is_none() {
+        Sensitivity::Level(level as u8)
+    } else if level % 257 == 0 {
+        Sensitivity::Level((level / 257) as u8)
+    } else {
+        Sensitivity::Fraction(level as f64 / u16::MAX as f64)
+    };
+    println!("Sensitivity {sensitivity} counts {count} stars, the closest to {target}");
+    Ok(())
+}
+
+/// Progress bar over the rows of the image, hidden when `show` is false
+fn progress_bar(show: bool, rows: u64, message: &'static str) -> ProgressBar {
+    if !show {
+        return ProgressBar::hidden();
+    }
+    ProgressBar::new(rows)
+        .with_style(
+            ProgressStyle::with_template("{msg} [{elapsed_precise}] {wide_bar} {pos}/{len} rows")
+                .expect("Progress bar template is valid"),
+        )
+        .with_message(message)
+}
+
+fn write_csv(path: &Path, stats: &StarStats, subpixel: bool) -> Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    writeln!(writer, "id,x,y,area,brightness,x_min,y_min,x_max,y_max")?;
+    for (id, star) in stats.stars.iter().enumerate() {
+        let id = id + 1;
+        if subpixel {
+            let (x, y) = star.weighted_centroid;
+            write!(
+                writer,
+                "{id},{x:.3},{y:.3},{},{}",
+                star.area, star.brightness
+            )?;
+        } else {
+            let (x, y) = star.centroid;
+            write!(writer, "{id},{x},{y},{},{}", star.area, star.brightness)?;
+        }
+        let bounding_box = star.bounding_box;
+        writeln!(
+            writer,
+            ",{},{},{},{}",
+            bounding_box.x_min, bounding_box.y_min, bounding_box.x_max, bounding_box.y_max
+        )?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Writes the stars as an `n` by 4 array in version 1.0 of the .npy format, which is a
+/// magic string, the length of the header, the header as a Python dict literal padded so the
+/// data starts at a multiple of 64 bytes, and the little-endian values row by row
+fn write_npy(path: &Path, stats: &StarStats, subpixel: bool) -> Result<()> {
+    const MAGIC: &[u8] = b"\x93NUMPY\x01\x00";
+    let mut header = format!(
+        "{{'descr': '<f8', 'fortran_order': False, 'shape': ({}, 4), }}",
+        stats.stars.len()
+    );
+    // The magic string and the two bytes of the header length come before it
+    let unpadded = MAGIC.len() + 2 + header.len() + 1;
+    header.extend(iter::repeat_n(
+        ' ',
+        unpadded.next_multiple_of(64) - unpadded,
+    ));
+    header.push('\n');
+    let header_len = u16::try_from(header.len()).context("Header of the .npy file is too long")?;
+
+    let mut writer = BufWriter::new(File::create(path)?);
+    writer.write_all(MAGIC)?;
+    writer.write_all(&header_len.to_le_bytes())?;
+    writer.write_all(header.as_bytes())?;
+    for star in &stats.stars {
+        let (x, y) = if subpixel {
+            star.weighted_centroid
+        } else {
+            (star.centroid.0 as f64, star.centroid.1 as f64)
+        };
+        for value in [x, y, star.area as f64, star.brightness as f64] {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Everything that is reported about a single processed file
+struct FileReport<'a> {
+    file: &'a str,
+    width: u32,
+    height: u32,
+    /// Sensitivity that was compared against, in range of the bit depth
+    sensitivity: u16,
+    bit_depth: u8,
+    /// Number of pixels that were searched for stars, less than the image when using --roi
+    searched_pixels: u64,
+    stats: &'a StarStats,
+    /// Number of merges of close stars, when --merge-radius was given
+    merged: Option<usize>,
+    /// Whether counting stopped at --limit
+    limited: bool,
+    comparison: Option<&'a Comparison>,
+    /// Number of stars brighter than the one at --brighter-than
+    brighter: Option<u64>,
+    /// Of every star, not only those reported, when --fingerprint was given
+    fingerprint: Option<String>,
+    fwhm: Option<Fwhm>,
+    /// Throughput of the detection, with --timings
+    megapixels_per_second: Option<f64>,
+    /// Star counts of the --grid cells, row by row
+    grid: Option<Vec<Vec<u64>>>,
+    /// Star counts of the named --roi regions, in the order they were given
+    rois: Vec<(&'a str, Roi, u64)>,
+    /// The --report-largest stars, in the reported coordinates
+    largest: Option<Vec<Star>>,
+}
+
+/// Counts the stars whose centroids are inside each named --roi, checking that it fits the
+/// image
+fn roi_counts<'a>(
+    args: &'a Args,
+    stats: &StarStats,
+    width: u32,
+    height: u32,
+) -> Result<Vec<(&'a str, Roi, u64)>> {
+    args.roi
+        .iter()
+        .filter_map(|roi| Some((roi.name.as_deref()?, roi.roi)))
+        .map(|(name, roi)| {
+            roi.check_bounds(width, height)
+                .with_context(|| format!("Can't count the stars in --roi {name}"))?;
+            let count = stats
+                .stars
+                .iter()
+                .filter(|star| roi.contains(star.centroid))
+                .count();
+            Ok((name, roi, count as u64))
+        })
+        .collect()
+}
+
+/// Stars of an image matched against those of the --compare image
+struct Comparison {
+    other: String,
+    /// Stars found in the other image
+    stars: Vec<Star>,
+    matches: StarMatches,
+}
+
+impl Comparison {
+    /// Stars that are only in the other image
+    fn appeared(&self) -> impl Iterator<Item = &Star> {
+        self.matches
+            .unmatched_second
+            .iter()
+            .map(|&index| &self.stars[index])
+    }
+}
+
+/// Finds the stars of `other` with the same settings and matches them against `stats`
+fn compare(
+    args: &Args,
+    dark: Option<&DarkFrame>,
+    other: &str,
+    stats: &StarStats,
+) -> Result<Comparison> {
+    let analysis = analyze(args, dark, other, &mut Timings::new())?
+        .expect("--compare conflicts with --histogram");
+    let matches = match_stars(&stats.stars, &analysis.stats.stars, args.match_radius);
+    let stars = reported_stats(args, &analysis.stats, analysis.height)
+        .into_owned()
+        .stars;
+    Ok(Comparison {
+        other: other.to_string(),
+        stars,
+        matches,
+    })
+}
+
+impl FileReport<'_> {
+    fn stars_per_megapixel(&self) -> f64 {
+        if self.searched_pixels == 0 {
+            return 0.0;
+        }
+        self.stats.count as f64 / self.searched_pixels as f64 * 1_000_000.0
+    }
+
+    /// Number of stars that reach white
+    fn saturated(&self) -> usize {
+        self.stats
+            .stars
+            .iter()
+            .filter(|star| star.is_saturated())
+            .count()
+    }
+}
+
+fn print_text(args: &Args, report: &FileReport) {
+    let stats = report.stats;
+    if report.limited {
+        println!("Found at least {} stars", stats.count);
+    } else {
+        println!("Found {} stars", stats.count);
+    }
+    if !args.quiet {
+        println!("{:.2} stars per megapixel", report.stars_per_megapixel());
+        print_size_summary(&stats.sizes());
+        let saturated = report.saturated();
+        if saturated > 0 {
+            println!("Saturated stars: {saturated}");
+        }
+        if let Some(merged) = report.merged {
+            println!("Merged close stars: {merged}");
+        }
+    }
+    if let (Some(brighter), Some(at)) = (report.brighter, args.brighter_than) {
+        println!("Stars brighter than the one at {at}: {brighter}");
+    }
+    if args.fwhm {
+        match report.fwhm {
+            Some(fwhm) => println!(
+                "Median FWHM: {:.2} pixels over {} stars",
+                fwhm.median, fwhm.measured
+            ),
+            None => println!("Median FWHM: no star is round and unsaturated enough to measure"),
+        }
+    }
+    if let Some(fingerprint) = &report.fingerprint {
+        println!("Fingerprint: {fingerprint}");
+    }
+    if args.list_centroids {
+        for star in &stats.stars {
+            let mut line = centroid_text(star, args.subpixel);
+            if args.with_area {
+                line += &format!(",{}", star.area);
+            }
+            if args.with_brightness {
+                line += &format!(",{}", star.brightness);
+            }
+            if args.with_bbox {
+                let bounding_box = star.bounding_box;
+                line += &format!(
+                    ",{},{},{},{}",
+                    bounding_box.x_min, bounding_box.y_min, bounding_box.x_max, bounding_box.y_max
+                );
+            }
+            println!("{line}");
+        }
+    }
+    if let Some(grid) = &report.grid {
+        println!("Stars per grid cell:");
+        let cell_width = grid
+            .iter()
+            .flatten()
+            .max()
+            .map_or(1, |max| max.to_string().len());
+        for row in grid {
+            let cells: Vec<String> = row
+                .iter()
+                .map(|count| format!("{count:>cell_width$}"))
+                .collect();
+            println!("{}", cells.join(" "));
+        }
+    }
+    for (name, roi, count) in &report.rois {
+        println!("Stars in {name} ({roi}): {count}");
+    }
+    if let Some(largest) = &report.largest {
+        println!("Largest stars:");
+        for star in largest {
+            let bounding_box = star.bounding_box;
+            println!(
+                "{} pixels at {} in {},{},{},{}",
+                star.area,
+                centroid_text(star, args.subpixel),
+                bounding_box.x_min,
+                bounding_box.y_min,
+                bounding_box.x_max,
+                bounding_box.y_max
+            );
+        }
+    }
+    if let Some(comparison) = report.comparison {
+        let matches = &comparison.matches;
+        println!(
+            "Compared with {}: {} matched, {} appeared, {} disappeared",
+            comparison.other,
+            matches.matched.len(),
+            matches.unmatched_second.len(),
+            matches.unmatched_first.len()
+        );
+        for star in comparison.appeared() {
+            println!("appeared {}", centroid_text(star, args.subpixel));
+        }
+        for &index in &matches.unmatched_first {
+            println!(
+                "disappeared {}",
+                centroid_text(&stats.stars[index], args.subpixel)
+            );
+        }
+    }
+}
+
+/// The centroid of the star as `x,y`
+fn centroid_text(star: &Star, subpixel: bool) -> String {
+    if subpixel {
+        let (x, y) = star.weighted_centroid;
+        format!("{x:.3},{y:.3}")
+    } else {
+        let (x, y) = star.centroid;
+        format!("{x},{y}")
+    }
+}
+
+/// Version of the JSON printed with `--format json` and written by --summary, raised whenever
+/// a field changes meaning or goes away so parsers can tell. New fields don't raise it
+const SCHEMA_VERSION: u32 = 1;
+
+/// Prints a JSON value on a line of its own, or indented with --pretty
+fn print_json_value(args: &Args, value: &impl Serialize) -> Result<()> {
+    let json = if args.pretty {
+        serde_json::to_string_pretty(value)?
+    } else {
+        serde_json::to_string(value)?
+    };
+    println!("{json}");
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct JsonReport<'a> {
+    schema_version: u32,
+    /// Version of star-counter that produced the report
+    version: &'static str,
+    parameters: JsonParameters<'a>,
+    file: &'a str,
+    count: u64,
+    width: u32,
+    height: u32,
+    sensitivity: u16,
+    bit_depth: u8,
+    stars_per_megapixel: f64,
+    saturated: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    merged: Option<usize>,
+    /// Whether counting stopped at --limit, only given with it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    limit_reached: Option<bool>,
+    /// Number of stars brighter than the one at --brighter-than
+    #[serde(skip_serializing_if = "Option::is_none")]
+    brighter: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fingerprint: Option<&'a str>,
+    /// With --fwhm, null when no star could be measured
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fwhm: Option<Option<JsonFwhm>>,
+    /// Searched pixels per second of thresholding and labelling, only given with --timings
+    #[serde(skip_serializing_if = "Option::is_none")]
+    megapixels_per_second: Option<f64>,
+    stars: Vec<JsonStar>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    comparison: Option<JsonComparison<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    grid: Option<&'a [Vec<u64>]>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    rois: Vec<JsonRoi<'a>>,
+    /// The --report-largest stars, largest first
+    #[serde(skip_serializing_if = "Option::is_none")]
+    largest: Option<Vec<JsonStar>>,
+}
+
+/// Star count of a named --roi
+#[derive(Serialize)]
+struct JsonRoi<'a> {
+    name: &'a str,
+    #[serde(flatten)]
+    roi: Roi,
+    count: u64,
+}
+
+impl<'a> JsonRoi<'a> {
+    fn all(report: &FileReport<'a>) -> Vec<Self> {
+        report
+            .rois
+            .iter()
+            .map(|&(name, roi, count)| JsonRoi { name, roi, count })
+            .collect()
+    }
+}
+
+/// Every option that changes which stars are found, with the sensitivities resolved in the bit
+/// depth of the image, so a count can be reproduced from the report alone
+#[derive(Serialize)]
+struct JsonParameters<'a> {
+    /// How the sensitivity was chosen: fixed, otsu, percentile, adaptive or hysteresis
+    threshold: &'static str,
+    sensitivity: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_sensitivity: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    percentile: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    adaptive_window: Option<usize>,
+    /// The high level of --hysteresis, `sensitivity` being the low one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hysteresis_high: Option<u16>,
+    invert: bool,
+    channel: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    gray_weights: Option<String>,
+    gray_method: String,
+    autorotate: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dark: Option<&'a Path>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mask: Option<&'a Path>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    roi: Option<Roi>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    downscale: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    denoise: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detect_blur: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    subtract_background: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    normalize: Option<f64>,
+    stretch: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    erode: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dilate: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bridge: Option<usize>,
+    connectivity: String,
+    wrap: String,
+    min_size: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_size: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_elongation: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_axis_ratio: Option<f64>,
+    exclude_saturated: bool,
+    exclude_border: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    merge_radius: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    split_peaks: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    limit: Option<u64>,
+}
+
+impl<'a> JsonParameters<'a> {
+    /// The parameters of `args`, with the sensitivity that was resolved for the image
+    fn new(args: &'a Args, sensitivity: u16, bit_depth: u8) -> Self {
+        let threshold = if args.auto_threshold {
+            "otsu"
+        } else if args.percentile.is_some() {
+            "percentile"
+        } else if args.adaptive.is_some() {
+            "adaptive"
+        } else if args.hysteresis.is_some() {
+            "hysteresis"
+        } else {
+            "fixed"
+        };
+        JsonParameters {
+            threshold,
+            sensitivity,
+            max_sensitivity: args.max_sensitivity.map(|max| max.in_bit_depth(bit_depth)),
+            percentile: args.percentile,
+            adaptive_window: args.adaptive,
+            hysteresis_high: args
+                .hysteresis
+                .as_deref()
+                .map(|levels| levels[0].in_bit_depth(bit_depth)),
+            invert: args.invert,
+            channel: args.channel.to_string(),
+            gray_weights: args.gray_weights.map(|weights| weights.to_string()),
+            gray_method: args.gray_method.to_string(),
+            autorotate: !args.no_autorotate,
+            dark: args.dark.as_deref(),
+            mask: args.mask.as_deref(),
+            roi: args.crop(),
+            downscale: args.downscale,
+            denoise: args.denoise,
+            detect_blur: args.detect_blur,
+            subtract_background: args.subtract_background,
+            normalize: args.normalize,
+            stretch: args.stretch.to_string(),
+            erode: args.erode,
+            dilate: args.dilate,
+            bridge: args.bridge,
+            connectivity: connectivity(args).to_string(),
+            wrap: args.wrap.to_string(),
+            min_size: args.min_size,
+            max_size: args.max_size,
+            max_elongation: args.max_elongation,
+            max_axis_ratio: args.max_axis_ratio,
+            exclude_saturated: args.exclude_saturated,
+            exclude_border: args.exclude_border,
+            merge_radius: args.merge_radius,
+            split_peaks: args.split_peaks,
+            limit: args.limit,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct JsonComparison<'a> {
+    other: &'a str,
+    matched: usize,
+    appeared: Vec<JsonCentroid>,
+    disappeared: Vec<JsonCentroid>,
+}
+
+#[derive(Serialize)]
+struct JsonCentroid {
+    x: Coordinate,
+    y: Coordinate,
+}
+
+impl JsonCentroid {
+    fn new(star: &Star, subpixel: bool) -> Self {
+        JsonCentroid {
+            x: coordinate(subpixel, star.centroid.0, star.weighted_centroid.0),
+            y: coordinate(subpixel, star.centroid.1, star.weighted_centroid.1),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct JsonStar {
+    x: Coordinate,
+    y: Coordinate,
+    area: usize,
+    brightness: u64,
+    peak: u8,
+    saturated: bool,
+    bounding_box: JsonBoundingBox,
+    moments: JsonMoments,
+    axis_ratio: f64,
+    /// In degrees, clockwise from the `x` axis
+    orientation: f64,
+}
+
+impl JsonStar {
+    fn new(star: &Star, subpixel: bool) -> Self {
+        JsonStar {
+            x: coordinate(subpixel, star.centroid.0, star.weighted_centroid.0),
+            y: coordinate(subpixel, star.centroid.1, star.weighted_centroid.1),
+            area: star.area,
+            brightness: star.brightness,
+            peak: star.peak,
+            saturated: star.is_saturated(),
+            bounding_box: star.bounding_box.into(),
+            moments: JsonMoments {
+                xx: star.moments.xx,
+                yy: star.moments.yy,
+                xy: star.moments.xy,
+            },
+            axis_ratio: star.axis_ratio(),
+            orientation: star.orientation(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct JsonFwhm {
+    /// In pixels of the full image
+    median: f64,
+    /// Number of round unsaturated stars it is the median of
+    stars: usize,
+}
+
+impl From<Fwhm> for JsonFwhm {
+    fn from(fwhm: Fwhm) -> Self {
+        JsonFwhm {
+            median: fwhm.median,
+            stars: fwhm.measured,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct JsonMoments {
+    xx: f64,
+    yy: f64,
+    xy: f64,
+}
+
+/// Inclusive on both ends, like [`BoundingBox`]
+#[derive(Serialize)]
+struct JsonBoundingBox {
+    x_min: usize,
+    y_min: usize,
+    x_max: usize,
+    y_max: usize,
+}
+
+impl From<BoundingBox> for JsonBoundingBox {
+    fn from(bounding_box: BoundingBox) -> Self {
+        JsonBoundingBox {
+            x_min: bounding_box.x_min,
+            y_min: bounding_box.y_min,
+            x_max: bounding_box.x_max,
+            y_max: bounding_box.y_max,
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum Coordinate {
+    Pixel(usize),
+    Subpixel(f64),
+}
+
+fn print_json(args: &Args, report: &FileReport) -> Result<()> {
+    let subpixel = args.subpixel;
+    let json = JsonReport {
+        schema_version: SCHEMA_VERSION,
+        version: env!("CARGO_PKG_VERSION"),
+        parameters: JsonParameters::new(args, report.sensitivity, report.bit_depth),
+        file: report.file,
+        count: report.stats.count,
+        width: report.width,
+        height: report.height,
+        sensitivity: report.sensitivity,
+        bit_depth: report.bit_depth,
+        stars_per_megapixel: report.stars_per_megapixel(),
+        saturated: report.saturated(),
+        merged: report.merged,
+        limit_reached: args.limit.map(|_| report.limited),
+        brighter: report.brighter,
+        fingerprint: report.fingerprint.as_deref(),
+        fwhm: args.fwhm.then(|| report.fwhm.map(JsonFwhm::from)),
+        megapixels_per_second: report.megapixels_per_second,
+        stars: report
+            .stats
+            .stars
+            .iter()
+            .map(|star| JsonStar::new(star, subpixel))
+            .collect(),
+        comparison: report.comparison.map(|comparison| JsonComparison {
+            other: &comparison.other,
+            matched: comparison.matches.matched.len(),
+            appeared: comparison
+                .appeared()
+                .map(|star| JsonCentroid::new(star, subpixel))
+                .collect(),
+            disappeared: comparison
+                .matches
+                .unmatched_first
+                .iter()
+                .map(|&index| JsonCentroid::new(&report.stats.stars[index], subpixel))
+                .collect(),
+        }),
+        grid: report.grid.as_deref(),
+        rois: JsonRoi::all(report),
+        largest: report.largest.as_ref().map(|largest| {
+            largest
+                .iter()
+                .map(|star| JsonStar::new(star, subpixel))
+                .collect()
+        }),
+    };
+    print_json_value(args, &json)
+}
+
+/// Line of the output of `--format jsonl`
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum JsonLine<'a> {
+    Star {
+        file: &'a str,
+        #[serde(flatten)]
+        star: JsonStar,
+    },
+    /// Follows the stars of the file
+    Summary {
+        file: &'a str,
+        version: &'static str,
+        parameters: Box<JsonParameters<'a>>,
+        count: u64,
+        width: u32,
+        height: u32,
+        sensitivity: u16,
+        bit_depth: u8,
+        saturated: usize,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        merged: Option<usize>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        limit_reached: Option<bool>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        brighter: Option<u64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        fingerprint: Option<&'a str>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        fwhm: Option<Option<JsonFwhm>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        megapixels_per_second: Option<f64>,
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        rois: Vec<JsonRoi<'a>>,
+    },
+}
+
+fn print_json_lines(args: &Args, report: &FileReport) -> Result<()> {
+    let mut stdout = io::stdout().lock();
+    for star in &report.stats.stars {
+        let line = JsonLine::Star {
+            file: report.file,
+            star: JsonStar::new(star, args.subpixel),
+        };
+        serde_json::to_writer(&mut stdout, &line)?;
+        writeln!(stdout)?;
+    }
+    let summary = JsonLine::Summary {
+        file: report.file,
+        version: env!("CARGO_PKG_VERSION"),
+        parameters: Box::new(JsonParameters::new(
+            args,
+            report.sensitivity,
+            report.bit_depth,
+        )),
+        count: report.stats.count,
+        width: report.width,
+        height: report.height,
+        sensitivity: report.sensitivity,
+        bit_depth: report.bit_depth,
+        saturated: report.saturated(),
+        merged: report.merged,
+        limit_reached: args.limit.map(|_| report.limited),
+        brighter: report.brighter,
+        fingerprint: report.fingerprint.as_deref(),
+        fwhm: args.fwhm.then(|| report.fwhm.map(JsonFwhm::from)),
+        megapixels_per_second: report.megapixels_per_second,
+        rois: JsonRoi::all(report),
+    };
+    serde_json::to_writer(&mut stdout, &summary)?;
+    writeln!(stdout)?;
+    Ok(())
+}
+
+fn coordinate(subpixel: bool, pixel: usize, weighted: f64) -> Coordinate {
+    if subpixel {
+        Coordinate::Subpixel(weighted)
+    } else {
+        Coordinate::Pixel(pixel)
+    }
+}
+
+fn print_size_summary(sizes: &[usize]) {
+    if sizes.is_empty() {
+        return;
+    }
+    let mut sorted = sizes.to_vec();
+    sorted.sort_unstable();
+    println!(
+        "Star area in pixels: min {}, median {}, max {}, total {}",
+        sorted[0],
+        sorted[sorted.len() / 2],
+        sorted[sorted.len() - 1],
+        sorted.iter().sum::<usize>()
+    );
+}
+
+enum OutputFileName {
+    FromOriginal(String),
+    Custom(String),
+}
+
+/// Output path for the file, with `suffix` appended to the original name when it is derived
+///
+/// Derived names are put in `output_dir` when given, which is created if needed, and next to
+/// the original otherwise. They have the extension of `format` when given and that of the
+/// original otherwise. Custom names are used as they are.
+fn create_output_path(
+    output_file_name: OutputFileName,
+    suffix: &str,
+    output_dir: Option<&Path>,
+    format: Option<OutputFormat>,
+) -> Result<PathBuf> {
+    match output_file_name {
+        OutputFileName::FromOriginal(original_file_name) => {
+            if original_file_name == STDIN_PATH {
+                bail!("Output file name can't be derived when reading stdin, use --output-name");
+            }
+            let original = Path::new(&original_file_name);
+            let stem = original.file_stem().with_context(|| {
+                format!("{original_file_name} has no file name, use --output-name")
+            })?;
+            // Raw files can't be written, so their outputs are pngs
+            let extension = match (format, original.extension()) {
+                (Some(format), _) => format.extension().as_ref(),
+                _ if is_raw(&original_file_name) => "png".as_ref(),
+                (None, Some(extension)) => extension,
+                (None, None) => "jpg".as_ref(),
+            };
+            let mut file_name = stem.to_os_string();
+            file_name.push(format!("-{suffix}."));
+            file_name.push(extension);
+            let Some(dir) = output_dir else {
+                return Ok(original.with_file_name(file_name));
+            };
+            fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+            Ok(dir.join(file_name))
+        }
+        OutputFileName::Custom(custom_file_name) => Ok(PathBuf::from(custom_file_name)),
     }
 }
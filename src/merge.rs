@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+
+use crate::{SecondMoments, Star, StarStats};
+
+/// Merges stars whose weighted centroids are closer than `radius` pixels into single stars,
+/// returning how many merges happened
+///
+/// Closeness is transitive, so a chain of stars that are each close to the next becomes one
+/// star even when its ends are further apart. A merged star has the combined area, brightness
+/// and bounding box of its parts and their brightness weighted centroid. This works on the
+/// measured stars only, the pixels of the parts stay separate groups.
+pub fn merge_close_stars(stats: &mut StarStats, radius: f64) -> usize {
+    let stars = &stats.stars;
+    // Stars by the cell of a radius sized grid they are in, so only the neighbouring cells
+    // need to be searched
+    let cell_size = radius.max(f64::MIN_POSITIVE);
+    let cell = |star: &Star| {
+        let (x, y) = star.weighted_centroid;
+        (
+            (x / cell_size).floor() as i64,
+            (y / cell_size).floor() as i64,
+        )
+    };
+    let mut grid: HashMap<(i64, i64), Vec<usize>> = HashMap::new();
+    for (index, star) in stars.iter().enumerate() {
+        grid.entry(cell(star)).or_default().push(index);
+    }
+
+    let mut parents: Vec<usize> = (0..stars.len()).collect();
+    let mut merges = 0;
+    for (index, star) in stars.iter().enumerate() {
+        let (x, y) = star.weighted_centroid;
+        let (cell_x, cell_y) = cell(star);
+        for neighbour_y in cell_y - 1..=cell_y + 1 {
+            for neighbour_x in cell_x - 1..=cell_x + 1 {
+                for &other in grid.get(&(neighbour_x, neighbour_y)).into_iter().flatten() {
+                    let (other_x, other_y) = stars[other].weighted_centroid;
+                    if other > index
+                        && (x - other_x).hypot(y - other_y) < radius
+                        && union(&mut parents, index, other)
+                    {
+                        merges += 1;
+                    }
+                }
+            }
+        }
+    }
+    if merges == 0 {
+        return 0;
+    }
+
+    let mut groups: Vec<Vec<&Star>> = vec![Vec::new(); stars.len()];
+    for (index, star) in stars.iter().enumerate() {
+        groups[find(&mut parents, index)].push(star);
+    }
+    let mut stars: Vec<Star> = groups
+        .iter()
+        .filter(|parts| !parts.is_empty())
+        .map(|parts| combine(parts))
+        .collect();
+    stars.sort_by(|a, b| {
+        (a.centroid.1, a.centroid.0)
+            .cmp(&(b.centroid.1, b.centroid.0))
+            .then(a.weighted_centroid.1.total_cmp(&b.weighted_centroid.1))
+            .then(a.weighted_centroid.0.total_cmp(&b.weighted_centroid.0))
+    });
+    stats.count = stars.len() as u64;
+    stats.stars = stars;
+    merges
+}
+
+/// One star made of all the parts
+fn combine(parts: &[&Star]) -> Star {
+    let area: usize = parts.iter().map(|star| star.area).sum();
+    let brightness: u64 = parts.iter().map(|star| star.brightness).sum();
+    // Parts without any brightness, e.g. when no image was given, are weighted by their area
+    let weight = |star: &Star| {
+        if brightness > 0 {
+            star.brightness as f64
+        } else {
+            star.area as f64
+        }
+    };
+    let total_weight: f64 = parts.iter().map(|star| weight(star)).sum();
+    let weighted = |coordinate: fn(&Star) -> f64| {
+        parts
+            .iter()
+            .map(|star| coordinate(star) * weight(star))
+            .sum::<f64>()
+            / total_weight
+    };
+    let mean = |coordinate: fn(&Star) -> usize| {
+        parts
+            .iter()
+            .map(|star| coordinate(star) * star.area)
+            .sum::<usize>()
+            / area
+    };
+    let weighted_centroid = (
+        weighted(|star| star.weighted_centroid.0),
+        weighted(|star| star.weighted_centroid.1),
+    );
+    // The spread of every part around the combined centroid is its own spread plus how far
+    // its centroid is from the combined one
+    let (mean_x, mean_y) = weighted_centroid;
+    let mut moments = SecondMoments::default();
+    for star in parts {
+        let (dx, dy) = (
+            star.weighted_centroid.0 - mean_x,
+            star.weighted_centroid.1 - mean_y,
+        );
+        let share = weight(star) / total_weight;
+        moments.xx += share * (star.moments.xx + dx * dx);
+        moments.yy += share * (star.moments.yy + dy * dy);
+        moments.xy += share * (star.moments.xy + dx * dy);
+    }
+    let mut bounding_box = parts[0].bounding_box;
+    for star in &parts[1..] {
+        bounding_box.x_min = bounding_box.x_min.min(star.bounding_box.x_min);
+        bounding_box.y_min = bounding_box.y_min.min(star.bounding_box.y_min);
+        bounding_box.x_max = bounding_box.x_max.max(star.bounding_box.x_max);
+        bounding_box.y_max = bounding_box.y_max.max(star.bounding_box.y_max);
+    }
+    Star {
+        area,
+        centroid: (mean(|star| star.centroid.0), mean(|star| star.centroid.1)),
+        weighted_centroid,
+        bounding_box,
+        brightness,
+        peak: parts.iter().map(|star| star.peak).max().unwrap_or(0),
+        moments,
+    }
+}
+
+fn find(parents: &mut [usize], mut index: usize) -> usize {
+    while parents[index] != index {
+        parents[index] = parents[parents[index]];
+        index = parents[index];
+    }
+    index
+}
+
+/// Joins the groups of both stars, returning whether they were separate before
+fn union(parents: &mut [usize], a: usize, b: usize) -> bool {
+    let (a, b) = (find(parents, a), find(parents, b));
+    parents[a.max(b)] = a.min(b);
+    a != b
+}
@@ -0,0 +1,65 @@
+use crate::Bitmap;
+
+/// Sets every pixel within `radius` (horizontally, vertically or diagonally) of a set pixel
+pub fn dilate(bitmap: &Bitmap, radius: usize) -> Bitmap {
+    apply(bitmap, radius, |count, _| count > 0)
+}
+
+/// Keeps only the pixels whose whole `radius` neighbourhood is set. Pixels outside of the
+/// bitmap count as set, so stars touching the edge don't shrink away from it
+pub fn erode(bitmap: &Bitmap, radius: usize) -> Bitmap {
+    apply(bitmap, radius, |count, window| count == window)
+}
+
+/// Dilates then erodes, which fills gaps up to `2 * radius` pixels wide without growing stars
+pub fn close(bitmap: &Bitmap, radius: usize) -> Bitmap {
+    erode(&dilate(bitmap, radius), radius)
+}
+
+/// Erodes then dilates, which removes specks and thin bridges without shrinking stars
+pub fn open(bitmap: &Bitmap, radius: usize) -> Bitmap {
+    dilate(&erode(bitmap, radius), radius)
+}
+
+/// Runs a square window over the bitmap, one axis at a time. `keep` gets the number of set
+/// pixels in the window and the number of pixels in the window that are inside the bitmap
+fn apply(bitmap: &Bitmap, radius: usize, keep: impl Fn(usize, usize) -> bool + Copy) -> Bitmap {
+    if radius == 0 {
+        return bitmap.clone();
+    }
+    let (width, height) = (bitmap.width(), bitmap.height());
+    let horizontal = pass(width, height, radius, keep, |x, y| bitmap.get(x, y));
+    let vertical = pass(height, width, radius, keep, |y, x| {
+        horizontal[y * width + x]
+    });
+    let mut output = Bitmap::new(width, height);
+    for y in 0..height {
+        for x in 0..width {
+            output.set(x, y, vertical[x * height + y]);
+        }
+    }
+    output
+}
+
+/// One axis of [`apply`], returning `lines * length` values laid out line by line
+fn pass(
+    length: usize,
+    lines: usize,
+    radius: usize,
+    keep: impl Fn(usize, usize) -> bool,
+    get: impl Fn(usize, usize) -> bool,
+) -> Vec<bool> {
+    let mut output = Vec::with_capacity(length * lines);
+    let mut prefix = vec![0; length + 1];
+    for line in 0..lines {
+        for i in 0..length {
+            prefix[i + 1] = prefix[i] + get(i, line) as usize;
+        }
+        for i in 0..length {
+            let start = i.saturating_sub(radius);
+            let end = (i + radius + 1).min(length);
+            output.push(keep(prefix[end] - prefix[start], end - start));
+        }
+    }
+    output
+}
@@ -0,0 +1,38 @@
+//! Python bindings, built with the `pyo3` feature
+
+use pyo3::{exceptions::PyIOError, prelude::*};
+
+use crate::{to_luma, Channel, Detections, StarField};
+
+fn detect(path: &str, sensitivity: u8) -> PyResult<Detections> {
+    let img = image::open(path).map_err(|err| PyIOError::new_err(format!("{path}: {err}")))?;
+    let luma = to_luma(&img, Channel::Gray);
+    Ok(StarField::from_image(&luma)
+        .sensitivity(sensitivity)
+        .detect())
+}
+
+/// Counts the stars in the image at `path`
+#[pyfunction]
+#[pyo3(signature = (path, sensitivity = 20))]
+fn count_stars(path: &str, sensitivity: u8) -> PyResult<u64> {
+    Ok(detect(path, sensitivity)?.count())
+}
+
+/// Finds the stars in the image at `path`, as a list of `(x, y, area)` tuples
+#[pyfunction]
+#[pyo3(signature = (path, sensitivity = 20))]
+fn find_stars(path: &str, sensitivity: u8) -> PyResult<Vec<(usize, usize, usize)>> {
+    let detections = detect(path, sensitivity)?;
+    Ok(detections
+        .iter()
+        .map(|star| (star.centroid.0, star.centroid.1, star.area))
+        .collect())
+}
+
+#[pymodule]
+fn star_counter(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_function(wrap_pyfunction!(count_stars, module)?)?;
+    module.add_function(wrap_pyfunction!(find_stars, module)?)?;
+    Ok(())
+}
@@ -0,0 +1,291 @@
+use std::io::Cursor;
+
+use image::{
+    imageops, DynamicImage, GrayImage, ImageBuffer, ImageError, ImageOutputFormat, Luma, Rgb,
+    RgbImage, Rgba, RgbaImage,
+};
+
+use crate::{Bitmap, Detections, LabelMap, Star, StarStats};
+
+const MARK_COLOR: Rgb<u8> = Rgb([255, 0, 0]);
+
+/// Draws a rectangle around every star on top of the original image
+pub fn annotate(img: &GrayImage, stats: &StarStats) -> RgbImage {
+    let (width, height) = img.dimensions();
+    let mut output = RgbImage::from_fn(width, height, |x, y| {
+        let luma = img.get_pixel(x, y).0[0];
+        Rgb([luma, luma, luma])
+    });
+    if width == 0 || height == 0 {
+        return output;
+    }
+
+    for star in &stats.stars {
+        // Leave a pixel of space so the rectangle doesn't cover the star itself
+        let bounding_box = star.bounding_box;
+        let x_min = (bounding_box.x_min as u32).saturating_sub(1);
+        let y_min = (bounding_box.y_min as u32).saturating_sub(1);
+        let x_max = (bounding_box.x_max as u32 + 1).min(width - 1);
+        let y_max = (bounding_box.y_max as u32 + 1).min(height - 1);
+        for x in x_min..=x_max {
+            output.put_pixel(x, y_min, MARK_COLOR);
+            output.put_pixel(x, y_max, MARK_COLOR);
+        }
+        for y in y_min..=y_max {
+            output.put_pixel(x_min, y, MARK_COLOR);
+            output.put_pixel(x_max, y, MARK_COLOR);
+        }
+    }
+    output
+}
+
+/// Pixels between the centroid and the inner end of every arm of a crosshair, so the brightest
+/// part of the star stays visible
+const CROSSHAIR_GAP: i64 = 2;
+
+/// Pixels between the centroid and the outer end of every arm of a crosshair
+const CROSSHAIR_REACH: i64 = 5;
+
+/// Digits 0 to 9 of a 3x5 pixel font, a row per byte with its lowest 3 bits left to right
+const DIGITS: [[u8; 5]; 10] = [
+    [0b111, 0b101, 0b101, 0b101, 0b111],
+    [0b010, 0b110, 0b010, 0b010, 0b111],
+    [0b111, 0b001, 0b111, 0b100, 0b111],
+    [0b111, 0b001, 0b011, 0b001, 0b111],
+    [0b101, 0b101, 0b111, 0b001, 0b001],
+    [0b111, 0b100, 0b111, 0b001, 0b111],
+    [0b111, 0b100, 0b111, 0b101, 0b111],
+    [0b111, 0b001, 0b010, 0b010, 0b010],
+    [0b111, 0b101, 0b111, 0b101, 0b111],
+    [0b111, 0b101, 0b111, 0b001, 0b111],
+];
+
+/// Draws a small crosshair at the weighted centroid of every star on top of the original
+/// image, covering less of the stars than the rectangles of [`annotate`]
+///
+/// With `labels`, every crosshair also gets the number of its star to the upper right, counting
+/// from 1 in the order of `stats.stars` like the id column of the csv output.
+pub fn mark_centroids(img: &GrayImage, stats: &StarStats, labels: bool) -> RgbImage {
+    let (width, height) = img.dimensions();
+    let mut output = RgbImage::from_fn(width, height, |x, y| {
+        let luma = img.get_pixel(x, y).0[0];
+        Rgb([luma, luma, luma])
+    });
+    let mut put = |x: i64, y: i64| {
+        if (0..width as i64).contains(&x) && (0..height as i64).contains(&y) {
+            output.put_pixel(x as u32, y as u32, MARK_COLOR);
+        }
+    };
+
+    for (index, star) in stats.stars.iter().enumerate() {
+        let (x, y) = star.weighted_centroid;
+        let (x, y) = (x.round() as i64, y.round() as i64);
+        for distance in CROSSHAIR_GAP..=CROSSHAIR_REACH {
+            put(x - distance, y);
+            put(x + distance, y);
+            put(x, y - distance);
+            put(x, y + distance);
+        }
+        if labels {
+            // The bottom of the digits is level with the end of the upper arm
+            let top = y - CROSSHAIR_REACH - 4;
+            for (position, digit) in (index + 1).to_string().bytes().enumerate() {
+                let left = x + CROSSHAIR_GAP + 4 * position as i64;
+                for (row, bits) in DIGITS[(digit - b'0') as usize].iter().enumerate() {
+                    for column in 0..3 {
+                        if bits & (0b100 >> column) != 0 {
+                            put(left + column, top + row as i64);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    output
+}
+
+/// Encodes the detections as a png in memory, e.g. for a server to send without touching the
+/// disk
+///
+/// With the `image` they were found in, this is the image with a rectangle around every star
+/// like [`annotate`]. Without it, it is the kept stars in white on black like
+/// [`Detections::render`].
+pub fn render_to_png_bytes(
+    detections: &Detections,
+    image: Option<&GrayImage>,
+) -> Result<Vec<u8>, ImageError> {
+    let rendered = match image {
+        Some(image) => DynamicImage::ImageRgb8(annotate(image, detections.stats())),
+        None => DynamicImage::ImageLuma8(detections.render()),
+    };
+    let mut bytes = Cursor::new(Vec::new());
+    rendered.write_to(&mut bytes, ImageOutputFormat::Png)?;
+    Ok(bytes.into_inner())
+}
+
+/// Renders the bitmap with the stars in `star` and everything else in `background`
+pub fn colorize(stars: &Bitmap, star: Rgba<u8>, background: Rgba<u8>) -> RgbaImage {
+    RgbaImage::from_fn(stars.width() as u32, stars.height() as u32, |x, y| {
+        if stars.get(x as usize, y as usize) {
+            star
+        } else {
+            background
+        }
+    })
+}
+
+/// Blends `color` over the star pixels of the original image at `opacity`, from 0 for the
+/// image as it is to 1 for solid `color`, so the stars stand out with the frame still visible
+/// around them. The bitmap must have the size of the image
+pub fn overlay(img: &GrayImage, stars: &Bitmap, color: Rgb<u8>, opacity: f64) -> RgbImage {
+    let (width, height) = img.dimensions();
+    assert_eq!(
+        (stars.width(), stars.height()),
+        (width as usize, height as usize)
+    );
+    let opacity = opacity.clamp(0.0, 1.0);
+    RgbImage::from_fn(width, height, |x, y| {
+        let luma = img.get_pixel(x, y).0[0];
+        if !stars.get(x as usize, y as usize) {
+            return Rgb([luma, luma, luma]);
+        }
+        Rgb(color.0.map(|channel| {
+            (luma as f64 * (1.0 - opacity) + channel as f64 * opacity).round() as u8
+        }))
+    })
+}
+
+/// Renders every group with its label as the pixel value, on a background of 0
+///
+/// The image is 8-bit if every label fits, then 16-bit and otherwise RGB with the label in the
+/// lower 24 bits, red being the most significant byte.
+pub fn render_labels(labels: &LabelMap) -> DynamicImage {
+    let (width, height) = (labels.width() as u32, labels.height() as u32);
+    let label = |x: u32, y: u32| labels.get(x as usize, y as usize);
+    if labels.count() <= u8::MAX as usize {
+        DynamicImage::ImageLuma8(GrayImage::from_fn(width, height, |x, y| {
+            Luma([label(x, y) as u8])
+        }))
+    } else if labels.count() <= u16::MAX as usize {
+        DynamicImage::ImageLuma16(ImageBuffer::from_fn(width, height, |x, y| {
+            Luma([label(x, y) as u16])
+        }))
+    } else {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, y| {
+            let [_, red, green, blue] = label(x, y).to_be_bytes();
+            Rgb([red, green, blue])
+        }))
+    }
+}
+
+/// Renders every group in a colour of its own on black, to tell the groups apart by eye
+///
+/// Hues step around the colour wheel by the golden ratio from a starting hue picked by `seed`,
+/// so neighbouring labels get very different colours and the same seed always gives the same
+/// colours.
+pub fn render_label_colors(labels: &LabelMap, seed: u64) -> RgbImage {
+    // The fractional part of the golden ratio, which never comes back close to earlier hues
+    const GOLDEN_RATIO_STEP: f64 = 0.618_033_988_749_895;
+    let start = (splitmix64(seed) >> 11) as f64 / (1u64 << 53) as f64;
+    let colors: Vec<Rgb<u8>> = (0..=labels.count())
+        .map(|label| {
+            if label == 0 {
+                return Rgb([0, 0, 0]);
+            }
+            let hue = (start + label as f64 * GOLDEN_RATIO_STEP).fract();
+            // Alternating brightness separates labels whose hues still end up close
+            let value = if label % 2 == 0 { 1.0 } else { 0.75 };
+            hsv_to_rgb(hue, 0.85, value)
+        })
+        .collect();
+    RgbImage::from_fn(labels.width() as u32, labels.height() as u32, |x, y| {
+        colors[labels.get(x as usize, y as usize) as usize]
+    })
+}
+
+/// Scrambles the bits of `seed`, so close seeds have unrelated results
+fn splitmix64(seed: u64) -> u64 {
+    let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// Converts a hue, saturation and value, all between 0 and 1, to RGB
+fn hsv_to_rgb(hue: f64, saturation: f64, value: f64) -> Rgb<u8> {
+    let sector = hue * 6.0;
+    let fraction = sector.fract();
+    let (p, q, t) = (
+        value * (1.0 - saturation),
+        value * (1.0 - saturation * fraction),
+        value * (1.0 - saturation * (1.0 - fraction)),
+    );
+    let (red, green, blue) = match sector as u32 % 6 {
+        0 => (value, t, p),
+        1 => (q, value, p),
+        2 => (p, value, t),
+        3 => (p, q, value),
+        4 => (t, p, value),
+        _ => (value, p, q),
+    };
+    let channel = |channel: f64| (channel * 255.0).round() as u8;
+    Rgb([channel(red), channel(green), channel(blue)])
+}
+
+/// Shades the image by how many star centroids are near each pixel, as a heatmap going from
+/// black through red and yellow to white for the densest cell
+///
+/// Centroids are counted in square cells of `cell_size` pixels, and the counts are blended
+/// between the centres of neighbouring cells so the map has no hard edges.
+pub fn density_map(img: &GrayImage, stats: &StarStats, cell_size: u32) -> RgbImage {
+    let (width, height) = img.dimensions();
+    let cell_size = cell_size.max(1);
+    let (columns, rows) = (width.div_ceil(cell_size), height.div_ceil(cell_size));
+    let mut counts = vec![0u32; columns as usize * rows as usize];
+    for star in &stats.stars {
+        let (x, y) = star.centroid;
+        let (column, row) = (x as u32 / cell_size, y as u32 / cell_size);
+        if column < columns && row < rows {
+            counts[(row * columns + column) as usize] += 1;
+        }
+    }
+    let densest = counts.iter().copied().max().unwrap_or(0).max(1) as f32;
+    let count = |column: usize, row: usize| counts[row * columns as usize + column] as f32;
+
+    // Position of the pixel between cell centres, along with the two cells around it
+    let between = |position: u32, cells: u32| {
+        let cell =
+            ((position as f32 + 0.5) / cell_size as f32 - 0.5).clamp(0.0, (cells - 1) as f32);
+        let first = cell.floor() as usize;
+        (first, (first + 1).min(cells as usize - 1), cell.fract())
+    };
+    RgbImage::from_fn(width, height, |x, y| {
+        let (left, right, along) = between(x, columns);
+        let (top, bottom, down) = between(y, rows);
+        let upper = count(left, top) * (1.0 - along) + count(right, top) * along;
+        let lower = count(left, bottom) * (1.0 - along) + count(right, bottom) * along;
+        let heat = heat_color((upper * (1.0 - down) + lower * down) / densest);
+        let luma = img.get_pixel(x, y).0[0];
+        // Keep a little of the image so the stars can still be seen under the map
+        Rgb(heat
+            .0
+            .map(|channel| (channel as u16 * 3 / 4 + luma as u16 / 4) as u8))
+    })
+}
+
+/// Black for 0, through red and yellow to white for 1
+fn heat_color(value: f32) -> Rgb<u8> {
+    let channel = |start: f32| ((value * 3.0 - start).clamp(0.0, 1.0) * 255.0).round() as u8;
+    Rgb([channel(0.0), channel(1.0), channel(2.0)])
+}
+
+/// Crops the star out of the image with `padding` pixels around its bounding box, less where
+/// it would run off the edge of the image
+pub fn thumbnail(img: &GrayImage, star: &Star, padding: u32) -> GrayImage {
+    let bounding_box = star.bounding_box;
+    let x_min = (bounding_box.x_min as u32).saturating_sub(padding);
+    let y_min = (bounding_box.y_min as u32).saturating_sub(padding);
+    let x_max = (bounding_box.x_max as u32 + padding).min(img.width() - 1);
+    let y_max = (bounding_box.y_max as u32 + padding).min(img.height() - 1);
+    imageops::crop_imm(img, x_min, y_min, x_max - x_min + 1, y_max - y_min + 1).to_image()
+}
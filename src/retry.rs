@@ -0,0 +1,66 @@
+use std::io::{self, ErrorKind, Read};
+use std::thread;
+use std::time::Duration;
+
+use log::warn;
+
+/// Errors of the usual `EIO` number on Unix, which is what reads on a network share fail with
+/// when the server is briefly unreachable, but has no [`ErrorKind`] of its own
+#[cfg(unix)]
+const EIO: i32 = 5;
+
+/// Whether a read that failed with the error may well succeed when tried again, e.g. because a
+/// network share was briefly unreachable, unlike a missing file or one that isn't allowed to be
+/// read
+pub fn is_transient(err: &io::Error) -> bool {
+    #[cfg(unix)]
+    if err.raw_os_error() == Some(EIO) {
+        return true;
+    }
+    matches!(
+        err.kind(),
+        ErrorKind::Interrupted
+            | ErrorKind::WouldBlock
+            | ErrorKind::TimedOut
+            | ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::NotConnected
+            | ErrorKind::BrokenPipe
+            | ErrorKind::NetworkDown
+            | ErrorKind::NetworkUnreachable
+            | ErrorKind::HostUnreachable
+            | ErrorKind::StaleNetworkFileHandle
+            | ErrorKind::ResourceBusy
+    )
+}
+
+/// Everything the reader that `open` returns gives, opening and reading it from the start again
+/// up to `retries` more times after a [transient](is_transient) error
+///
+/// The wait before a retry starts at `backoff` and doubles with every one. Other errors are
+/// returned right away, as is the last transient one. Since only the bytes are read here,
+/// decoding them afterwards is never retried.
+pub fn read_with_retries<R: Read>(
+    mut open: impl FnMut() -> io::Result<R>,
+    retries: u32,
+    backoff: Duration,
+) -> io::Result<Vec<u8>> {
+    let mut wait = backoff;
+    let mut attempt = 0;
+    loop {
+        let result = open().and_then(|mut reader| {
+            let mut data = Vec::new();
+            reader.read_to_end(&mut data)?;
+            Ok(data)
+        });
+        match result {
+            Err(err) if attempt < retries && is_transient(&err) => {
+                attempt += 1;
+                warn!("Reading failed with {err}, retrying in {wait:?} ({attempt}/{retries})");
+                thread::sleep(wait);
+                wait = wait.saturating_mul(2);
+            }
+            result => return result,
+        }
+    }
+}
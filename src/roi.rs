@@ -0,0 +1,71 @@
+use std::{fmt, str::FromStr};
+
+use serde::Serialize;
+
+use crate::StarCounterError;
+
+/// Rectangular region of interest of the image
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct Roi {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl FromStr for Roi {
+    type Err = String;
+
+    /// Parses `x,y,width,height`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values = s
+            .split(',')
+            .map(|value| value.trim().parse::<u32>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| format!("invalid ROI {s}: {err}"))?;
+        let [x, y, width, height] = values[..] else {
+            return Err(format!("ROI must be x,y,width,height, got {s}"));
+        };
+        if width == 0 || height == 0 {
+            return Err(format!("ROI {s} is empty"));
+        }
+        Ok(Roi {
+            x,
+            y,
+            width,
+            height,
+        })
+    }
+}
+
+impl fmt::Display for Roi {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{},{},{},{}", self.x, self.y, self.width, self.height)
+    }
+}
+
+impl Roi {
+    /// Fails with [`StarCounterError::InvalidRoi`] unless the region is inside an image of the
+    /// size
+    pub fn check_bounds(&self, width: u32, height: u32) -> Result<(), StarCounterError> {
+        let fits = |start: u32, size: u32, limit: u32| {
+            start.checked_add(size).is_some_and(|end| end <= limit)
+        };
+        if !fits(self.x, self.width, width) || !fits(self.y, self.height, height) {
+            return Err(StarCounterError::InvalidRoi {
+                roi: *self,
+                width,
+                height,
+            });
+        }
+        Ok(())
+    }
+
+    /// Whether the pixel is inside the region
+    pub fn contains(&self, (x, y): (usize, usize)) -> bool {
+        let inside = |at: usize, start: u32, size: u32| {
+            at >= start as usize && at - (start as usize) < size as usize
+        };
+        inside(x, self.x, self.width) && inside(y, self.y, self.height)
+    }
+}
@@ -0,0 +1,185 @@
+use std::collections::VecDeque;
+
+use image::GrayImage;
+use log::debug;
+
+use crate::{label_groups, Bitmap, DetectOptions, Moments, StarStats};
+
+/// Counts the groups of the bitmap like [`count_groups`](crate::count_groups), but counts a
+/// group with several brightness peaks at least `min_distance` pixels apart as one star per
+/// peak, e.g. for two stars of a crowded field that merged into one blob
+///
+/// Peaks are the pixels of a group that no neighbouring pixel of the group is brighter than.
+/// They are taken from the brightest down, skipping those closer than `min_distance` to a
+/// brighter one that was already taken. Every pixel of the group then goes to the star of the
+/// peak it is the fewest steps away from within the group. This is much cruder than a
+/// watershed, so the border between two stars runs halfway between their peaks rather than
+/// along the faintest pixels between them.
+///
+/// [`DetectOptions::limit`] is ignored.
+pub fn split_peaks(
+    stars: &mut Bitmap,
+    luma: &GrayImage,
+    options: &DetectOptions,
+    min_distance: f64,
+) -> StarStats {
+    let (width, height) = (stars.width(), stars.height());
+    let labels = label_groups(stars, options.connectivity);
+    let mut groups = vec![Vec::new(); labels.count()];
+    for (index, &label) in labels.as_slice().iter().enumerate() {
+        if label != 0 {
+            groups[label as usize - 1].push((index % width, index / width));
+        }
+    }
+    let value = |(x, y): (usize, usize)| luma.get_pixel(x as u32, y as u32).0[0];
+    // Index of the peak that every pixel of the group being split goes to
+    let mut owners = vec![usize::MAX; width * height];
+
+    let mut stats = StarStats::default();
+    for pixels in groups {
+        let label = labels.get(pixels[0].0, pixels[0].1);
+        let in_group = |x, y| x < width && y < height && labels.get(x, y) == label;
+        let peaks = find_peaks(&pixels, min_distance, value, in_group);
+        if peaks.len() > 1 {
+            debug!(
+                "Split the group at {:?} into {} stars",
+                pixels[0],
+                peaks.len()
+            );
+        }
+        assign_to_peaks(&peaks, &mut owners, width, in_group);
+        let owner = |x: usize, y: usize| match owners[y * width + x] {
+            // Only groups connected by a radius can have pixels that no step reaches
+            usize::MAX => nearest(&peaks, (x, y)),
+            owner => owner,
+        };
+        let mut parts: Vec<Moments> = peaks.iter().map(|&(x, y)| Moments::new(x, y)).collect();
+        for &(x, y) in &pixels {
+            parts[owner(x, y)].add(x, y, Some(value((x, y))));
+        }
+        let stars_of_group: Vec<_> = parts.into_iter().map(Moments::into_star).collect();
+        let kept: Vec<bool> = stars_of_group
+            .iter()
+            .map(|star| options.accepts(star) && !options.touches_border(star, width, height))
+            .collect();
+        for &(x, y) in &pixels {
+            if !kept[owner(x, y)] {
+                stars.set(x, y, false);
+            }
+        }
+        for (star, kept) in stars_of_group.into_iter().zip(kept) {
+            if kept {
+                stats.count += 1;
+                stats.stars.push(star);
+            } else {
+                debug!(
+                    "Discarded group of {} pixels at {:?}",
+                    star.area, star.centroid
+                );
+            }
+        }
+    }
+    stats
+        .stars
+        .sort_by_key(|star| (star.centroid.1, star.centroid.0));
+    stats
+}
+
+/// The peaks of a group from the brightest down, at least `min_distance` apart. There is always
+/// at least one, the brightest pixel
+fn find_peaks(
+    pixels: &[(usize, usize)],
+    min_distance: f64,
+    value: impl Fn((usize, usize)) -> u8,
+    in_group: impl Fn(usize, usize) -> bool,
+) -> Vec<(usize, usize)> {
+    let mut candidates: Vec<(usize, usize)> = pixels
+        .iter()
+        .copied()
+        .filter(|&(x, y)| {
+            let center = value((x, y));
+            neighbours(x, y).all(|(neighbour_x, neighbour_y, earlier)| {
+                if !in_group(neighbour_x, neighbour_y) {
+                    return true;
+                }
+                let neighbour = value((neighbour_x, neighbour_y));
+                // Only the first pixel of a plateau in scan order is a peak
+                if earlier {
+                    center > neighbour
+                } else {
+                    center >= neighbour
+                }
+            })
+        })
+        .collect();
+    // Stable, so equally bright peaks stay in scan order
+    candidates.sort_by_key(|&pixel| std::cmp::Reverse(value(pixel)));
+    let mut peaks: Vec<(usize, usize)> = Vec::new();
+    for candidate in candidates {
+        if peaks
+            .iter()
+            .all(|&peak| distance(peak, candidate) >= min_distance)
+        {
+            peaks.push(candidate);
+        }
+    }
+    peaks
+}
+
+/// Grows the stars of the peaks one step at a time through the pixels of the group, so every
+/// pixel goes to the peak it is the fewest steps away from and to the brightest of them on a tie
+fn assign_to_peaks(
+    peaks: &[(usize, usize)],
+    owners: &mut [usize],
+    width: usize,
+    in_group: impl Fn(usize, usize) -> bool,
+) {
+    let mut queue = VecDeque::new();
+    for (index, &(x, y)) in peaks.iter().enumerate() {
+        owners[y * width + x] = index;
+        queue.push_back((x, y));
+    }
+    while let Some((x, y)) = queue.pop_front() {
+        let owner = owners[y * width + x];
+        for (neighbour_x, neighbour_y, _) in neighbours(x, y) {
+            if in_group(neighbour_x, neighbour_y)
+                && owners[neighbour_y * width + neighbour_x] == usize::MAX
+            {
+                owners[neighbour_y * width + neighbour_x] = owner;
+                queue.push_back((neighbour_x, neighbour_y));
+            }
+        }
+    }
+}
+
+/// The 8 neighbours of a pixel inside the positive quadrant, with whether a row by row scan
+/// reaches them before the pixel. Neighbours past the right and bottom edges are left to the
+/// caller, which only looks at pixels of the group anyway
+fn neighbours(x: usize, y: usize) -> impl Iterator<Item = (usize, usize, bool)> {
+    (-1isize..=1)
+        .flat_map(|dy| (-1isize..=1).map(move |dx| (dx, dy)))
+        .filter(|&offset| offset != (0, 0))
+        .filter_map(move |(dx, dy)| {
+            let earlier = dy < 0 || (dy == 0 && dx < 0);
+            Some((
+                x.checked_add_signed(dx)?,
+                y.checked_add_signed(dy)?,
+                earlier,
+            ))
+        })
+}
+
+/// Index of the peak closest to the pixel as the crow flies, the brighter of them on a tie
+fn nearest(peaks: &[(usize, usize)], pixel: (usize, usize)) -> usize {
+    let mut best = 0;
+    for (index, &peak) in peaks.iter().enumerate().skip(1) {
+        if distance(peak, pixel) < distance(peaks[best], pixel) {
+            best = index;
+        }
+    }
+    best
+}
+
+fn distance((x1, y1): (usize, usize), (x2, y2): (usize, usize)) -> f64 {
+    (x1 as f64 - x2 as f64).hypot(y1 as f64 - y2 as f64)
+}
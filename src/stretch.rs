@@ -0,0 +1,136 @@
+use std::{fmt, str::FromStr};
+
+use image::{ImageBuffer, Luma, Primitive};
+
+/// Tone curve applied to the brightness before thresholding. The curves other than
+/// [`Stretch::None`] lift faint pixels much more than bright ones, so stars barely above the
+/// sky pass a threshold that bright stars pass anyway
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stretch {
+    None,
+    /// `log(1 + 1000 v) / log(1001)` of the brightness `v` from 0 to 1, the strongest of them
+    Log,
+    /// Square root, the mildest of them
+    Sqrt,
+    /// `asinh(10 v) / asinh(10)`, linear for the faintest pixels and logarithmic above
+    Asinh,
+}
+
+impl Stretch {
+    /// The curve at `value`, both from 0 to 1
+    fn apply(self, value: f64) -> f64 {
+        match self {
+            Stretch::None => value,
+            Stretch::Log => (1000.0 * value).ln_1p() / 1000f64.ln_1p(),
+            Stretch::Sqrt => value.sqrt(),
+            Stretch::Asinh => (10.0 * value).asinh() / 10f64.asinh(),
+        }
+    }
+}
+
+impl FromStr for Stretch {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Stretch::None),
+            "log" => Ok(Stretch::Log),
+            "sqrt" => Ok(Stretch::Sqrt),
+            "asinh" => Ok(Stretch::Asinh),
+            _ => Err(format!(
+                "stretch must be one of none, log, sqrt or asinh, got {s}"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Stretch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Stretch::None => "none",
+            Stretch::Log => "log",
+            Stretch::Sqrt => "sqrt",
+            Stretch::Asinh => "asinh",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// Applies the tone curve to every pixel, with black and white staying where they are
+///
+/// The curve is computed once for every value of the subpixel type and looked up from then on,
+/// which is 256 values for 8-bit images and 65536 for 16-bit ones.
+pub fn stretch<T: Primitive>(
+    img: &ImageBuffer<Luma<T>, Vec<T>>,
+    curve: Stretch,
+) -> ImageBuffer<Luma<T>, Vec<T>> {
+    let max = T::DEFAULT_MAX_VALUE.to_f64().unwrap_or(1.0);
+    let table: Vec<T> = (0..=max as usize)
+        .map(|value| {
+            let stretched = (curve.apply(value as f64 / max) * max).round();
+            T::from(stretched.clamp(0.0, max)).unwrap_or_else(T::zero)
+        })
+        .collect();
+    look_up(img, &table)
+}
+
+/// Rescales the brightness linearly so the darkest pixel becomes black and the brightest white,
+/// which makes a fixed sensitivity mean about the same for frames exposed differently
+///
+/// The darkest and brightest `clip` percent of the pixels are left out when finding the range,
+/// so a few hot or dead pixels don't keep the rest from being stretched, and become black and
+/// white themselves. An image of a single value is returned as it is. Like [`stretch`], this
+/// looks every pixel up in a table of all the values of the subpixel type.
+pub fn normalize<T: Primitive>(
+    img: &ImageBuffer<Luma<T>, Vec<T>>,
+    clip: f64,
+) -> ImageBuffer<Luma<T>, Vec<T>> {
+    let max = T::DEFAULT_MAX_VALUE.to_f64().unwrap_or(1.0);
+    let mut histogram = vec![0u64; max as usize + 1];
+    for value in img.iter() {
+        histogram[value.to_usize().unwrap_or(0)] += 1;
+    }
+    let clipped = img.len() as f64 * clip / 100.0;
+    let (Some(low), Some(high)) = (
+        past_clipped(&histogram, 0..histogram.len(), clipped),
+        past_clipped(&histogram, (0..histogram.len()).rev(), clipped),
+    ) else {
+        return img.clone();
+    };
+    if high <= low {
+        return img.clone();
+    }
+    let table: Vec<T> = (0..histogram.len())
+        .map(|value| {
+            let scaled = (value as f64 - low as f64) / (high - low) as f64 * max;
+            T::from(scaled.round().clamp(0.0, max)).unwrap_or_else(T::zero)
+        })
+        .collect();
+    look_up(img, &table)
+}
+
+/// The first of the values with more than `clipped` pixels at it and the values before it
+fn past_clipped(
+    histogram: &[u64],
+    mut values: impl Iterator<Item = usize>,
+    clipped: f64,
+) -> Option<usize> {
+    let mut seen = 0;
+    values.find(|&value| {
+        seen += histogram[value];
+        seen as f64 > clipped
+    })
+}
+
+/// The image with every value replaced by the entry of the table at it
+fn look_up<T: Primitive>(
+    img: &ImageBuffer<Luma<T>, Vec<T>>,
+    table: &[T],
+) -> ImageBuffer<Luma<T>, Vec<T>> {
+    let data = img
+        .iter()
+        .map(|value| table[value.to_usize().unwrap_or(0)])
+        .collect();
+    ImageBuffer::from_raw(img.width(), img.height(), data)
+        .expect("Buffer has a value for every pixel")
+}
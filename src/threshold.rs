@@ -0,0 +1,197 @@
+use std::fmt;
+
+use image::GrayImage;
+
+use crate::StarStats;
+
+/// Fraction of white pixels above which [`assess_exposure`] considers a frame overexposed
+pub const OVEREXPOSED_FRACTION: f64 = 0.01;
+
+/// Fraction of the searched pixels above which [`assess_groups`] considers a single star to be
+/// stars that merged with the sky
+pub const MERGED_FRACTION: f64 = 0.1;
+
+/// Why a frame is unlikely to give a useful count
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExposureWarning {
+    /// More than [`OVEREXPOSED_FRACTION`] of the pixels are white, so bright stars have
+    /// probably merged and the sky may be above the sensitivity
+    Overexposed { white_fraction: f64 },
+    /// No pixel is brighter than the sensitivity, so there is nothing to detect
+    Underexposed { brightest: u8 },
+    /// A single star covers more than [`MERGED_FRACTION`] of the pixels, so the sensitivity is
+    /// probably below the sky and the stars merged with it into one blob
+    Merged { largest_fraction: f64 },
+}
+
+impl fmt::Display for ExposureWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExposureWarning::Overexposed { white_fraction } => write!(
+                f,
+                "{:.1}% of the pixels are white, so the frame looks overexposed",
+                white_fraction * 100.0
+            ),
+            ExposureWarning::Underexposed { brightest } => write!(
+                f,
+                "The brightest pixel is {brightest}, not above the sensitivity, so the frame \
+                 looks underexposed"
+            ),
+            ExposureWarning::Merged { largest_fraction } => write!(
+                f,
+                "The largest star covers {:.1}% of the image, so stars have probably merged \
+                 with the sky, try a higher sensitivity",
+                largest_fraction * 100.0
+            ),
+        }
+    }
+}
+
+/// Checks the histogram for a frame that can't be counted, because it is clipped or has
+/// nothing above the sensitivity
+///
+/// This looks at the brightest pixel rather than a percentile, since in a sparse field the
+/// stars can be well under a percent of the pixels.
+pub fn assess_exposure(img: &GrayImage, sensitivity: u8) -> Option<ExposureWarning> {
+    let histogram = histogram(img);
+    let total: u64 = histogram.iter().sum();
+    if total == 0 {
+        return None;
+    }
+    let white_fraction = histogram[u8::MAX as usize] as f64 / total as f64;
+    if white_fraction > OVEREXPOSED_FRACTION {
+        return Some(ExposureWarning::Overexposed { white_fraction });
+    }
+    let brightest = histogram.iter().rposition(|&count| count > 0)? as u8;
+    (brightest <= sensitivity).then_some(ExposureWarning::Underexposed { brightest })
+}
+
+/// Checks the counted stars for one that covers so much of the `pixels` that were searched that
+/// it can't be a star, which turns a count of a single star into a hint at the sensitivity
+pub fn assess_groups(stats: &StarStats, pixels: u64) -> Option<ExposureWarning> {
+    let largest = stats.stars.iter().map(|star| star.area).max()?;
+    let largest_fraction = largest as f64 / pixels.max(1) as f64;
+    (largest_fraction > MERGED_FRACTION).then_some(ExposureWarning::Merged { largest_fraction })
+}
+
+/// Number of pixels at each luma value
+pub fn histogram(img: &GrayImage) -> [u64; 256] {
+    let mut histogram = [0; 256];
+    for pixel in img.pixels() {
+        histogram[pixel.0[0] as usize] += 1;
+    }
+    histogram
+}
+
+/// Robust statistics of the sky, which make up most of the pixels of an image, so the stars
+/// barely move them
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BackgroundEstimate {
+    pub median: u8,
+    /// Median absolute deviation of the pixels from the median
+    pub mad: u8,
+    /// Standard deviation of the sky noise, if it is normally distributed: 1.4826 times
+    /// the MAD
+    pub sigma: f64,
+}
+
+impl BackgroundEstimate {
+    /// The sensitivity `k` sigmas above the median, as usual 3 to 5
+    pub fn suggested_sensitivity(&self, k: f64) -> u8 {
+        (self.median as f64 + k * self.sigma)
+            .round()
+            .clamp(0.0, u8::MAX as f64) as u8
+    }
+}
+
+/// Estimates the sky of the image from its median and median absolute deviation
+pub fn estimate_background(img: &GrayImage) -> BackgroundEstimate {
+    estimate_background_from_histogram(&histogram(img))
+}
+
+/// Same as [`estimate_background`] for an already computed [`histogram`]
+pub fn estimate_background_from_histogram(histogram: &[u64; 256]) -> BackgroundEstimate {
+    let median = histogram_median(histogram);
+    let mut deviations = [0; 256];
+    for (value, &count) in histogram.iter().enumerate() {
+        deviations[value.abs_diff(median as usize)] += count;
+    }
+    let mad = histogram_median(&deviations);
+    BackgroundEstimate {
+        median,
+        mad,
+        sigma: 1.4826 * mad as f64,
+    }
+}
+
+/// The lower median of the values counted by the histogram, 0 when it is empty
+fn histogram_median(histogram: &[u64; 256]) -> u8 {
+    let total: u64 = histogram.iter().sum();
+    let mut below = 0;
+    for (value, &count) in histogram.iter().enumerate() {
+        below += count;
+        if below * 2 >= total && below > 0 {
+            return value as u8;
+        }
+    }
+    0
+}
+
+/// Picks the sensitivity that best separates the image into two classes using Otsu's method
+///
+/// Pixels above the returned value are the brighter class, matching [`crate::is_white`].
+pub fn otsu_threshold(img: &GrayImage) -> u8 {
+    otsu_threshold_from_histogram(&histogram(img))
+}
+
+/// Picks the sensitivity that leaves the brightest `percent` of the pixels above it
+///
+/// Pixels sharing a value are either all above or all below, so fewer pixels than asked for
+/// can be left above when many of them have the same value.
+pub fn percentile_threshold(img: &GrayImage, percent: f64) -> u8 {
+    percentile_threshold_from_histogram(&histogram(img), percent)
+}
+
+/// Same as [`percentile_threshold`] for an already computed [`histogram`]
+pub fn percentile_threshold_from_histogram(histogram: &[u64; 256], percent: f64) -> u8 {
+    let total: u64 = histogram.iter().sum();
+    let allowed = total as f64 * percent / 100.0;
+    let mut above = 0;
+    for value in (1..histogram.len()).rev() {
+        above += histogram[value];
+        if above as f64 > allowed {
+            return value as u8;
+        }
+    }
+    0
+}
+
+/// Same as [`otsu_threshold`] for an already computed [`histogram`]
+pub fn otsu_threshold_from_histogram(histogram: &[u64; 256]) -> u8 {
+    let total: u64 = histogram.iter().sum();
+    let sum_all: f64 = histogram
+        .iter()
+        .enumerate()
+        .map(|(value, &count)| value as f64 * count as f64)
+        .sum();
+
+    let mut best = (0, 0.0);
+    let mut weight_dark = 0;
+    let mut sum_dark = 0.0;
+    for (value, &count) in histogram.iter().enumerate() {
+        weight_dark += count;
+        sum_dark += value as f64 * count as f64;
+        let weight_bright = total - weight_dark;
+        if weight_dark == 0 || weight_bright == 0 {
+            continue;
+        }
+        let mean_dark = sum_dark / weight_dark as f64;
+        let mean_bright = (sum_all - sum_dark) / weight_bright as f64;
+        let variance =
+            weight_dark as f64 * weight_bright as f64 * (mean_dark - mean_bright).powi(2);
+        if variance > best.1 {
+            best = (value, variance);
+        }
+    }
+    best.0 as u8
+}
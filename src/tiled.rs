@@ -0,0 +1,218 @@
+use std::collections::{HashMap, HashSet};
+
+use image::{GrayImage, ImageBuffer, Luma, Primitive};
+use log::debug;
+
+use crate::{threshold_image, Connectivity, DetectOptions, Moments, Star, StarStats, Threshold};
+
+/// A horizontal run of star pixels, from `start` to `end` inclusive
+#[derive(Clone, Copy, Debug)]
+struct Run {
+    start: usize,
+    end: usize,
+    label: usize,
+}
+
+/// Finds stars one row at a time, only remembering the runs of star pixels in the row above
+///
+/// Parts of a group that meet in a later row are joined with a union-find over run labels, and
+/// a group is measured as soon as it doesn't continue into the current row. Memory use is
+/// bounded by the width of the image and the number of groups, not by its height, so rows can
+/// come from anywhere, e.g. a decoder that produces them as it reads the file.
+#[derive(Debug)]
+pub struct RowLabeller<'a> {
+    width: usize,
+    options: &'a DetectOptions,
+    y: usize,
+    parents: Vec<usize>,
+    /// Sums of the groups that are still growing, keyed by their root label
+    moments: HashMap<usize, Moments>,
+    above: Vec<Run>,
+    /// Kept stars with their root label. Labels are handed out in scan order and the smallest
+    /// one becomes the root, so it orders stars by where the scan first found them
+    stars: Vec<(usize, Star)>,
+}
+
+impl<'a> RowLabeller<'a> {
+    pub fn new(width: usize, options: &'a DetectOptions) -> Self {
+        RowLabeller {
+            width,
+            options,
+            y: 0,
+            parents: Vec::new(),
+            moments: HashMap::new(),
+            above: Vec::new(),
+            stars: Vec::new(),
+        }
+    }
+
+    /// Adds the next row, given as which pixels are stars and their original luma
+    pub fn push_row(&mut self, row: &[bool], luma: Option<&[u8]>) {
+        assert_eq!(row.len(), self.width, "Row has wrong width");
+        // How far past the ends of a run a run in the next row can be and still touch it
+        let reach = match self.options.connectivity {
+            Connectivity::Four => 0,
+            Connectivity::Eight | Connectivity::Radius(1) => 1,
+            Connectivity::Radius(radius) => {
+                panic!("Rows can only be labelled one at a time up to a radius of 1, got {radius}")
+            }
+        };
+        let y = self.y;
+        let mut runs = Vec::new();
+        let mut first_above = 0;
+        let mut x = 0;
+        while x < self.width {
+            if !row[x] {
+                x += 1;
+                continue;
+            }
+            let start = x;
+            while x < self.width && row[x] {
+                x += 1;
+            }
+            let end = x - 1;
+
+            // Runs above are sorted, so the ones ending too far left can't touch later runs
+            while first_above < self.above.len() && self.above[first_above].end + reach < start {
+                first_above += 1;
+            }
+            let mut label = None;
+            for above in &self.above[first_above..] {
+                if above.start > end + reach {
+                    break;
+                }
+                let other = above.label;
+                label = Some(match label {
+                    Some(label) => union(&mut self.parents, &mut self.moments, label, other),
+                    None => find(&mut self.parents, other),
+                });
+            }
+            let label = label.unwrap_or_else(|| {
+                let label = self.parents.len();
+                self.parents.push(label);
+                self.moments.insert(label, Moments::new(start, y));
+                label
+            });
+            let moments = self.moments.get_mut(&label).expect("Roots have moments");
+            for x in start..=end {
+                moments.add(x, y, luma.map(|luma| luma[x]));
+            }
+            runs.push(Run { start, end, label });
+        }
+
+        // Groups that didn't continue into this row are complete
+        let mut continued = HashSet::new();
+        for run in &mut runs {
+            run.label = find(&mut self.parents, run.label);
+            continued.insert(run.label);
+        }
+        for run in std::mem::replace(&mut self.above, runs) {
+            let root = find(&mut self.parents, run.label);
+            if !continued.contains(&root) {
+                if let Some(moments) = self.moments.remove(&root) {
+                    self.finish_group(root, moments);
+                }
+            }
+        }
+        self.y += 1;
+    }
+
+    /// Measures the groups that reach the last row and returns every star that was kept
+    pub fn finish(mut self) -> StarStats {
+        for run in std::mem::take(&mut self.above) {
+            let root = find(&mut self.parents, run.label);
+            if let Some(moments) = self.moments.remove(&root) {
+                self.finish_group(root, moments);
+            }
+        }
+        // The bottom edge is only known now
+        let (width, height, options) = (self.width, self.y, self.options);
+        self.stars.retain(|(_, star)| {
+            let touches = options.touches_border(star, width, height);
+            if touches {
+                debug!(
+                    "Discarded group of {} pixels at {:?} on the border",
+                    star.area, star.centroid
+                );
+            }
+            !touches
+        });
+        // Same order as count_groups, which sorts stars stably in the order it found them
+        self.stars
+            .sort_by_key(|(label, star)| (star.centroid.1, star.centroid.0, *label));
+        StarStats {
+            count: self.stars.len() as u64,
+            stars: self.stars.into_iter().map(|(_, star)| star).collect(),
+        }
+    }
+
+    fn finish_group(&mut self, root: usize, moments: Moments) {
+        let star = moments.into_star();
+        if self.options.accepts(&star) {
+            debug!("Star of {} pixels at {:?}", star.area, star.centroid);
+            self.stars.push((root, star));
+        } else {
+            debug!(
+                "Discarded group of {} pixels at {:?}",
+                star.area, star.centroid
+            );
+        }
+    }
+}
+
+fn find(parents: &mut [usize], mut label: usize) -> usize {
+    while parents[label] != label {
+        parents[label] = parents[parents[label]];
+        label = parents[label];
+    }
+    label
+}
+
+/// Joins the groups of both labels, returning the root of the joined group
+fn union(
+    parents: &mut [usize],
+    moments: &mut HashMap<usize, Moments>,
+    a: usize,
+    b: usize,
+) -> usize {
+    let (a, b) = (find(parents, a), find(parents, b));
+    if a == b {
+        return a;
+    }
+    let (root, child) = (a.min(b), a.max(b));
+    parents[child] = root;
+    let child = moments.remove(&child).expect("Roots have moments");
+    moments
+        .get_mut(&root)
+        .expect("Roots have moments")
+        .merge(&child);
+    root
+}
+
+/// Same as thresholding the image and calling [`count_groups`](crate::count_groups), but only
+/// `strip_height` rows are thresholded at a time instead of the whole image
+///
+/// The stars are the same, but as there is no bitmap of the whole image, the pixels of the
+/// kept stars aren't available afterwards.
+pub fn count_groups_tiled<T: Primitive + Send + Sync>(
+    img: &ImageBuffer<Luma<T>, Vec<T>>,
+    threshold: Threshold<T>,
+    luma: Option<&GrayImage>,
+    options: &DetectOptions,
+    strip_height: usize,
+) -> StarStats {
+    let (width, height) = (img.width() as usize, img.height() as usize);
+    let mut labeller = RowLabeller::new(width, options);
+    for top in (0..height).step_by(strip_height.max(1)) {
+        let rows = strip_height.max(1).min(height - top);
+        let pixels = img.as_raw()[top * width..(top + rows) * width].to_vec();
+        let strip = ImageBuffer::<Luma<T>, _>::from_raw(width as u32, rows as u32, pixels)
+            .expect("Strip has the size of its rows");
+        let stars = threshold_image(&strip, threshold);
+        for y in 0..rows {
+            let luma = luma.map(|luma| &luma.as_raw()[(top + y) * width..(top + y + 1) * width]);
+            labeller.push_row(stars.row(y), luma);
+        }
+    }
+    labeller.finish()
+}
@@ -0,0 +1,57 @@
+use image::{GrayImage, Luma};
+use star_counter::{
+    adaptive_threshold, count_groups, subtract_background, threshold_image, DetectOptions,
+    Threshold,
+};
+
+/// Three 3x3 stars 60 brighter than a sky that brightens from left to right
+fn gradient_field() -> GrayImage {
+    GrayImage::from_fn(64, 32, |x, y| {
+        let star = [8, 30, 52]
+            .iter()
+            .any(|&left| (left..left + 3).contains(&x))
+            && (14..17).contains(&y);
+        Luma([(x * 3 + if star { 60 } else { 0 }) as u8])
+    })
+}
+
+#[test]
+fn adaptive_threshold_finds_stars_on_a_gradient() {
+    let img = gradient_field();
+    let options = DetectOptions::default();
+
+    // The faint star on the dark side is missed while the bright side is one huge star
+    let global = count_groups(
+        &mut threshold_image(&img, Threshold::new(100)),
+        None,
+        &options,
+    );
+    assert_ne!(global.count, 3);
+    assert!(global.stars.iter().any(|star| star.area > 9));
+
+    let mut stars = adaptive_threshold(&img, 15, Threshold::new(20));
+    let adaptive = count_groups(&mut stars, None, &options);
+    assert_eq!(adaptive.count, 3);
+    assert!(adaptive.stars.iter().all(|star| star.area == 9));
+    let centroids: Vec<_> = adaptive.stars.iter().map(|star| star.centroid).collect();
+    assert_eq!(centroids, [(9, 15), (31, 15), (53, 15)]);
+}
+
+#[test]
+fn subtracting_the_background_flattens_a_gradient() {
+    let img = gradient_field();
+    let flat = subtract_background(&img, 15);
+    // The sky went up to 189, and is now below the sensitivity everywhere
+    assert!(flat.enumerate_pixels().all(|(x, y, pixel)| {
+        let star = img.get_pixel(x, y).0[0] as u32 > x * 3;
+        star || pixel.0[0] < 20
+    }));
+
+    let stats = count_groups(
+        &mut threshold_image(&flat, Threshold::new(20)),
+        None,
+        &DetectOptions::default(),
+    );
+    assert_eq!(stats.count, 3);
+    assert!(stats.stars.iter().all(|star| star.area == 9));
+}
@@ -0,0 +1,35 @@
+mod common;
+
+use std::fs;
+use std::process::Command;
+
+use common::{image, scratch_dir};
+use serde_json::Value;
+
+#[test]
+fn one_run_writes_every_requested_output() {
+    let dir = scratch_dir("all-outputs");
+    let input = dir.join("field.png");
+    image(&["#.....", "...##.", "...##.", "......"], 255)
+        .save(&input)
+        .unwrap();
+    let (csv, catalog) = (dir.join("stars.csv"), dir.join("stars.cat"));
+    let output = Command::new(env!("CARGO_BIN_EXE_star-counter"))
+        .arg("--file")
+        .arg(&input)
+        .args(["--output-image", "--format", "json", "--csv"])
+        .arg(&csv)
+        .arg("--catalog")
+        .arg(&catalog)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+
+    let report: Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["count"], 2);
+    assert!(dir.join("field-starred.png").is_file());
+    let csv = fs::read_to_string(&csv).unwrap();
+    assert_eq!(csv.lines().count(), 3, "{csv}");
+    assert!(fs::metadata(&catalog).unwrap().len() > 0);
+    fs::remove_dir_all(&dir).unwrap();
+}
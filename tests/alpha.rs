@@ -0,0 +1,32 @@
+mod common;
+
+use std::fs;
+use std::process::Command;
+
+use common::scratch_dir;
+use image::{Rgba, RgbaImage};
+use serde_json::Value;
+
+#[test]
+fn bright_pixels_that_are_fully_transparent_are_not_stars() {
+    let dir = scratch_dir("alpha");
+    let input = dir.join("field.png");
+    let mut img = RgbaImage::from_pixel(5, 3, Rgba([0, 0, 0, 255]));
+    img.put_pixel(1, 1, Rgba([255, 255, 255, 0]));
+    img.put_pixel(3, 1, Rgba([255, 255, 255, 255]));
+    img.save(&input).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_star-counter"))
+        .arg("--file")
+        .arg(&input)
+        .args(["--format", "json"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    // Only the opaque pixel counts at the default --alpha-cutoff
+    let report: Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["count"], 1);
+    let star = &report["stars"][0];
+    assert_eq!((&star["x"], &star["y"]), (&3.into(), &1.into()));
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,50 @@
+mod common;
+
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output};
+
+use common::{image, scratch_dir};
+
+fn count(input: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_star-counter"))
+        .arg("--file")
+        .arg(input)
+        .arg("--count-only")
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn listed_pixels_are_not_counted() {
+    let dir = scratch_dir("bad-pixels");
+    let input = dir.join("frame.png");
+    image(&["#......", "...##..", "...##..", "#.....#"], 255)
+        .save(&input)
+        .unwrap();
+    let list = dir.join("pixels.txt");
+    fs::write(&list, "# Stuck pixels\n0,0\n\n6, 3\n").unwrap();
+
+    let output = count(&input, &[]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "4\n");
+    let output = count(&input, &["--bad-pixels", list.to_str().unwrap()]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "2\n");
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn listed_pixels_outside_the_image_are_an_error() {
+    let dir = scratch_dir("bad-pixels-bounds");
+    let input = dir.join("frame.png");
+    image(&["#..", "..#"], 255).save(&input).unwrap();
+    let list = dir.join("pixels.txt");
+    fs::write(&list, "1,1\n3,0\n").unwrap();
+
+    let output = count(&input, &["--bad-pixels", list.to_str().unwrap()]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("outside the 3x2 image"), "{stderr}");
+    fs::remove_dir_all(&dir).unwrap();
+}
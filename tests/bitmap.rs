@@ -0,0 +1,16 @@
+use star_counter::Bitmap;
+
+// (3, 0) has the index of (0, 1), so these would read and write the next row without the
+// bounds check
+
+#[test]
+#[should_panic(expected = "(3, 0) is outside the bitmap")]
+fn reading_past_the_end_of_a_row_panics() {
+    Bitmap::new(3, 2).get(3, 0);
+}
+
+#[test]
+#[should_panic(expected = "(3, 0) is outside the bitmap")]
+fn writing_past_the_end_of_a_row_panics() {
+    Bitmap::new(3, 2).set(3, 0, true);
+}
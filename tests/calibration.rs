@@ -0,0 +1,104 @@
+mod common;
+
+use std::fs;
+use std::process::Command;
+
+use common::scratch_dir;
+use image::{GrayImage, Luma};
+use serde_json::Value;
+use star_counter::{count_groups, subtract_dark, threshold_image, DetectOptions, Threshold};
+
+/// Amp glow that brightens towards the top-left corner up to 120, the same in every frame
+fn glow(x: u32, y: u32) -> u8 {
+    120u32.saturating_sub((x + y) * 10) as u8
+}
+
+fn stars(img: &GrayImage) -> u64 {
+    count_groups(
+        &mut threshold_image(img, Threshold::new(20)),
+        None,
+        &DetectOptions::default(),
+    )
+    .count
+}
+
+#[test]
+fn subtracting_a_dark_frame_removes_corner_glow() {
+    let dark = GrayImage::from_fn(20, 20, |x, y| Luma([glow(x, y)]));
+    let img = GrayImage::from_fn(20, 20, |x, y| {
+        let star = (12..14).contains(&x) && (12..14).contains(&y);
+        Luma([glow(x, y) + if star { 150 } else { 0 }])
+    });
+    // The glow is counted as a star of its own
+    assert_eq!(stars(&img), 2);
+
+    let calibrated = subtract_dark(&img, &dark).unwrap();
+    assert_eq!(stars(&calibrated), 1);
+    assert_eq!(calibrated.get_pixel(0, 0), &Luma([0]));
+    assert_eq!(calibrated.get_pixel(12, 13), &Luma([150]));
+}
+
+#[test]
+fn a_dark_frame_of_another_size_is_rejected() {
+    let img = GrayImage::new(20, 20);
+    assert!(subtract_dark(&img, &GrayImage::new(20, 19)).is_none());
+    assert!(subtract_dark(&img, &GrayImage::new(10, 40)).is_none());
+}
+
+#[test]
+fn one_dark_frame_calibrates_every_file_of_the_run() {
+    let dir = scratch_dir("dark");
+    let dark = dir.join("dark.png");
+    GrayImage::from_fn(20, 20, |x, y| Luma([glow(x, y)]))
+        .save(&dark)
+        .unwrap();
+    for (name, left) in [("a.png", 4), ("b.png", 12)] {
+        GrayImage::from_fn(20, 20, |x, y| {
+            let star = (left..left + 2).contains(&x) && (12..14).contains(&y);
+            Luma([glow(x, y) + if star { 150 } else { 0 }])
+        })
+        .save(dir.join(name))
+        .unwrap();
+    }
+    let summary = dir.join("summary.json");
+    let status = Command::new(env!("CARGO_BIN_EXE_star-counter"))
+        .arg("--file")
+        .arg(dir.join("a.png"))
+        .arg("--file")
+        .arg(dir.join("b.png"))
+        .arg("--dark")
+        .arg(&dark)
+        .arg("--quiet")
+        .arg("--summary")
+        .arg(&summary)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    // Without the dark frame the glow would be a second star in both files
+    let summary: Value = serde_json::from_slice(&fs::read(&summary).unwrap()).unwrap();
+    assert_eq!(summary["files"][0]["count"], 1);
+    assert_eq!(summary["files"][1]["count"], 1);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn a_dark_frame_of_another_size_fails_the_file() {
+    let dir = scratch_dir("dark-size");
+    let (dark, input) = (dir.join("dark.png"), dir.join("field.png"));
+    GrayImage::new(20, 19).save(&dark).unwrap();
+    GrayImage::new(20, 20).save(&input).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_star-counter"))
+        .arg("--file")
+        .arg(&input)
+        .arg("--dark")
+        .arg(&dark)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("is 20x19 but the image is 20x20"),
+        "{stderr}"
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
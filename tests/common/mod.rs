@@ -0,0 +1,56 @@
+//! Helpers shared by the integration tests, which draw small bitmaps and images as rows of
+//! text: `#` is a star pixel and any other character is background
+
+// Every test file compiles this module on its own and uses only some of the helpers
+#![allow(dead_code)]
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use image::{GrayImage, Luma};
+use star_counter::{count_groups, Bitmap, Connectivity, DetectOptions, StarStats};
+
+/// The bitmap drawn by `rows`, which must all have the same length
+pub fn grid(rows: &[&str]) -> Bitmap {
+    let width = rows.first().map_or(0, |row| row.len());
+    assert!(
+        rows.iter().all(|row| row.len() == width),
+        "Rows have different lengths"
+    );
+    let data = rows
+        .iter()
+        .flat_map(|row| row.bytes().map(|pixel| pixel == b'#'))
+        .collect();
+    Bitmap::from_vec(width, rows.len(), data)
+}
+
+/// The image drawn by `rows`, with star pixels at `star` and the background black
+pub fn image(rows: &[&str], star: u8) -> GrayImage {
+    let stars = grid(rows);
+    GrayImage::from_fn(stars.width() as u32, stars.height() as u32, |x, y| {
+        Luma([if stars.get(x as usize, y as usize) {
+            star
+        } else {
+            0
+        }])
+    })
+}
+
+/// Counts the groups drawn by `rows` with the default options and `connectivity`
+pub fn count(rows: &[&str], connectivity: Connectivity) -> StarStats {
+    let options = DetectOptions {
+        connectivity,
+        verify: true,
+        ..DetectOptions::default()
+    };
+    count_groups(&mut grid(rows), None, &options)
+}
+
+/// An empty directory of its own for the test named `name`
+pub fn scratch_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("star-counter-{name}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
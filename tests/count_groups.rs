@@ -0,0 +1,515 @@
+mod common;
+
+use common::{count, grid, image};
+use image::{GrayImage, Luma};
+use star_counter::{
+    convert_to_image, count_components, count_groups, count_groups_tiled, count_stars_in_image,
+    label_groups, mark_group, threshold_image, Bitmap, BoundingBox, Connectivity, DetectOptions,
+    StarField, Threshold,
+};
+
+#[test]
+fn empty_grid_has_no_stars() {
+    let stats = count(&["....", "....", "...."], Connectivity::Eight);
+    assert_eq!(stats.count, 0);
+    assert!(stats.stars.is_empty());
+}
+
+#[test]
+fn grid_without_pixels_has_no_stars() {
+    let stats = count(&[], Connectivity::Eight);
+    assert_eq!(stats.count, 0);
+}
+
+#[test]
+fn single_pixel_is_a_star() {
+    let stats = count(&["...", ".#.", "..."], Connectivity::Eight);
+    assert_eq!(stats.count, 1);
+    let star = &stats.stars[0];
+    assert_eq!(star.area, 1);
+    assert_eq!(star.centroid, (1, 1));
+    assert_eq!(
+        star.bounding_box,
+        BoundingBox {
+            x_min: 1,
+            y_min: 1,
+            x_max: 1,
+            y_max: 1
+        }
+    );
+}
+
+#[test]
+fn isolated_pixels_are_separate_stars() {
+    let rows = ["#.#.#", ".....", "#.#.#"];
+    assert_eq!(count(&rows, Connectivity::Eight).count, 6);
+    assert_eq!(count(&rows, Connectivity::Four).count, 6);
+}
+
+#[test]
+fn diagonal_neighbours_touch_with_eight_connectivity() {
+    let rows = ["#...", ".#..", "..#.", "...#"];
+    let stats = count(&rows, Connectivity::Eight);
+    assert_eq!(stats.count, 1);
+    assert_eq!(stats.sizes(), [4]);
+}
+
+#[test]
+fn diagonal_neighbours_are_apart_with_four_connectivity() {
+    let rows = ["#...", ".#..", "..#.", "...#"];
+    assert_eq!(count(&rows, Connectivity::Four).count, 4);
+}
+
+#[test]
+fn anti_diagonal_neighbours_touch_with_eight_connectivity() {
+    // Only the north-east neighbour joins the pixels of each row
+    let rows = ["...#", "..#.", ".#..", "#..."];
+    assert_eq!(count(&rows, Connectivity::Eight).count, 1);
+    assert_eq!(count(&rows, Connectivity::Four).count, 4);
+}
+
+#[test]
+fn ring_is_one_star() {
+    let rows = [".###.", "#...#", "#...#", "#...#", ".###."];
+    let stats = count(&rows, Connectivity::Eight);
+    assert_eq!(stats.count, 1);
+    assert_eq!(stats.sizes(), [12]);
+    assert_eq!(stats.stars[0].centroid, (2, 2));
+    // The corners are cut, so the sides only touch diagonally
+    assert_eq!(count(&rows, Connectivity::Four).count, 4);
+}
+
+#[test]
+fn pixel_inside_a_ring_is_a_star_of_its_own() {
+    let rows = ["#####", "#...#", "#.#.#", "#...#", "#####"];
+    let stats = count(&rows, Connectivity::Eight);
+    assert_eq!(stats.count, 2);
+    let mut areas = stats.sizes();
+    areas.sort_unstable();
+    assert_eq!(areas, [1, 16]);
+}
+
+#[test]
+fn full_frame_blob_is_one_star() {
+    let rows = ["######"; 4];
+    let stats = count(&rows, Connectivity::Eight);
+    assert_eq!(stats.count, 1);
+    let star = &stats.stars[0];
+    assert_eq!(star.area, 24);
+    assert_eq!(
+        star.bounding_box,
+        BoundingBox {
+            x_min: 0,
+            y_min: 0,
+            x_max: 5,
+            y_max: 3
+        }
+    );
+}
+
+#[test]
+fn arms_that_only_join_at_the_bottom_are_one_star() {
+    // The scan labels every arm separately before reaching the row that joins them
+    let rows = ["#.#.#", "#.#.#", "#####"];
+    for connectivity in [Connectivity::Four, Connectivity::Eight] {
+        let stats = count(&rows, connectivity);
+        assert_eq!(stats.count, 1);
+        assert_eq!(stats.sizes(), [11]);
+    }
+}
+
+#[test]
+fn chains_of_joined_labels_are_one_star() {
+    let rows = ["#.#.#.#", ".#.#.#.", "......#", "######."];
+    let stats = count(&rows, Connectivity::Eight);
+    assert_eq!(stats.count, 1);
+    assert_eq!(stats.sizes(), [14]);
+}
+
+#[test]
+fn stars_are_sorted_by_centroid_row_then_column() {
+    let rows = ["....#", "#....", "..#..", "#...#"];
+    let stats = count(&rows, Connectivity::Eight);
+    let centroids: Vec<(usize, usize)> = stats.stars.iter().map(|star| star.centroid).collect();
+    assert_eq!(centroids, [(4, 0), (0, 1), (2, 2), (0, 3), (4, 3)]);
+}
+
+#[test]
+fn radius_joins_pixels_across_gaps() {
+    let rows = ["#..#...#", "........", "#......."];
+    assert_eq!(count(&rows, Connectivity::Eight).count, 4);
+    assert_eq!(count(&rows, Connectivity::Radius(2)).count, 3);
+    assert_eq!(count(&rows, Connectivity::Radius(3)).count, 2);
+    assert_eq!(count(&rows, Connectivity::Radius(4)).count, 1);
+}
+
+#[test]
+fn radius_one_and_zero_match_eight_and_four() {
+    let rows = ["#.#..", ".#...", "#..##", "..#.#"];
+    assert_eq!(
+        count(&rows, Connectivity::Radius(1)),
+        count(&rows, Connectivity::Eight)
+    );
+    assert_eq!(
+        count(&rows, Connectivity::Radius(0)),
+        count(&rows, Connectivity::Four)
+    );
+}
+
+#[test]
+fn groups_touching_the_border_are_discarded() {
+    let rows = ["#....", "..#..", "....#"];
+    let options = DetectOptions {
+        exclude_border: 1,
+        ..DetectOptions::default()
+    };
+    let stats = count_groups(&mut grid(&rows), None, &options);
+    assert_eq!(stats.count, 1);
+    assert_eq!(stats.stars[0].centroid, (2, 1));
+}
+
+#[test]
+fn brightness_is_measured_on_the_image() {
+    let rows = ["##..", "##..", "...#"];
+    let img = image(&rows, 200);
+    let stats = count_groups(&mut grid(&rows), Some(&img), &DetectOptions::default());
+    let brightness: Vec<u64> = stats.stars.iter().map(|star| star.brightness).collect();
+    assert_eq!(brightness, [800, 200]);
+    assert!(stats.stars.iter().all(|star| star.peak == 200));
+}
+
+#[test]
+fn label_map_agrees_with_the_count() {
+    let rows = ["#.#.#", "#.#.#", "#####", ".....", "#.#.#"];
+    for connectivity in [Connectivity::Four, Connectivity::Eight] {
+        let labels = label_groups(&grid(&rows), connectivity);
+        assert_eq!(labels.count() as u64, count(&rows, connectivity).count);
+    }
+}
+
+#[test]
+fn strips_find_the_same_stars_as_the_whole_image() {
+    let rows = [
+        "#..#....#.",
+        "#..#...#..",
+        "####..#...",
+        "......#..#",
+        ".##...#..#",
+        ".##.###...",
+        "........##",
+    ];
+    let img = image(&rows, 255);
+    let threshold = Threshold::new(20);
+    let options = DetectOptions::default();
+    let whole = count_groups(&mut grid(&rows), Some(&img), &options);
+    for strip_height in 1..=rows.len() {
+        let tiled = count_groups_tiled(&img, threshold, Some(&img), &options, strip_height);
+        assert_eq!(tiled, whole, "strips of {strip_height} rows");
+    }
+}
+
+#[test]
+fn lazy_stars_match_the_detected_ones() {
+    let rows = ["#..#.", "....#", "##...", "##..#", "...#."];
+    let img = image(&rows, 255);
+    for connectivity in [
+        Connectivity::Four,
+        Connectivity::Eight,
+        Connectivity::Radius(2),
+    ] {
+        let field = StarField::from_image(&img).connectivity(connectivity);
+        let mut lazy: Vec<_> = field.stars().collect();
+        lazy.sort_by_key(|star| (star.centroid.1, star.centroid.0));
+        assert_eq!(lazy, field.detect().stats().stars, "{connectivity}");
+    }
+}
+
+#[test]
+fn limit_stops_after_enough_stars() {
+    let rows = ["#.#.#", ".....", "#.#.#"];
+    let options = DetectOptions {
+        limit: Some(4),
+        ..DetectOptions::default()
+    };
+    let mut stars = grid(&rows);
+    let stats = count_groups(&mut stars, None, &options);
+    assert_eq!(stats.count, 4);
+    let centroids: Vec<(usize, usize)> = stats.stars.iter().map(|star| star.centroid).collect();
+    assert_eq!(centroids, [(0, 0), (2, 0), (4, 0), (0, 2)]);
+    // The stars that weren't counted aren't left in the bitmap either
+    assert_eq!(stars, grid(&["#.#.#", ".....", "#...."]));
+}
+
+#[test]
+fn checkerboard_is_one_star_with_eight_connectivity() {
+    const N: usize = 8;
+    let rows: Vec<String> = (0..N)
+        .map(|y| {
+            (0..N)
+                .map(|x| if (x + y) % 2 == 0 { '#' } else { '.' })
+                .collect()
+        })
+        .collect();
+    let rows: Vec<&str> = rows.iter().map(String::as_str).collect();
+    assert_eq!(count(&rows, Connectivity::Four).count, (N * N / 2) as u64);
+    let stats = count(&rows, Connectivity::Eight);
+    assert_eq!(stats.count, 1);
+    assert_eq!(stats.sizes(), [N * N / 2]);
+}
+
+#[test]
+fn sizes_are_the_areas_of_the_stars() {
+    let rows = ["###...", "###...", ".....#", ".....#"];
+    let stats = count(&rows, Connectivity::Eight);
+    assert_eq!(stats.sizes(), [6, 2]);
+    assert_eq!(stats.sizes().iter().sum::<usize>(), 8);
+}
+
+#[test]
+fn min_size_drops_a_single_pixel_but_keeps_a_block() {
+    let mut stars = grid(&["#....", "...##", "...##"]);
+    let options = DetectOptions {
+        min_size: 2,
+        ..DetectOptions::default()
+    };
+    let stats = count_groups(&mut stars, None, &options);
+    assert_eq!(stats.sizes(), [4]);
+    // The dropped pixel is gone from the grid the output image is drawn from
+    assert_eq!(stars, grid(&[".....", "...##", "...##"]));
+}
+
+#[test]
+fn max_elongation_drops_a_trail_but_keeps_a_round_star() {
+    let mut stars = grid(&["#######...", "........##", "........##"]);
+    let options = DetectOptions {
+        max_elongation: Some(3.0),
+        ..DetectOptions::default()
+    };
+    let stats = count_groups(&mut stars, None, &options);
+    assert_eq!(stats.sizes(), [4]);
+    assert_eq!(stats.stars[0].elongation(), 1.0);
+    assert_eq!(stars, grid(&["..........", "........##", "........##"]));
+}
+
+#[test]
+fn bounding_box_of_an_l_spans_both_arms() {
+    let rows = ["......", ".#....", ".#....", ".#....", ".####.", "......"];
+    let stats = count(&rows, Connectivity::Eight);
+    assert_eq!(stats.count, 1);
+    let bounding_box = stats.stars[0].bounding_box;
+    assert_eq!(
+        bounding_box,
+        BoundingBox {
+            x_min: 1,
+            y_min: 1,
+            x_max: 4,
+            y_max: 4
+        }
+    );
+    assert_eq!((bounding_box.width(), bounding_box.height()), (4, 4));
+}
+
+#[test]
+fn centroids_are_the_centres_of_two_rectangles() {
+    let rows = [
+        ".........",
+        ".###.....",
+        ".###.....",
+        ".###.....",
+        ".......#.",
+        ".......#.",
+        ".......#.",
+    ];
+    let stats = count(&rows, Connectivity::Eight);
+    let centroids: Vec<(usize, usize)> = stats.stars.iter().map(|star| star.centroid).collect();
+    assert_eq!(centroids, [(2, 2), (7, 5)]);
+}
+
+#[test]
+fn weighted_centroid_leans_towards_the_bright_end() {
+    let rows = ["###", "..."];
+    let mut img = image(&rows, 10);
+    img.put_pixel(2, 0, image::Luma([250]));
+    let stats = count_groups(&mut grid(&rows), Some(&img), &DetectOptions::default());
+    let star = &stats.stars[0];
+    assert_eq!(star.centroid, (1, 0));
+    // (0 * 10 + 1 * 10 + 2 * 250) / 270
+    let (x, y) = star.weighted_centroid;
+    assert!((x - 510.0 / 270.0).abs() < 1e-9, "{x}");
+    assert_eq!(y, 0.0);
+}
+
+#[test]
+fn a_single_black_pixel_has_no_stars() {
+    let img = image::GrayImage::new(1, 1);
+    assert_eq!(count_stars_in_image(&img, 20), 0);
+}
+
+#[test]
+fn an_image_without_columns_has_no_stars() {
+    let img = image::GrayImage::new(0, 5);
+    assert_eq!(count_stars_in_image(&img, 20), 0);
+    assert_eq!(
+        convert_to_image(&threshold_image(&img, Threshold::new(20))).dimensions(),
+        (0, 5)
+    );
+}
+
+#[test]
+fn an_all_white_image_is_one_star() {
+    let img = image::GrayImage::from_pixel(7, 4, image::Luma([255]));
+    assert_eq!(count_stars_in_image(&img, 20), 1);
+}
+
+#[test]
+fn non_square_grids_keep_width_and_height_apart() {
+    // 3 wide and 5 high, so swapping the axes would put pixels outside of the grid
+    let rows = ["#..", "...", "..#", "...", ".#."];
+    let stats = count(&rows, Connectivity::Eight);
+    let centroids: Vec<(usize, usize)> = stats.stars.iter().map(|star| star.centroid).collect();
+    assert_eq!(centroids, [(0, 0), (2, 2), (1, 4)]);
+
+    let img = convert_to_image(&grid(&rows));
+    assert_eq!(img.dimensions(), (3, 5));
+    assert_eq!(img, image(&rows, 255));
+}
+
+#[test]
+fn groups_over_max_size_are_discarded_and_erased() {
+    // 6 pixels on the left, 7 on the right
+    let rows = ["##...###", "##...###", "##....#."];
+    let mut stars = grid(&rows);
+    let options = DetectOptions {
+        max_size: Some(6),
+        ..DetectOptions::default()
+    };
+    let stats = count_groups(&mut stars, None, &options);
+    assert_eq!(stats.sizes(), [6]);
+    assert_eq!(stars, grid(&["##......", "##......", "##......"]));
+}
+
+#[test]
+fn large_solid_blob_is_filled_without_overflowing_the_stack() {
+    // A million pixels, far deeper than a recursive fill could go on a test thread's stack
+    let size = 1000;
+    let stars = Bitmap::from_vec(size, size, vec![true; size * size]);
+    let mut visited = Bitmap::new(size, size);
+    let group = mark_group(
+        (size / 2, size / 2),
+        &stars,
+        &mut visited,
+        Connectivity::Four,
+    );
+    assert_eq!(group.len(), size * size);
+    assert_eq!(visited, stars);
+
+    let options = DetectOptions {
+        limit: Some(1),
+        ..DetectOptions::default()
+    };
+    let stats = count_groups(&mut stars.clone(), None, &options);
+    assert_eq!(stats.sizes(), [size * size]);
+}
+
+#[test]
+fn strips_keep_the_filters_of_the_whole_image() {
+    // Stars that are cut by the strips, touch the border or are too small in any one strip
+    let mut state = 0x2545_f491_4f6c_dd1d_u64;
+    let img = GrayImage::from_fn(37, 53, |_, _| {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        Luma([if state.is_multiple_of(3) {
+            (state >> 56) as u8 | 0x80
+        } else {
+            0
+        }])
+    });
+    let threshold = Threshold::new(20);
+    let options = DetectOptions {
+        min_size: 3,
+        max_size: Some(40),
+        exclude_border: 2,
+        ..DetectOptions::default()
+    };
+    let whole = count_groups(&mut threshold_image(&img, threshold), Some(&img), &options);
+    assert!(whole.count > 10, "only {} stars", whole.count);
+    for strip_height in [1, 2, 5, 16, 52, 53, 100] {
+        let tiled = count_groups_tiled(&img, threshold, Some(&img), &options, strip_height);
+        assert_eq!(tiled, whole, "strips of {strip_height} rows");
+    }
+}
+
+#[test]
+fn a_star_on_the_top_edge_is_discarded_by_every_scan() {
+    let rows = [
+        "..##...", "..##...", ".......", "....##.", "....##.", ".......",
+    ];
+    let img = image(&rows, 255);
+    let options = DetectOptions {
+        exclude_border: 1,
+        ..DetectOptions::default()
+    };
+    let stats = count_groups(&mut grid(&rows), Some(&img), &options);
+    assert_eq!(stats.sizes(), [4]);
+    assert_eq!(stats.stars[0].centroid, (4, 3));
+
+    let tiled = count_groups_tiled(&img, Threshold::new(20), Some(&img), &options, 2);
+    assert_eq!(tiled, stats);
+    let field = StarField::from_image(&img).exclude_border(1);
+    let lazy: Vec<_> = field.stars().collect();
+    assert_eq!(lazy, stats.stars);
+    // Without a border both stars are kept
+    assert_eq!(count(&rows, Connectivity::Eight).count, 2);
+}
+
+#[test]
+fn lazy_stars_stop_at_the_limit() {
+    let img = image(&["#.#.#", ".....", "#.#.#"], 255);
+    let field = StarField::from_image(&img).limit(Some(4));
+    assert_eq!(field.stars().count(), 4);
+    assert_eq!(field.detect().count(), 4);
+    assert_eq!(field.limit(None).stars().count(), 6);
+}
+
+#[test]
+fn counting_components_agrees_with_counting_groups() {
+    // A scatter of pixels, runs and blocks that join in every direction
+    let mut state = 0x9e37_79b9_7f4a_7c15_u64;
+    let field: Vec<String> = (0..24)
+        .map(|_| {
+            (0..40)
+                .map(|_| {
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    if state % 5 < 2 {
+                        '#'
+                    } else {
+                        '.'
+                    }
+                })
+                .collect()
+        })
+        .collect();
+    let rows: Vec<&str> = field.iter().map(String::as_str).collect();
+    let shapes: [&[&str]; 4] = [
+        &rows,
+        &["#.#.#", "#.#.#", "#####"],
+        &[".###.", "#...#", "#...#", ".###."],
+        &["...#", "..#.", ".#..", "#..."],
+    ];
+    for rows in shapes {
+        for connectivity in [
+            Connectivity::Four,
+            Connectivity::Eight,
+            Connectivity::Radius(2),
+        ] {
+            assert_eq!(
+                count_components(&grid(rows), connectivity) as u64,
+                count(rows, connectivity).count,
+                "{connectivity}"
+            );
+        }
+    }
+}
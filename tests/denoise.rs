@@ -0,0 +1,43 @@
+mod common;
+
+use std::fs;
+use std::process::Command;
+
+use common::{image, scratch_dir};
+use serde_json::Value;
+
+#[test]
+fn denoise_removes_a_hot_pixel_but_keeps_a_star() {
+    let dir = scratch_dir("denoise");
+    let input = dir.join("frame.png");
+    image(
+        &[
+            "..........",
+            ".#........",
+            "..........",
+            "..........",
+            ".....####.",
+            ".....####.",
+            ".....####.",
+            ".....####.",
+            "..........",
+        ],
+        255,
+    )
+    .save(&input)
+    .unwrap();
+    let count = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_star-counter"))
+            .arg("--file")
+            .arg(&input)
+            .args(["--format", "json", "--sensitivity", "100"])
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{output:?}");
+        serde_json::from_slice::<Value>(&output.stdout).unwrap()["count"].clone()
+    };
+    assert_eq!(count(&[]), 2);
+    assert_eq!(count(&["--denoise"]), 1);
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,52 @@
+mod common;
+
+use std::fs;
+use std::process::Command;
+
+use common::scratch_dir;
+use image::{GrayImage, Luma};
+use serde_json::Value;
+
+/// Stars of the image found with the options, from the JSON report
+fn stars(name: &str, args: &[&str]) -> Vec<Value> {
+    let dir = scratch_dir(name);
+    let input = dir.join("frame.png");
+    // A star peaking at 200 in the middle of a ring at 100, and a hot pixel
+    let mut img = GrayImage::new(15, 9);
+    for y in 3..6 {
+        for x in 3..6 {
+            img.put_pixel(x, y, Luma([100]));
+        }
+    }
+    img.put_pixel(4, 4, Luma([200]));
+    img.put_pixel(12, 4, Luma([60]));
+    img.save(&input).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_star-counter"))
+        .arg("--file")
+        .arg(&input)
+        .args(["--format", "json"])
+        .args(args)
+        .output()
+        .unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(output.status.success(), "{output:?}");
+    let report: Value = serde_json::from_slice(&output.stdout).unwrap();
+    report["stars"].as_array().unwrap().clone()
+}
+
+#[test]
+fn blurred_detection_leaves_out_the_hot_pixel() {
+    assert_eq!(stars("detect-blur-sharp", &[]).len(), 2);
+    assert_eq!(stars("detect-blur", &["--detect-blur", "1"]).len(), 1);
+}
+
+#[test]
+fn stars_found_blurred_are_measured_sharp() {
+    let stars = stars("detect-blur-measure", &["--detect-blur", "1"]);
+    let star = &stars[0];
+    assert_eq!((&star["x"], &star["y"]), (&Value::from(4), &Value::from(4)));
+    // Blurring spreads the star over more pixels, but they add nothing to it
+    assert!(star["area"].as_u64().unwrap() > 9, "{star}");
+    assert_eq!(star["peak"], 200);
+    assert_eq!(star["brightness"], 200 + 8 * 100);
+}
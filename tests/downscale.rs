@@ -0,0 +1,55 @@
+mod common;
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use common::scratch_dir;
+use image::{GrayImage, Luma};
+use serde_json::Value;
+
+/// The top-left corners of the 2x2 stars, on even pixels so no block splits them
+const STARS: [(u32, u32); 3] = [(4, 6), (20, 10), (30, 32)];
+
+fn report(input: &Path, factor: &str) -> Value {
+    let output = Command::new(env!("CARGO_BIN_EXE_star-counter"))
+        .arg("--file")
+        .arg(input)
+        .args(["--format", "json", "--downscale", factor])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    serde_json::from_slice(&output.stdout).unwrap()
+}
+
+#[test]
+fn downscaling_a_sparse_field_finds_the_same_stars() {
+    let dir = scratch_dir("downscale");
+    let input = dir.join("field.png");
+    GrayImage::from_fn(40, 40, |x, y| {
+        let star = STARS
+            .iter()
+            .any(|&(left, top)| (left..left + 2).contains(&x) && (top..top + 2).contains(&y));
+        Luma([if star { 200 } else { 5 }])
+    })
+    .save(&input)
+    .unwrap();
+
+    let full = report(&input, "1");
+    let halved = report(&input, "2");
+    assert_eq!(full["count"], 3);
+    assert_eq!(halved["count"], 3);
+    let (full, halved) = (
+        full["stars"].as_array().unwrap(),
+        halved["stars"].as_array().unwrap(),
+    );
+    for (full, halved) in full.iter().zip(halved) {
+        assert_eq!(halved["area"], full["area"]);
+        assert_eq!(halved["bounding_box"], full["bounding_box"]);
+        for axis in ["x", "y"] {
+            let offset = halved[axis].as_f64().unwrap() - full[axis].as_f64().unwrap();
+            assert!(offset.abs() <= 1.0, "{axis} of {full} and {halved}");
+        }
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,70 @@
+mod common;
+
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::process::Command;
+
+use common::{image, scratch_dir};
+
+const FIELD: [&str; 3] = ["#....", "...##", "...##"];
+
+#[test]
+fn parameters_are_read_back_from_png_and_jpeg() {
+    let dir = scratch_dir("embed-params");
+    let input = dir.join("field.png");
+    image(&FIELD, 255).save(&input).unwrap();
+    for format in ["png", "jpg"] {
+        let status = Command::new(env!("CARGO_BIN_EXE_star-counter"))
+            .arg("--file")
+            .arg(&input)
+            .args([
+                "--quiet",
+                "--output-image",
+                "--embed-params",
+                "--sensitivity",
+                "30",
+            ])
+            .arg("--output-name")
+            .arg(dir.join(format!("field-starred.{format}")))
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+
+    let png = png::Decoder::new(BufReader::new(
+        File::open(dir.join("field-starred.png")).unwrap(),
+    ))
+    .read_info()
+    .unwrap();
+    let text: Vec<(&str, &str)> = png
+        .info()
+        .uncompressed_latin1_text
+        .iter()
+        .map(|chunk| (chunk.keyword.as_str(), chunk.text.as_str()))
+        .collect();
+    let software = format!("star-counter {}", env!("CARGO_PKG_VERSION"));
+    assert_eq!(
+        text,
+        [
+            ("Software", software.as_str()),
+            ("Sensitivity", "30"),
+            ("Connectivity", "8"),
+            ("Min size", "1"),
+            ("Star count", "2"),
+        ]
+    );
+
+    let jpeg = fs::read(dir.join("field-starred.jpg")).unwrap();
+    // The comment segment comes right after the start of image marker
+    assert_eq!(jpeg[..4], [0xFF, 0xD8, 0xFF, 0xFE]);
+    let length = u16::from_be_bytes([jpeg[4], jpeg[5]]) as usize;
+    let comment = std::str::from_utf8(&jpeg[6..4 + length]).unwrap();
+    assert_eq!(
+        comment,
+        format!("Software={software}; Sensitivity=30; Connectivity=8; Min size=1; Star count=2")
+    );
+    // The comment doesn't get in the way of decoding
+    let decoded = image::open(dir.join("field-starred.jpg")).unwrap();
+    assert_eq!((decoded.width(), decoded.height()), (5, 3));
+    fs::remove_dir_all(&dir).unwrap();
+}
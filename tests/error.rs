@@ -0,0 +1,127 @@
+mod common;
+
+use std::error::Error;
+use std::io::{self, ErrorKind};
+use std::time::Instant;
+
+use common::image;
+use star_counter::{
+    count_from_bytes, decode_preserving_depth, threshold_image, try_count_groups_until, Bitmap,
+    DetectOptions, Roi, StarCounterError, Threshold,
+};
+
+/// Messages of the error and its sources, outermost first
+fn chain(err: &StarCounterError) -> Vec<String> {
+    let mut messages = vec![err.to_string()];
+    let mut source = err.source();
+    while let Some(err) = source {
+        messages.push(err.to_string());
+        source = err.source();
+    }
+    messages
+}
+
+#[test]
+fn io_errors_keep_the_original_error_as_their_source() {
+    let err = StarCounterError::from(io::Error::new(ErrorKind::TimedOut, "share went away"));
+    assert!(matches!(&err, StarCounterError::Io(err) if err.kind() == ErrorKind::TimedOut));
+    assert_eq!(chain(&err), ["failed to read the image", "share went away"]);
+
+    let err = decode_preserving_depth("/nonexistent/frame.png").unwrap_err();
+    let StarCounterError::Io(source) = &err else {
+        panic!("{err:?}");
+    };
+    assert_eq!(source.kind(), ErrorKind::NotFound);
+}
+
+#[test]
+fn broken_images_are_decode_errors() {
+    let mut png = Vec::new();
+    image(&["#."], 200)
+        .write_to(
+            &mut io::Cursor::new(&mut png),
+            ::image::ImageOutputFormat::Png,
+        )
+        .unwrap();
+    png.truncate(png.len() / 2);
+    let err = count_from_bytes(&png, 20).unwrap_err();
+    assert!(matches!(err, StarCounterError::Decode(_)), "{err:?}");
+    let messages = chain(&err);
+    assert_eq!(messages[0], "failed to decode the image");
+    assert!(messages.len() > 1, "{messages:?}");
+}
+
+#[test]
+fn unknown_formats_are_unsupported() {
+    let err = count_from_bytes(b"definitely not an image", 20).unwrap_err();
+    assert!(
+        matches!(err, StarCounterError::UnsupportedFormat(_)),
+        "{err:?}"
+    );
+    let messages = chain(&err);
+    assert_eq!(messages[0], "the image format isn't supported");
+    assert!(messages.len() > 1, "{messages:?}");
+}
+
+#[test]
+fn rois_past_the_edge_are_invalid() {
+    let roi: Roi = "5,0,10,4".parse().unwrap();
+    assert!(roi.check_bounds(15, 4).is_ok());
+    let err = roi.check_bounds(14, 4).unwrap_err();
+    assert!(matches!(
+        err,
+        StarCounterError::InvalidRoi {
+            roi: Roi { x: 5, .. },
+            width: 14,
+            height: 4
+        }
+    ));
+    assert_eq!(chain(&err), ["ROI 5,0,10,4 does not fit in the 14x4 image"]);
+}
+
+#[test]
+fn masks_of_another_size_are_a_mismatch() {
+    let mut stars = Bitmap::new(4, 3);
+    let err = stars.try_intersect(&Bitmap::new(3, 4)).unwrap_err();
+    assert!(matches!(
+        err,
+        StarCounterError::MaskDimensionMismatch {
+            mask: (3, 4),
+            image: (4, 3)
+        }
+    ));
+    assert_eq!(chain(&err), ["mask is 3x4 but the image is 4x3"]);
+    assert!(stars.try_intersect(&Bitmap::new(4, 3)).is_ok());
+}
+
+#[test]
+fn counting_errors_keep_their_messages() {
+    let img = image(&["#.#.#", ".....", "#.#.#"], 200);
+    let stars = threshold_image(&img, Threshold::new(20));
+    let options = DetectOptions::default();
+
+    let err = try_count_groups_until(&mut stars.clone(), None, &options, Some(2), None, || {})
+        .map_err(StarCounterError::from)
+        .unwrap_err();
+    assert!(matches!(err, StarCounterError::TooManyGroups(_)), "{err:?}");
+    assert_eq!(
+        chain(&err),
+        ["found 6 groups of star pixels, more than the maximum of 2"]
+    );
+
+    let err = try_count_groups_until(
+        &mut stars.clone(),
+        None,
+        &options,
+        None,
+        Some(Instant::now()),
+        || {},
+    )
+    .map_err(StarCounterError::from)
+    .unwrap_err();
+    assert!(matches!(err, StarCounterError::PastDeadline(_)), "{err:?}");
+    assert_eq!(
+        chain(&err),
+        ["ran past the deadline after scanning 0 of 3 rows"]
+    );
+}
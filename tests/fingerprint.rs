@@ -0,0 +1,59 @@
+mod common;
+
+use std::fs;
+use std::process::Command;
+
+use common::{count, image, scratch_dir};
+use serde_json::Value;
+use star_counter::Connectivity;
+
+const FIELD: [&str; 5] = ["#....#", "......", "..##..", "..##..", "#....."];
+
+#[test]
+fn the_same_image_gives_the_same_fingerprint_every_run() {
+    let dir = scratch_dir("fingerprint");
+    let input = dir.join("field.png");
+    image(&FIELD, 255).save(&input).unwrap();
+    let fingerprint = || {
+        let output = Command::new(env!("CARGO_BIN_EXE_star-counter"))
+            .arg("--file")
+            .arg(&input)
+            .args(["--format", "json", "--fingerprint"])
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{output:?}");
+        let report: Value = serde_json::from_slice(&output.stdout).unwrap();
+        report["fingerprint"].as_str().unwrap().to_string()
+    };
+    let first = fingerprint();
+    assert_eq!(first.len(), 64, "{first}");
+    assert_eq!(fingerprint(), first);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn the_order_of_the_stars_does_not_change_the_fingerprint() {
+    let stats = count(&FIELD, Connectivity::Eight);
+    let mut reversed = stats.clone();
+    reversed.stars.reverse();
+    assert_ne!(reversed.stars, stats.stars);
+    assert_eq!(reversed.fingerprint(), stats.fingerprint());
+}
+
+#[test]
+fn different_stars_give_a_different_fingerprint() {
+    let stats = count(&FIELD, Connectivity::Eight);
+    // The block in the middle grows by a pixel
+    let grown = count(
+        &["#....#", "......", "..##..", "..###.", "#....."],
+        Connectivity::Eight,
+    );
+    assert_eq!(grown.count, stats.count);
+    assert_ne!(grown.fingerprint(), stats.fingerprint());
+    // A star goes missing
+    let fewer = count(
+        &["#.....", "......", "..##..", "..##..", "#....."],
+        Connectivity::Eight,
+    );
+    assert_ne!(fewer.fingerprint(), stats.fingerprint());
+}
@@ -0,0 +1,83 @@
+mod common;
+
+use std::fs::{self, File};
+use std::path::Path;
+use std::process::Command;
+
+use common::scratch_dir;
+use serde_json::Value;
+use star_counter::luma16_from_float;
+use tiff::encoder::{colortype, TiffEncoder};
+
+/// A 3x3 star with a NaN in its middle and NaNs and infinities scattered over the sky
+fn frame() -> (u32, u32, Vec<f32>) {
+    let (width, height) = (9, 7);
+    let mut samples = vec![0.5f32; (width * height) as usize];
+    for y in 2..5 {
+        for x in 2..5 {
+            samples[y * width as usize + x] = 1000.0;
+        }
+    }
+    for (x, y, value) in [
+        (3, 3, f32::NAN),
+        (7, 1, f32::NAN),
+        (7, 5, f32::INFINITY),
+        (0, 6, f32::NEG_INFINITY),
+        (6, 3, f32::NAN),
+    ] {
+        samples[y * width as usize + x] = value;
+    }
+    (width, height, samples)
+}
+
+fn write_tiff(path: &Path, (width, height, samples): &(u32, u32, Vec<f32>)) {
+    let mut encoder = TiffEncoder::new(File::create(path).unwrap()).unwrap();
+    encoder
+        .write_image::<colortype::Gray32Float>(*width, *height, samples)
+        .unwrap();
+}
+
+/// The JSON report of the binary on the file with the options
+fn report(input: &Path, args: &[&str]) -> Value {
+    let output = Command::new(env!("CARGO_BIN_EXE_star-counter"))
+        .arg("--file")
+        .arg(input)
+        .args(["--format", "json"])
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    serde_json::from_slice(&output.stdout).unwrap()
+}
+
+#[test]
+fn nan_pixels_are_never_part_of_a_star() {
+    let dir = scratch_dir("float-tiff");
+    let input = dir.join("frame.tif");
+    write_tiff(&input, &frame());
+    let normal = report(&input, &[]);
+    // NaNs are black, so they would be white once inverted
+    let inverted = report(&input, &["--invert", "--sensitivity", "1"]);
+    fs::remove_dir_all(dir).unwrap();
+
+    assert_eq!(normal["count"], 1);
+    assert_eq!(normal["stars"][0]["area"], 8);
+    // All of the sky but the NaNs and infinities on it
+    assert_eq!(inverted["count"], 1);
+    assert_eq!(inverted["stars"][0]["area"], 9 * 7 - 9 - 4);
+}
+
+#[test]
+fn finite_values_are_stretched_to_16_bits() {
+    let (width, height, samples) = frame();
+    let img = luma16_from_float(width, height, &samples).unwrap();
+    assert_eq!(img.get_pixel(0, 0).0, [0, u16::MAX]);
+    assert_eq!(img.get_pixel(2, 2).0, [u16::MAX, u16::MAX]);
+    assert_eq!(img.get_pixel(3, 3).0, [0, 0]);
+    assert_eq!(img.get_pixel(7, 5).0, [0, 0]);
+    assert!(luma16_from_float(width, height + 1, &samples).is_none());
+}
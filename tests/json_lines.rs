@@ -0,0 +1,41 @@
+mod common;
+
+use std::fs;
+use std::process::Command;
+
+use common::{image, scratch_dir};
+use serde_json::Value;
+
+#[test]
+fn one_line_for_every_star_then_the_count() {
+    let dir = scratch_dir("json-lines");
+    let input = dir.join("frame.png");
+    image(&["#.....#", "...##..", "...##..", "#......"], 255)
+        .save(&input)
+        .unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_star-counter"))
+        .arg("--file")
+        .arg(&input)
+        .args(["--format", "jsonl"])
+        .output()
+        .unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(output.status.success());
+
+    let lines: Vec<Value> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let (summary, stars) = lines.split_last().unwrap();
+    assert_eq!(summary["type"], "summary");
+    assert_eq!(summary["count"], 4);
+    assert_eq!(stars.len() as u64, summary["count"].as_u64().unwrap());
+    assert!(stars.iter().all(|star| star["type"] == "star"));
+    assert_eq!(
+        stars
+            .iter()
+            .map(|star| star["area"].as_u64().unwrap())
+            .sum::<u64>(),
+        7
+    );
+}
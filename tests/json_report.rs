@@ -0,0 +1,37 @@
+mod common;
+
+use std::fs;
+use std::process::Command;
+
+use common::{image, scratch_dir};
+use serde_json::Value;
+
+#[test]
+fn the_report_has_a_schema_version_compact_or_pretty() {
+    let dir = scratch_dir("json-report");
+    let input = dir.join("field.png");
+    image(&["#...", "..##", "..##"], 255).save(&input).unwrap();
+    let run = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_star-counter"))
+            .arg("--file")
+            .arg(&input)
+            .args(["--format", "json"])
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{output:?}");
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    let compact = run(&[]);
+    assert_eq!(compact.trim_end().lines().count(), 1, "{compact}");
+    let report: Value = serde_json::from_str(&compact).unwrap();
+    assert_eq!(report["schema_version"], 1);
+    assert_eq!(report["version"], env!("CARGO_PKG_VERSION"));
+    assert_eq!(report["count"], 2);
+
+    let pretty = run(&["--pretty"]);
+    assert!(pretty.lines().count() > 1, "{pretty}");
+    assert_eq!(serde_json::from_str::<Value>(&pretty).unwrap(), report);
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,69 @@
+mod common;
+
+use std::process::Command;
+
+use common::{count, image, scratch_dir};
+use serde_json::Value;
+use star_counter::{Connectivity, StarStats};
+
+const FIELD: [&str; 5] = ["#....###", "......##", ".##.....", ".##....#", ".......#"];
+
+#[test]
+fn the_largest_stars_come_first() {
+    let stats = count(&FIELD, Connectivity::Eight);
+    assert_eq!(stats.sizes(), [1, 5, 4, 2]);
+    let largest = StarStats {
+        count: stats.count,
+        stars: stats.largest(2),
+    };
+    assert_eq!(largest.sizes(), [5, 4]);
+    assert_eq!(largest.stars[0].centroid, (6, 0));
+    let every = StarStats {
+        count: stats.count,
+        stars: stats.largest(10),
+    };
+    assert_eq!(every.sizes(), [5, 4, 2, 1]);
+    assert!(stats.largest(0).is_empty());
+}
+
+#[test]
+fn equally_large_stars_keep_their_order() {
+    let stats = count(&["#.#.##", "......", "#....."], Connectivity::Eight);
+    let largest = stats.largest(3);
+    let centroids: Vec<_> = largest.iter().map(|star| star.centroid).collect();
+    assert_eq!(centroids, [(4, 0), (0, 0), (2, 0)]);
+}
+
+#[test]
+fn the_cli_lists_the_largest_stars() {
+    let dir = scratch_dir("report-largest");
+    let input = dir.join("field.png");
+    image(&FIELD, 255).save(&input).unwrap();
+    let run = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_star-counter"))
+            .arg("--file")
+            .arg(&input)
+            .args(["--quiet", "--report-largest", "2"])
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{output:?}");
+        output.stdout
+    };
+
+    let text = String::from_utf8(run(&[])).unwrap();
+    assert_eq!(
+        text,
+        "Found 4 stars\n\
+         Largest stars:\n\
+         5 pixels at 6,0 in 5,0,7,1\n\
+         4 pixels at 1,2 in 1,2,2,3\n"
+    );
+
+    let report: Value = serde_json::from_slice(&run(&["--format", "json"])).unwrap();
+    let largest = report["largest"].as_array().unwrap();
+    assert_eq!(largest.len(), 2);
+    assert_eq!(largest[0]["area"], 5);
+    assert_eq!(largest[1]["bounding_box"]["y_max"], 3);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
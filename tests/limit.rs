@@ -0,0 +1,29 @@
+mod common;
+
+use std::fs;
+use std::process::Command;
+
+use common::{image, scratch_dir};
+
+#[test]
+fn the_output_image_only_has_the_counted_stars() {
+    let dir = scratch_dir("limit");
+    let input = dir.join("field.png");
+    image(&["#.#.#", ".....", "#.#.#"], 255)
+        .save(&input)
+        .unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_star-counter"))
+        .arg("--file")
+        .arg(&input)
+        .args(["--count-only", "--limit", "2", "--output-image"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "2\n");
+
+    let starred = image::open(dir.join("field-starred.png"))
+        .unwrap()
+        .to_luma8();
+    assert_eq!(starred, image(&["#.#..", ".....", "....."], 255));
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,101 @@
+mod common;
+
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output};
+
+use common::{image, scratch_dir};
+use serde_json::Value;
+
+const FIELD: [&str; 4] = ["##....", "##....", "....##", "....##"];
+
+fn run(input: &Path, mask: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_star-counter"))
+        .arg("--file")
+        .arg(input)
+        .arg("--mask")
+        .arg(mask)
+        .args(["--format", "json"])
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+/// The star count of a successful run
+fn count(output: &Output) -> Value {
+    assert!(output.status.success(), "{output:?}");
+    serde_json::from_slice::<Value>(&output.stdout).unwrap()["count"].clone()
+}
+
+#[test]
+fn stars_in_the_black_of_the_mask_are_dropped() {
+    let dir = scratch_dir("mask");
+    let input = dir.join("field.png");
+    image(&FIELD, 255).save(&input).unwrap();
+    let mask = dir.join("mask.png");
+    image(&["###...", "###...", "###...", "###..."], 255)
+        .save(&mask)
+        .unwrap();
+
+    let output = run(&input, &mask, &[]);
+    assert_eq!(count(&output), 1);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn a_mask_of_another_size_is_an_error_unless_resized() {
+    let dir = scratch_dir("mask-size");
+    let input = dir.join("field.png");
+    image(&FIELD, 255).save(&input).unwrap();
+    let mask = dir.join("mask.png");
+    // Half the size of the field, keeping its left half
+    image(&["#..", "#.."], 255).save(&mask).unwrap();
+
+    let output = run(&input, &mask, &[]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("is 3x2 but the image is 6x4, use --resize-mask"),
+        "{stderr}"
+    );
+
+    let output = run(&input, &mask, &["--resize-mask"]);
+    assert_eq!(count(&output), 1);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn a_corner_outside_the_circular_mask_is_ignored() {
+    let dir = scratch_dir("circular-mask");
+    let input = dir.join("field.png");
+    image(
+        &[
+            "#........",
+            ".........",
+            ".........",
+            "...##....",
+            "...##....",
+            ".........",
+            ".........",
+            ".........",
+            ".........",
+        ],
+        255,
+    )
+    .save(&input)
+    .unwrap();
+    let stars = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_star-counter"))
+            .arg("--file")
+            .arg(&input)
+            .args(["--format", "json"])
+            .args(args)
+            .output()
+            .unwrap();
+        count(&output)
+    };
+    assert_eq!(stars(&[]), 2);
+    assert_eq!(stars(&["--circular-mask"]), 1);
+    assert_eq!(stars(&["--circular-mask", "3"]), 1);
+    fs::remove_dir_all(&dir).unwrap();
+}
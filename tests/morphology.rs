@@ -0,0 +1,81 @@
+mod common;
+
+use common::grid;
+use star_counter::{count_groups, morphology, Bitmap, DetectOptions};
+
+/// Two 3x3 stars joined by a bridge one pixel thick
+const BRIDGED: [&str; 5] = [
+    ".........",
+    ".###.###.",
+    ".#######.",
+    ".###.###.",
+    ".........",
+];
+
+fn count(stars: &Bitmap) -> u64 {
+    count_groups(&mut stars.clone(), None, &DetectOptions::default()).count
+}
+
+#[test]
+fn erosion_splits_stars_joined_by_a_bridge() {
+    let stars = grid(&BRIDGED);
+    assert_eq!(count(&stars), 1);
+    let eroded = morphology::erode(&stars, 1);
+    assert_eq!(
+        eroded,
+        grid(&[
+            ".........",
+            ".........",
+            "..#...#..",
+            ".........",
+            ".........",
+        ])
+    );
+    assert_eq!(count(&eroded), 2);
+}
+
+#[test]
+fn dilating_after_eroding_restores_the_stars_without_the_bridge() {
+    let opened = morphology::dilate(&morphology::erode(&grid(&BRIDGED), 1), 1);
+    assert_eq!(opened, morphology::open(&grid(&BRIDGED), 1));
+    assert_eq!(
+        opened,
+        grid(&[
+            ".........",
+            ".###.###.",
+            ".###.###.",
+            ".###.###.",
+            ".........",
+        ])
+    );
+    assert_eq!(count(&opened), 2);
+}
+
+#[test]
+fn bridging_joins_stars_a_pixel_apart() {
+    let split = grid(&[
+        "...........",
+        "...........",
+        "..###.###..",
+        "..###.###..",
+        "..###.###..",
+        "...........",
+        "...........",
+    ]);
+    assert_eq!(count(&split), 2);
+    // What --bridge 1 does before counting
+    let closed = morphology::close(&split, 1);
+    assert_eq!(
+        closed,
+        grid(&[
+            "...........",
+            "...........",
+            "..#######..",
+            "..#######..",
+            "..#######..",
+            "...........",
+            "...........",
+        ])
+    );
+    assert_eq!(count(&closed), 1);
+}
@@ -0,0 +1,56 @@
+mod common;
+
+use std::fs;
+use std::process::Command;
+
+use common::{image, scratch_dir};
+
+/// The header dict and the values of a version 1.0 .npy file
+fn read_npy(data: &[u8]) -> (String, Vec<f64>) {
+    assert_eq!(&data[..8], b"\x93NUMPY\x01\x00");
+    let header_len = u16::from_le_bytes([data[8], data[9]]) as usize;
+    let start = 10 + header_len;
+    assert_eq!(start % 64, 0, "Data isn't aligned");
+    let header = String::from_utf8(data[10..start].to_vec()).unwrap();
+    assert!(header.ends_with('\n'));
+    let values = data[start..]
+        .chunks(8)
+        .map(|value| f64::from_le_bytes(value.try_into().unwrap()))
+        .collect();
+    (header, values)
+}
+
+#[test]
+fn stars_are_written_as_an_array_of_rows() {
+    let dir = scratch_dir("npy");
+    let input = dir.join("frame.png");
+    image(&["#.....#", "...##..", "...##..", "#......"], 200)
+        .save(&input)
+        .unwrap();
+    let npy = dir.join("stars.npy");
+    let output = Command::new(env!("CARGO_BIN_EXE_star-counter"))
+        .arg("--file")
+        .arg(&input)
+        .arg("--npy")
+        .arg(&npy)
+        .arg("--quiet")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    let (header, values) = read_npy(&fs::read(&npy).unwrap());
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(header.contains("'descr': '<f8'"), "{header}");
+    assert!(header.contains("'fortran_order': False"), "{header}");
+    assert!(header.contains("'shape': (4, 4)"), "{header}");
+    let rows: Vec<&[f64]> = values.chunks(4).collect();
+    assert_eq!(
+        rows,
+        [
+            [0.0, 0.0, 1.0, 200.0],
+            [6.0, 0.0, 1.0, 200.0],
+            [3.0, 1.0, 4.0, 800.0],
+            [0.0, 3.0, 1.0, 200.0],
+        ]
+    );
+}
@@ -0,0 +1,33 @@
+mod common;
+
+use std::fs;
+use std::process::Command;
+
+use common::scratch_dir;
+use image::{GrayImage, Luma};
+
+#[test]
+fn derived_outputs_are_written_into_a_nested_output_dir() {
+    let dir = scratch_dir("output-dir");
+    let input = dir.join("field.png");
+    GrayImage::from_fn(16, 16, |x, y| {
+        Luma([if (x + y) % 7 == 0 { 255 } else { 0 }])
+    })
+    .save(&input)
+    .unwrap();
+    let output_dir = dir.join("results").join("run 1");
+
+    let status = Command::new(env!("CARGO_BIN_EXE_star-counter"))
+        .arg("--file")
+        .arg(&input)
+        .args(["--output-image", "--annotate", "--output-dir"])
+        .arg(&output_dir)
+        .status()
+        .unwrap();
+
+    assert!(status.success());
+    assert!(output_dir.join("field-starred.png").is_file());
+    assert!(output_dir.join("field-annotated.png").is_file());
+    assert!(!dir.join("field-starred.png").exists());
+    fs::remove_dir_all(&dir).unwrap();
+}
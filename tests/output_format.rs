@@ -0,0 +1,45 @@
+mod common;
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use common::{image, scratch_dir};
+
+const FIELD: [&str; 4] = ["#......", "...##..", "...##..", "......#"];
+
+/// Runs the binary on the image with the options, asserting that it succeeds
+fn run(input: &Path, args: &[&str]) {
+    let status = Command::new(env!("CARGO_BIN_EXE_star-counter"))
+        .arg("--file")
+        .arg(input)
+        .args(args)
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
+
+#[test]
+fn netpbm_outputs_round_trip_to_the_same_pixels() {
+    let dir = scratch_dir("output-format");
+    let input = dir.join("field.png");
+    image(&FIELD, 180).save(&input).unwrap();
+    run(&input, &["--output-image", "--annotate"]);
+    run(
+        &input,
+        &["--output-image", "--annotate", "--output-format", "pgm"],
+    );
+    run(&input, &["--annotate", "--output-format", "ppm"]);
+
+    let starred = fs::read(dir.join("field-starred.pgm")).unwrap();
+    assert!(starred.starts_with(b"P5\n7 4\n255\n"));
+    let starred = image::open(dir.join("field-starred.pgm")).unwrap();
+    assert_eq!(starred.to_luma8(), image(&FIELD, 255));
+    let png = image::open(dir.join("field-annotated.png")).unwrap();
+    let ppm = image::open(dir.join("field-annotated.ppm")).unwrap();
+    assert_eq!(ppm.to_rgb8(), png.to_rgb8());
+    // Colours are written as their luma
+    let pgm = image::open(dir.join("field-annotated.pgm")).unwrap();
+    assert_eq!(pgm.to_luma8(), png.to_luma8());
+    fs::remove_dir_all(&dir).unwrap();
+}
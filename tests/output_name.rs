@@ -0,0 +1,40 @@
+mod common;
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+use common::scratch_dir;
+use image::{GrayImage, ImageFormat, Luma};
+
+/// Writes a small field to `name` in its own directory and runs --output-image on it,
+/// returning the directory
+fn output_image_of(test: &str, name: &str) -> PathBuf {
+    let dir = scratch_dir(test);
+    let input = dir.join(name);
+    GrayImage::from_fn(8, 8, |x, y| Luma([if (x, y) == (3, 3) { 255 } else { 0 }]))
+        .save_with_format(&input, ImageFormat::Png)
+        .unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_star-counter"))
+        .arg("--file")
+        .arg(&input)
+        .arg("--output-image")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    dir
+}
+
+#[test]
+fn only_the_last_dot_starts_the_extension() {
+    let dir = output_image_of("output-dotted", "a.b.c.png");
+    assert!(dir.join("a.b.c-starred.png").is_file());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn inputs_without_an_extension_give_a_jpg() {
+    let dir = output_image_of("output-no-extension", "frame");
+    assert!(dir.join("frame-starred.jpg").is_file());
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,85 @@
+mod common;
+
+use std::process::Command;
+
+use common::{grid, scratch_dir};
+use image::{GrayImage, Luma, Rgb, RgbImage};
+use star_counter::overlay;
+
+const STARS: [&str; 3] = ["#...", ".##.", "...."];
+
+/// A gradient, so every pixel of the original is different
+fn frame() -> GrayImage {
+    GrayImage::from_fn(4, 3, |x, y| Luma([(y * 4 + x) as u8 * 20 + 10]))
+}
+
+fn gray(img: &GrayImage) -> RgbImage {
+    RgbImage::from_fn(img.width(), img.height(), |x, y| {
+        let luma = img.get_pixel(x, y).0[0];
+        Rgb([luma, luma, luma])
+    })
+}
+
+#[test]
+fn no_opacity_keeps_the_original() {
+    let img = frame();
+    assert_eq!(
+        overlay(&img, &grid(&STARS), Rgb([255, 0, 0]), 0.0),
+        gray(&img)
+    );
+}
+
+#[test]
+fn full_opacity_paints_the_stars_solid() {
+    let img = frame();
+    let color = Rgb([0, 200, 255]);
+    let output = overlay(&img, &grid(&STARS), color, 1.0);
+    let stars = grid(&STARS);
+    for (x, y, &pixel) in output.enumerate_pixels() {
+        if stars.get(x as usize, y as usize) {
+            assert_eq!(pixel, color);
+        } else {
+            let luma = img.get_pixel(x, y).0[0];
+            assert_eq!(pixel, Rgb([luma, luma, luma]));
+        }
+    }
+}
+
+#[test]
+fn half_opacity_blends_with_the_original() {
+    let img = GrayImage::from_pixel(1, 1, Luma([100]));
+    let output = overlay(&img, &grid(&["#"]), Rgb([255, 0, 50]), 0.5);
+    // Halves round up
+    assert_eq!(output.get_pixel(0, 0), &Rgb([178, 50, 75]));
+}
+
+#[test]
+fn the_cli_blends_the_stars_over_the_searched_region() {
+    let dir = scratch_dir("overlay");
+    let input = dir.join("frame.png");
+    let img = GrayImage::from_fn(6, 4, |x, y| Luma([if (x, y) == (3, 2) { 250 } else { 40 }]));
+    img.save(&input).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_star-counter"))
+        .arg("--file")
+        .arg(&input)
+        .args([
+            "--output-image",
+            "--overlay-opacity",
+            "1",
+            "--star-color",
+            "#00ff00",
+        ])
+        .args(["--sensitivity", "100", "--roi", "2,1,3,2", "--output-dir"])
+        .arg(&dir)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+
+    let written = image::open(dir.join("frame-starred.png"))
+        .unwrap()
+        .to_rgb8();
+    assert_eq!(written.dimensions(), (3, 2));
+    assert_eq!(written.get_pixel(1, 1), &Rgb([0, 255, 0]));
+    assert_eq!(written.get_pixel(0, 0), &Rgb([40, 40, 40]));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,86 @@
+mod common;
+
+use common::grid;
+use star_counter::{
+    count_groups, label_groups, label_groups_parallel, trace_groups, Bitmap, Connectivity,
+    DetectOptions,
+};
+
+/// Random star pixels covering about `fill` of the field, different for every `seed`
+fn random_field(width: usize, height: usize, fill: f64, seed: u64) -> Bitmap {
+    let mut state = seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1;
+    let mut field = Bitmap::new(width, height);
+    for y in 0..height {
+        for x in 0..width {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            let value = (state >> 11) as f64 / (1u64 << 53) as f64;
+            field.set(x, y, value < fill);
+        }
+    }
+    field
+}
+
+/// Runs `f` on enough threads to split the fields below into several strips
+fn on_threads<T: Send>(f: impl FnOnce() -> T + Send) -> T {
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(8)
+        .build()
+        .unwrap()
+        .install(f)
+}
+
+#[test]
+fn strips_label_random_fields_like_the_serial_pass() {
+    for (seed, fill) in [(1, 0.05), (2, 0.3), (3, 0.5), (4, 0.6), (5, 0.9)] {
+        let field = random_field(97, 700, fill, seed);
+        for connectivity in [Connectivity::Four, Connectivity::Eight] {
+            let parallel = on_threads(|| label_groups_parallel(&field, connectivity));
+            assert_eq!(
+                parallel,
+                label_groups(&field, connectivity),
+                "seed {seed}, {connectivity}"
+            );
+            let flood_filled = trace_groups(&field, connectivity, usize::MAX, |_| {});
+            assert_eq!(
+                parallel.count(),
+                flood_filled,
+                "seed {seed}, {connectivity}"
+            );
+        }
+    }
+}
+
+#[test]
+fn groups_across_many_strips_are_joined() {
+    // A diagonal line and a zig-zag crossing every boundary between the strips
+    let rows: Vec<String> = (0..600)
+        .map(|y| {
+            let mut row = vec!['.'; 40];
+            row[y * 30 / 599] = '#';
+            row[35 + (y / 3) % 2] = '#';
+            row.into_iter().collect()
+        })
+        .collect();
+    let rows: Vec<&str> = rows.iter().map(String::as_str).collect();
+    let field = grid(&rows);
+    let eight = on_threads(|| label_groups_parallel(&field, Connectivity::Eight));
+    assert_eq!(eight.count(), 2);
+    assert_eq!(eight, label_groups(&field, Connectivity::Eight));
+    let four = on_threads(|| label_groups_parallel(&field, Connectivity::Four));
+    assert_eq!(four, label_groups(&field, Connectivity::Four));
+}
+
+#[test]
+fn counting_gives_the_same_stars_on_any_number_of_threads() {
+    let field = random_field(120, 500, 0.4, 6);
+    let options = DetectOptions::default();
+    let serial = rayon::ThreadPoolBuilder::new()
+        .num_threads(1)
+        .build()
+        .unwrap()
+        .install(|| count_groups(&mut field.clone(), None, &options));
+    let parallel = on_threads(|| count_groups(&mut field.clone(), None, &options));
+    assert_eq!(parallel, serial);
+}
@@ -0,0 +1,51 @@
+use std::io::{self, Cursor, ErrorKind};
+use std::time::Duration;
+
+use star_counter::read_with_retries;
+
+/// Opens a reader of `data`, failing with the errors first
+fn flaky<'a>(
+    mut errors: Vec<ErrorKind>,
+    data: &'static [u8],
+    opened: &'a mut u32,
+) -> impl FnMut() -> io::Result<Cursor<&'static [u8]>> + 'a {
+    errors.reverse();
+    move || {
+        *opened += 1;
+        match errors.pop() {
+            Some(kind) => Err(io::Error::from(kind)),
+            None => Ok(Cursor::new(data)),
+        }
+    }
+}
+
+#[test]
+fn transient_errors_are_retried_until_the_read_succeeds() {
+    let mut opened = 0;
+    let open = flaky(
+        vec![ErrorKind::TimedOut, ErrorKind::ConnectionReset],
+        b"P5",
+        &mut opened,
+    );
+    let data = read_with_retries(open, 3, Duration::ZERO).unwrap();
+    assert_eq!(data, b"P5");
+    assert_eq!(opened, 3);
+}
+
+#[test]
+fn the_last_transient_error_is_returned_when_the_retries_run_out() {
+    let mut opened = 0;
+    let open = flaky(vec![ErrorKind::TimedOut; 3], b"P5", &mut opened);
+    let err = read_with_retries(open, 2, Duration::ZERO).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::TimedOut);
+    assert_eq!(opened, 3);
+}
+
+#[test]
+fn other_errors_are_not_retried() {
+    let mut opened = 0;
+    let open = flaky(vec![ErrorKind::NotFound], b"P5", &mut opened);
+    let err = read_with_retries(open, 5, Duration::ZERO).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::NotFound);
+    assert_eq!(opened, 1);
+}
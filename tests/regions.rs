@@ -0,0 +1,67 @@
+mod common;
+
+use std::fs;
+use std::process::Command;
+
+use common::{image, scratch_dir};
+
+/// A horizontal bar three times as long as it is thick and a trail running down to the right
+const ELONGATED: [&str; 12] = [
+    "............",
+    ".#########..",
+    ".#########..",
+    ".#########..",
+    "............",
+    "..#.........",
+    "...#........",
+    "....#.......",
+    ".....#......",
+    "......#.....",
+    ".......#....",
+    "............",
+];
+
+/// The `(x, y, semi-major axis, semi-minor axis, angle)` of the ellipses in the region file
+fn ellipses(regions: &str) -> Vec<[f64; 5]> {
+    regions
+        .lines()
+        .filter_map(|line| line.strip_prefix("ellipse(")?.strip_suffix(')'))
+        .map(|values| {
+            let values: Vec<f64> = values.split(',').map(|v| v.parse().unwrap()).collect();
+            values.try_into().unwrap()
+        })
+        .collect()
+}
+
+#[test]
+fn elongated_stars_get_ellipses_of_their_shape() {
+    let dir = scratch_dir("regions");
+    let input = dir.join("frame.png");
+    let regions = dir.join("frame.reg");
+    image(&ELONGATED, 200).save(&input).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_star-counter"))
+        .arg("--file")
+        .arg(&input)
+        .arg("--regions")
+        .arg(&regions)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let written = fs::read_to_string(&regions).unwrap();
+    fs::remove_dir_all(dir).unwrap();
+
+    let ellipses = ellipses(&written);
+    assert_eq!(ellipses.len(), 2, "{written}");
+    let [x, y, major, minor, angle] = ellipses[0];
+    // DS9 counts from 1 and up from the bottom row
+    assert_eq!((x, y), (6.0, 10.0));
+    assert!((major / minor - 3.0).abs() < 1e-3, "{written}");
+    assert!((major - 27f64.sqrt()).abs() < 1e-3, "{written}");
+    assert_eq!(angle, 0.0);
+
+    let [x, y, major, minor, angle] = ellipses[1];
+    assert_eq!((x, y), (5.5, 4.5));
+    assert!(major / minor > 5.0, "{written}");
+    // Down to the right on the image is up to the left with y pointing up
+    assert_eq!(angle, 135.0);
+}
@@ -0,0 +1,58 @@
+mod common;
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use common::{image, scratch_dir};
+use serde_json::{json, Value};
+
+/// A star on the left, one in the middle and one on the right
+const FIELD: [&str; 5] = [
+    "...........",
+    ".#...##...#",
+    ".....##....",
+    "...........",
+    "...........",
+];
+
+fn run(input: &Path, args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_star-counter"))
+        .arg("--file")
+        .arg(input)
+        .args(["--roi", "left=0,0,7,5", "--roi", "right=4,0,7,5"])
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn a_star_in_overlapping_rois_counts_for_both() {
+    let dir = scratch_dir("rois");
+    let input = dir.join("field.png");
+    image(&FIELD, 200).save(&input).unwrap();
+    let text = run(&input, &["--quiet"]);
+    let report: Value = serde_json::from_str(&run(&input, &["--format", "json"])).unwrap();
+    fs::remove_dir_all(dir).unwrap();
+
+    assert_eq!(
+        text,
+        "Found 3 stars\nStars in left (0,0,7,5): 2\nStars in right (4,0,7,5): 2\n"
+    );
+    assert_eq!(report["count"], 3);
+    assert_eq!(
+        report["rois"],
+        json!([
+            {"name": "left", "x": 0, "y": 0, "width": 7, "height": 5, "count": 2},
+            {"name": "right", "x": 4, "y": 0, "width": 7, "height": 5, "count": 2},
+        ])
+    );
+    // Named regions don't limit the search
+    assert!(report["parameters"].get("roi").is_none());
+}
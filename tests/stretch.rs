@@ -0,0 +1,87 @@
+use image::{GrayImage, Luma};
+use star_counter::{
+    count_groups, normalize, stretch, threshold_image, DetectOptions, Stretch, Threshold,
+};
+
+/// A bright star on the left and a faint one on the right of a black sky
+fn field() -> GrayImage {
+    let mut img = GrayImage::new(12, 5);
+    for (x, y) in [(1, 1), (2, 1), (1, 2), (2, 2)] {
+        img.put_pixel(x, y, Luma([200]));
+    }
+    for (x, y) in [(8, 2), (9, 2)] {
+        img.put_pixel(x, y, Luma([6]));
+    }
+    img
+}
+
+fn count(img: &GrayImage) -> u64 {
+    count_at(img, 20)
+}
+
+fn count_at(img: &GrayImage, sensitivity: u8) -> u64 {
+    let mut stars = threshold_image(img, Threshold::new(sensitivity));
+    count_groups(&mut stars, Some(img), &DetectOptions::default()).count
+}
+
+#[test]
+fn faint_star_is_only_counted_after_a_log_stretch() {
+    let img = field();
+    assert_eq!(count(&img), 1);
+    assert_eq!(count(&stretch(&img, Stretch::Log)), 2);
+}
+
+#[test]
+fn stretches_keep_black_and_white() {
+    let img = GrayImage::from_fn(256, 1, |x, _| Luma([x as u8]));
+    for curve in [Stretch::None, Stretch::Log, Stretch::Sqrt, Stretch::Asinh] {
+        let stretched = stretch(&img, curve);
+        assert_eq!(stretched.get_pixel(0, 0).0[0], 0, "{curve}");
+        assert_eq!(stretched.get_pixel(255, 0).0[0], 255, "{curve}");
+        let values: Vec<u8> = stretched.pixels().map(|pixel| pixel.0[0]).collect();
+        assert!(values.windows(2).all(|pair| pair[0] <= pair[1]), "{curve}");
+    }
+    assert_eq!(stretch(&img, Stretch::None), img);
+}
+
+/// A bright and a faint star on a grey sky, both only a little brighter than it
+fn low_contrast() -> GrayImage {
+    let mut img = GrayImage::from_pixel(12, 5, Luma([100]));
+    img.put_pixel(1, 1, Luma([130]));
+    img.put_pixel(2, 1, Luma([130]));
+    img.put_pixel(8, 2, Luma([115]));
+    img
+}
+
+#[test]
+fn normalizing_a_low_contrast_frame_counts_like_its_stretched_version() {
+    let img = low_contrast();
+    let normalized = normalize(&img, 0.0);
+    assert_eq!(normalized.get_pixel(0, 0).0[0], 0);
+    assert_eq!(normalized.get_pixel(1, 1).0[0], 255);
+    assert_eq!(normalized.get_pixel(8, 2).0[0], 128);
+    // All of the sky passes the usual sensitivity, but not once it is black
+    assert_eq!(count_at(&img, 20), 1);
+    assert_eq!(count_at(&normalized, 20), 2);
+    assert_eq!(count_at(&img, 110), 2);
+    assert_eq!(count_at(&normalized, 85), 2);
+    assert_eq!(count_at(&img, 120), 1);
+    assert_eq!(count_at(&normalized, 170), 1);
+}
+
+#[test]
+fn normalizing_with_clipping_ignores_a_hot_pixel() {
+    let mut img = low_contrast();
+    img.put_pixel(11, 4, Luma([255]));
+    assert_eq!(normalize(&img, 0.0).get_pixel(8, 2).0[0], 25);
+    // 2% of the 60 pixels is more than the hot pixel, so it is left out when finding the range
+    let clipped = normalize(&img, 2.0);
+    assert_eq!(clipped.get_pixel(8, 2).0[0], 128);
+    assert_eq!(clipped.get_pixel(11, 4).0[0], 255);
+    assert_eq!(
+        normalize(&GrayImage::from_pixel(3, 3, Luma([7])), 0.0)
+            .get_pixel(1, 1)
+            .0[0],
+        7
+    );
+}
@@ -0,0 +1,94 @@
+mod common;
+
+use common::grid;
+use image::{GrayImage, Luma};
+use star_counter::{
+    convert_to_image, count_groups, is_in_band, threshold_image, Bitmap, DetectOptions, Threshold,
+};
+
+/// Every brightness in a pattern that differs from row to row, so rows mixed up between
+/// threads would show
+fn generated(width: u32, height: u32) -> GrayImage {
+    GrayImage::from_fn(width, height, |x, y| {
+        Luma([((x * 7 + y * 13 + x * y) % 256) as u8])
+    })
+}
+
+#[test]
+fn rows_thresholded_in_parallel_match_a_serial_scan() {
+    let img = generated(317, 251);
+    let threshold = Threshold::new(128);
+    let mut serial = Bitmap::new(317, 251);
+    for (x, y, pixel) in img.enumerate_pixels() {
+        serial.set(x as usize, y as usize, threshold.is_star(pixel));
+    }
+    let stars = threshold_image(&img, threshold);
+    assert_eq!(stars, serial);
+
+    let serial_image = GrayImage::from_fn(317, 251, |x, y| {
+        Luma([if serial.get(x as usize, y as usize) {
+            255
+        } else {
+            0
+        }])
+    });
+    assert_eq!(convert_to_image(&stars), serial_image);
+}
+
+#[test]
+fn inverted_threshold_finds_dark_stars_on_a_light_sky() {
+    // Dark stars at 20 on a sky at 230, the way a scanned plate looks
+    let rows = ["##....", "##....", ".....#"];
+    let dark = grid(&rows);
+    let img = GrayImage::from_fn(6, 3, |x, y| {
+        Luma([if dark.get(x as usize, y as usize) {
+            20
+        } else {
+            230
+        }])
+    });
+    let inverted = Threshold {
+        invert: true,
+        ..Threshold::new(100)
+    };
+    let mut stars = threshold_image(&img, inverted);
+    assert_eq!(stars, dark);
+    let stats = count_groups(&mut stars, Some(&img), &DetectOptions::default());
+    assert_eq!(stats.sizes(), [4, 1]);
+    // Without inverting, the sky is one big star
+    assert_eq!(
+        threshold_image(&img, Threshold::new(100)),
+        grid(&["..####", "..####", "#####."])
+    );
+}
+
+#[test]
+fn the_band_excludes_its_low_edge_and_includes_its_high_edge() {
+    let (low, high) = (100u8, 200u8);
+    let edges = [
+        (99, false),
+        (100, false),
+        (101, true),
+        (150, true),
+        (199, true),
+        (200, true),
+        (201, false),
+        (255, false),
+    ];
+    for (value, expected) in edges {
+        assert_eq!(
+            is_in_band(&Luma([value]), low, high),
+            expected,
+            "{value} in {low}..={high}"
+        );
+    }
+
+    let threshold = Threshold {
+        max_sensitivity: Some(high),
+        ..Threshold::new(low)
+    };
+    let img = GrayImage::from_fn(256, 1, |x, _| Luma([x as u8]));
+    let stars = threshold_image(&img, threshold);
+    let band: Vec<usize> = (0..256).filter(|&x| stars.get(x, 0)).collect();
+    assert_eq!(band, (101..=200).collect::<Vec<_>>());
+}
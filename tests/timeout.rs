@@ -0,0 +1,101 @@
+mod common;
+
+use std::fs;
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+use common::scratch_dir;
+use image::{GrayImage, Luma};
+use star_counter::{
+    threshold_image, try_count_groups_until, Connectivity, CountError, DetectOptions, PastDeadline,
+    Threshold,
+};
+
+/// A checkerboard, so every other pixel is a star of its own with 4 neighbours
+fn dense(size: u32) -> GrayImage {
+    GrayImage::from_fn(size, size, |x, y| {
+        Luma([if (x + y) % 2 == 0 { 255 } else { 0 }])
+    })
+}
+
+#[test]
+fn counting_gives_up_once_the_deadline_has_passed() {
+    let img = dense(200);
+    let mut stars = threshold_image(&img, Threshold::new(20));
+    let options = DetectOptions {
+        connectivity: Connectivity::Four,
+        ..DetectOptions::default()
+    };
+    let result = try_count_groups_until(
+        &mut stars,
+        Some(&img),
+        &options,
+        None,
+        Some(Instant::now()),
+        || {},
+    );
+    assert_eq!(
+        result.unwrap_err(),
+        CountError::PastDeadline(PastDeadline {
+            scanned: 0,
+            height: 200
+        })
+    );
+
+    let deadline = Instant::now() + Duration::from_secs(3600);
+    let stats = try_count_groups_until(
+        &mut stars,
+        Some(&img),
+        &options,
+        None,
+        Some(deadline),
+        || {},
+    )
+    .unwrap();
+    assert_eq!(stats.count, 200 * 200 / 2);
+}
+
+#[test]
+fn a_tiny_timeout_aborts_a_large_dense_image() {
+    let dir = scratch_dir("timeout");
+    let input = dir.join("dense.png");
+    dense(1000).save(&input).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_star-counter"))
+        .arg("--file")
+        .arg(&input)
+        .args(["--connectivity", "4", "--timeout-seconds", "0.000001"])
+        .output()
+        .unwrap();
+    fs::remove_dir_all(dir).unwrap();
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Gave up after --timeout-seconds 0.000001"),
+        "{stderr}"
+    );
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn the_timeout_must_be_a_finite_number_above_zero() {
+    let error = |seconds: &str| {
+        let output = Command::new(env!("CARGO_BIN_EXE_star-counter"))
+            .args(["--file", "unused.png", "--timeout-seconds", seconds])
+            .output()
+            .unwrap();
+        assert!(!output.status.success());
+        String::from_utf8_lossy(&output.stderr).into_owned()
+    };
+    for seconds in ["inf", "NaN"] {
+        let stderr = error(seconds);
+        assert!(
+            stderr.contains(&format!(
+                "{seconds} must be a finite number of seconds above 0"
+            )),
+            "{stderr}"
+        );
+    }
+    let stderr = error("0");
+    assert!(stderr.contains("0 must be above 0"), "{stderr}");
+}
@@ -0,0 +1,129 @@
+mod common;
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+use common::{image, scratch_dir};
+
+/// Runs the binary on a small image with the options, returning its output
+fn run(name: &str, args: &[&str]) -> (Output, PathBuf) {
+    let dir = scratch_dir(name);
+    let input = dir.join("frame.png");
+    image(&["#...", "..#.", "...."], 200).save(&input).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_star-counter"))
+        .arg("--file")
+        .arg(&input)
+        .args(args)
+        .output()
+        .unwrap();
+    (output, dir)
+}
+
+/// Asserts that the options are rejected with an error containing `message`
+fn assert_rejected(name: &str, args: &[&str], message: &str) {
+    let (output, dir) = run(name, args);
+    fs::remove_dir_all(dir).unwrap();
+    assert!(!output.status.success(), "{args:?} were accepted");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains(message), "{args:?}: {stderr}");
+    assert!(output.stdout.is_empty(), "{args:?} counted before failing");
+}
+
+#[test]
+fn max_sensitivity_must_be_above_the_sensitivity() {
+    assert_rejected(
+        "max-below",
+        &["--sensitivity", "100", "--max-sensitivity", "50"],
+        "--max-sensitivity 50 must be above the sensitivity 100",
+    );
+    assert_rejected(
+        "max-equal",
+        &["--sensitivity", "40%", "--max-sensitivity", "0.4"],
+        "must be above the sensitivity",
+    );
+}
+
+#[test]
+fn hysteresis_levels_must_be_in_order() {
+    assert_rejected(
+        "hysteresis-order",
+        &["--hysteresis", "20", "80"],
+        "The high level of --hysteresis 20 must be above the low one 80",
+    );
+    assert_rejected(
+        "hysteresis-max",
+        &["--hysteresis", "150", "20", "--max-sensitivity", "150"],
+        "must be below --max-sensitivity 150",
+    );
+}
+
+#[test]
+fn size_and_shape_limits_must_allow_some_star() {
+    assert_rejected(
+        "sizes",
+        &["--min-size", "10", "--max-size", "4"],
+        "--max-size 4 can't be below --min-size 10",
+    );
+    assert_rejected(
+        "elongation",
+        &["--max-elongation", "0.5"],
+        "--max-elongation 0.5 is below 1",
+    );
+    assert_rejected(
+        "axis-ratio",
+        &["--max-axis-ratio", "0.9"],
+        "--max-axis-ratio 0.9 is below 1",
+    );
+}
+
+#[test]
+fn ring_must_be_wider_than_nothing() {
+    assert_rejected(
+        "ring",
+        &["--circular-mask", "10", "--inner-radius", "12"],
+        "--inner-radius 12 must be below the --circular-mask radius 10",
+    );
+}
+
+#[test]
+fn sweep_and_expectations_must_be_in_order() {
+    assert_rejected(
+        "sweep-step",
+        &["--sweep", "10", "50", "0"],
+        "The step of --sweep must be above 0",
+    );
+    assert_rejected(
+        "sweep-order",
+        &["--sweep", "50", "10", "5"],
+        "--sweep must start at most at its end",
+    );
+    assert_rejected(
+        "expectations",
+        &["--expect-min", "5", "--expect-max", "2"],
+        "--expect-min 5 can't be above --expect-max 2",
+    );
+}
+
+#[test]
+fn coherent_options_are_accepted() {
+    let (output, dir) = run(
+        "coherent",
+        &[
+            "--count-only",
+            "--sensitivity",
+            "20",
+            "--max-sensitivity",
+            "250",
+            "--min-size",
+            "1",
+            "--max-size",
+            "1",
+            "--max-elongation",
+            "1",
+        ],
+    );
+    fs::remove_dir_all(dir).unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "2\n");
+}
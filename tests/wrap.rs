@@ -0,0 +1,82 @@
+mod common;
+
+use std::process::Command;
+
+use common::{grid, image, scratch_dir};
+use star_counter::{count_groups, Connectivity, DetectOptions, Wrap};
+
+fn count(rows: &[&str], connectivity: Connectivity, wrap: Wrap) -> star_counter::StarStats {
+    let options = DetectOptions {
+        connectivity,
+        wrap,
+        verify: true,
+        ..DetectOptions::default()
+    };
+    count_groups(&mut grid(rows), None, &options)
+}
+
+#[test]
+fn a_star_across_the_seam_is_counted_once() {
+    let rows = ["......", "##..##", "#....#", "......"];
+    assert_eq!(count(&rows, Connectivity::Four, Wrap::None).count, 2);
+
+    let stats = count(&rows, Connectivity::Four, Wrap::Horizontal);
+    assert_eq!(stats.count, 1);
+    let star = &stats.stars[0];
+    assert_eq!(star.area, 6);
+    // Measured as if the image went on past its right edge, with the centroid back inside
+    assert_eq!((star.bounding_box.x_min, star.bounding_box.x_max), (4, 7));
+    assert_eq!(star.centroid, (5, 1));
+    assert!((star.weighted_centroid.0 - 5.5).abs() < 1e-9);
+}
+
+#[test]
+fn diagonals_connect_across_the_seam() {
+    let rows = ["#.....", ".....#"];
+    assert_eq!(count(&rows, Connectivity::Four, Wrap::Horizontal).count, 2);
+    assert_eq!(count(&rows, Connectivity::Eight, Wrap::Horizontal).count, 1);
+}
+
+#[test]
+fn only_both_connects_the_top_and_bottom() {
+    let rows = ["..#...", "......", "..#..."];
+    assert_eq!(count(&rows, Connectivity::Four, Wrap::Horizontal).count, 2);
+    let stats = count(&rows, Connectivity::Four, Wrap::Both);
+    assert_eq!(stats.count, 1);
+    // Halfway between the bottom row and the top one past it
+    assert!((stats.stars[0].weighted_centroid.1 - 2.5).abs() < 1e-9);
+
+    let corners = ["#....#", "......", "#....#"];
+    assert_eq!(count(&corners, Connectivity::Four, Wrap::Both).count, 1);
+}
+
+#[test]
+fn a_band_around_the_whole_image_is_one_star() {
+    let rows = ["......", "######", "......"];
+    let stats = count(&rows, Connectivity::Eight, Wrap::Horizontal);
+    assert_eq!(stats.count, 1);
+    assert_eq!(stats.stars[0].area, 6);
+    assert_eq!(stats.stars[0].centroid, (2, 1));
+}
+
+#[test]
+fn the_cli_counts_a_blob_on_the_seam_once_with_wrap_horizontal() {
+    let dir = scratch_dir("wrap");
+    let input = dir.join("panorama.png");
+    image(&["........", "##....##", "##....##", "........"], 255)
+        .save(&input)
+        .unwrap();
+    let count = |wrap: &str| {
+        let output = Command::new(env!("CARGO_BIN_EXE_star-counter"))
+            .arg("--file")
+            .arg(&input)
+            .args(["--count-only", "--wrap", wrap])
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{output:?}");
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+    assert_eq!(count("none"), "2\n");
+    assert_eq!(count("horizontal"), "1\n");
+    std::fs::remove_dir_all(&dir).unwrap();
+}